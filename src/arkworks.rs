@@ -0,0 +1,118 @@
+// Export for arkworks, only built with the `arkworks` feature: Rust source
+// that needs `ark-ff` and `ark-relations` in the crate it is added to, this
+// crate doesn't depend on them.
+
+use crate::circuit::{Circuit, R1csSystem, Wire};
+use crate::export::{constraint_terms, ExportError};
+
+impl Circuit {
+    pub fn to_arkworks(&self, struct_name: &str) -> Result<String, ExportError> {
+        self.to_r1cs().to_arkworks(struct_name)
+    }
+}
+
+impl R1csSystem {
+    // A struct implementing `ConstraintSynthesizer<F>`, usable with Groth16 or
+    // Marlin, with one `enforce_constraint` per constraint and the variables
+    // of proof_variables. `witness` holds the wire values by variable index,
+    // None during setup. Fails on a coefficient that only means something
+    // over Goldilocks.
+    pub fn to_arkworks(&self, struct_name: &str) -> Result<String, ExportError> {
+        let constraints = constraint_terms(self, "F")?;
+        let mut lines = vec![
+            "use ark_ff::PrimeField;".to_string(),
+            "use ark_relations::lc;".to_string(),
+            "use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError, Variable};".to_string(),
+            String::new(),
+            format!("pub struct {}<F: PrimeField> {{", struct_name),
+            "    pub witness: Option<Vec<F>>,".to_string(),
+            "}".to_string(),
+            String::new(),
+            format!("impl<F: PrimeField> ConstraintSynthesizer<F> for {}<F> {{", struct_name),
+            "    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {".to_string(),
+            "        let witness = self.witness;".to_string(),
+            "        let value = |id: usize| {".to_string(),
+            "            witness.as_ref().map(|values| values[id]).ok_or(SynthesisError::AssignmentMissing)".to_string(),
+            "        };".to_string(),
+            String::new(),
+            format!("        let w{} = Variable::One;", Wire::ONE.id),
+        ];
+
+        for (id, _, public) in self.proof_variables() {
+            let kind = if public { "input" } else { "witness" };
+            lines.push(format!(
+                "        let w{} = cs.new_{}_variable(|| value({}))?;",
+                id, kind, id
+            ));
+        }
+
+        for terms in &constraints {
+            lines.push(String::new());
+            lines.push("        cs.enforce_constraint(".to_string());
+            for lc in terms {
+                lines.push(format!("            lc!(){},", lc));
+            }
+            lines.push("        )?;".to_string());
+        }
+
+        lines.push("        Ok(())".to_string());
+        lines.push("    }".to_string());
+        lines.push("}".to_string());
+        Ok(lines.join("\n") + "\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::compile_str;
+    use crate::export::read_terms;
+    use crate::field::FieldElement;
+    use crate::witness::{InputFile, WitnessCalculator};
+
+    // what ConstraintSystem::is_satisfied checks: every enforced a * b == c
+    // under the assignment, read back from the generated source since
+    // ark-relations is not a dependency of this crate
+    fn is_satisfied(source: &str, witness: &[FieldElement]) -> bool {
+        let lcs: Vec<_> = source
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("lc!()"))
+            .map(|terms| read_terms(terms.trim_end_matches(','), "F"))
+            .collect();
+        let dot = |lc: &[(usize, FieldElement)]| {
+            lc.iter().fold(FieldElement::zero(), |acc, (wire, coeff)| {
+                acc + *coeff * witness[*wire]
+            })
+        };
+        lcs.chunks(3)
+            .all(|lcs| dot(&lcs[0]) * dot(&lcs[1]) == dot(&lcs[2]))
+    }
+
+    #[test]
+    fn synthesized_circuit_is_satisfied() {
+        let circuit = compile_str("public x\nprivate y\nreturn x * y - 3").unwrap();
+        let r1cs = circuit.to_r1cs();
+        let source = r1cs.to_arkworks("Product").unwrap();
+        assert_eq!(
+            source.matches("cs.enforce_constraint(").count(),
+            r1cs.constraints.len()
+        );
+        assert!(source.contains("cs.new_input_variable(|| value("));
+
+        let mut calculator = WitnessCalculator::new();
+        let inputs = InputFile::builder().public("x", 4).private("y", 5).build();
+        calculator.calculate_witness(&circuit, inputs).unwrap();
+        let mut witness = calculator.witness_vector();
+        assert!(is_satisfied(&source, &witness));
+
+        witness[r1cs.output_wire] = witness[r1cs.output_wire] + FieldElement::one();
+        assert!(!is_satisfied(&source, &witness));
+    }
+
+    #[test]
+    fn goldilocks_only_coefficient_is_rejected() {
+        let circuit = compile_str("public x\nreturn x + 5000000000").unwrap();
+        let err = circuit.to_arkworks("Big").unwrap_err();
+        assert_eq!(err.coefficient, FieldElement::new(5000000000));
+        assert!(err.to_string().contains("only meaningful over Goldilocks"));
+    }
+}
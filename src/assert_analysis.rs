@@ -0,0 +1,423 @@
+// Best-effort static check of the asserts of an SSA program, before any
+// witness exists. Every value is tracked as an interval of field elements
+// and, where it is an affine combination of inputs and nonlinear results, as
+// that combination. An assert whose two sides differ by a nonzero constant
+// (`assert x + 1 == x`), or whose intervals don't overlap, can never hold;
+// one whose sides are the same combination always holds. Anything else is
+// reported as unknown.
+
+use crate::ast::Span;
+use crate::field::{FieldElement, MODULUS};
+use crate::ssa::{SsaInstruction, SsaProgram, SsaValue};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssertVerdict {
+    AlwaysHolds,
+    AlwaysFails,
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct AssertReport {
+    // position of the assert in the instruction list
+    pub index: usize,
+    pub left: SsaValue,
+    pub right: SsaValue,
+    pub verdict: AssertVerdict,
+    // source of the left operand, as in ConstantAssertError
+    pub span: Option<Span>,
+}
+
+impl std::fmt::Display for AssertReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let verdict = match self.verdict {
+            AssertVerdict::AlwaysHolds => "always holds",
+            AssertVerdict::AlwaysFails => "can never hold",
+            AssertVerdict::Unknown => "depends on the inputs",
+        };
+        write!(
+            f,
+            "assert {} == {} (instruction {})",
+            self.left, self.right, self.index
+        )?;
+        if let Some(span) = &self.span {
+            write!(f, " at {}", span)?;
+        }
+        write!(f, " {}", verdict)
+    }
+}
+
+// Inclusive range of canonical field values, lo <= hi; ranges that would
+// wrap around the modulus are widened to the whole field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Interval {
+    lo: u64,
+    hi: u64,
+}
+
+impl Interval {
+    const FULL: Interval = Interval {
+        lo: 0,
+        hi: MODULUS - 1,
+    };
+
+    fn exact(value: FieldElement) -> Self {
+        Interval {
+            lo: value.as_u64(),
+            hi: value.as_u64(),
+        }
+    }
+
+    fn from_bounds(lo: u128, hi: u128) -> Self {
+        if hi < MODULUS as u128 {
+            Interval {
+                lo: lo as u64,
+                hi: hi as u64,
+            }
+        } else {
+            Interval::FULL
+        }
+    }
+
+    fn add(self, other: Interval) -> Self {
+        Interval::from_bounds(
+            self.lo as u128 + other.lo as u128,
+            self.hi as u128 + other.hi as u128,
+        )
+    }
+
+    fn sub(self, other: Interval) -> Self {
+        if self.lo >= other.hi {
+            Interval {
+                lo: self.lo - other.hi,
+                hi: self.hi - other.lo,
+            }
+        } else {
+            Interval::FULL
+        }
+    }
+
+    fn mul(self, other: Interval) -> Self {
+        Interval::from_bounds(
+            self.lo as u128 * other.lo as u128,
+            self.hi as u128 * other.hi as u128,
+        )
+    }
+
+    fn neg(self) -> Self {
+        if self.hi == 0 {
+            self
+        } else if self.lo > 0 {
+            Interval {
+                lo: MODULUS - self.hi,
+                hi: MODULUS - self.lo,
+            }
+        } else {
+            Interval::FULL
+        }
+    }
+
+    fn contains(self, value: FieldElement) -> bool {
+        (self.lo..=self.hi).contains(&value.as_u64())
+    }
+
+    fn overlaps(self, other: Interval) -> bool {
+        self.lo <= other.hi && other.lo <= self.hi
+    }
+
+    // a range check only passes for values below 2^bits
+    fn below_power_of_two(self, bits: u32) -> Self {
+        if bits >= 64 {
+            return self;
+        }
+        let max = (1u64 << bits) - 1;
+        Interval {
+            lo: self.lo.min(max),
+            hi: self.hi.min(max),
+        }
+    }
+}
+
+// constant + sum of coeff * atom, where atoms are inputs and values computed
+// by nonlinear instructions
+#[derive(Debug, Clone, PartialEq)]
+struct Affine {
+    constant: FieldElement,
+    terms: BTreeMap<SsaValue, FieldElement>,
+}
+
+impl Affine {
+    fn constant(value: FieldElement) -> Self {
+        Affine {
+            constant: value,
+            terms: BTreeMap::new(),
+        }
+    }
+
+    fn atom(value: &SsaValue) -> Self {
+        Affine {
+            constant: FieldElement::zero(),
+            terms: BTreeMap::from([(value.clone(), FieldElement::one())]),
+        }
+    }
+
+    fn scaled_add(&self, other: &Affine, factor: FieldElement) -> Self {
+        let mut result = self.clone();
+        result.constant = result.constant + other.constant * factor;
+        for (atom, coeff) in &other.terms {
+            let entry = result
+                .terms
+                .entry(atom.clone())
+                .or_insert(FieldElement::zero());
+            *entry = *entry + *coeff * factor;
+        }
+        result.terms.retain(|_, coeff| !coeff.is_zero());
+        result
+    }
+
+    fn scale(&self, factor: FieldElement) -> Self {
+        Affine::constant(FieldElement::zero()).scaled_add(self, factor)
+    }
+
+    fn as_constant(&self) -> Option<FieldElement> {
+        self.terms.is_empty().then_some(self.constant)
+    }
+}
+
+// Classifies every assert of the program, in order. Run it before
+// optimization: the optimizer removes asserts that always hold.
+pub fn analyze_asserts(ssa_program: &SsaProgram) -> Vec<AssertReport> {
+    let mut intervals: HashMap<SsaValue, Interval> = HashMap::new();
+    let mut forms: HashMap<SsaValue, Affine> = HashMap::new();
+    for input in ssa_program
+        .public_inputs
+        .iter()
+        .chain(&ssa_program.private_inputs)
+    {
+        intervals.insert(input.clone(), Interval::FULL);
+        forms.insert(input.clone(), Affine::atom(input));
+    }
+
+    // range checks narrow their value for every later use, so all of them
+    // are applied before the asserts are judged
+    for instr in &ssa_program.instructions {
+        if let SsaInstruction::RangeCheck(value, bits) = instr {
+            let interval = intervals.entry(value.clone()).or_insert(Interval::FULL);
+            *interval = interval.below_power_of_two(*bits);
+        }
+    }
+
+    let interval_of = |intervals: &HashMap<SsaValue, Interval>, value: &SsaValue| {
+        intervals.get(value).copied().unwrap_or(Interval::FULL)
+    };
+    let form_of = |forms: &HashMap<SsaValue, Affine>, value: &SsaValue| {
+        forms
+            .get(value)
+            .cloned()
+            .unwrap_or_else(|| Affine::atom(value))
+    };
+
+    let mut reports = Vec::new();
+    for (index, instr) in ssa_program.instructions.iter().enumerate() {
+        let (dest, interval, form) = match instr {
+            SsaInstruction::Const(dest, value) => {
+                (dest, Interval::exact(*value), Affine::constant(*value))
+            }
+            SsaInstruction::Add(dest, left, right) => (
+                dest,
+                interval_of(&intervals, left).add(interval_of(&intervals, right)),
+                form_of(&forms, left).scaled_add(&form_of(&forms, right), FieldElement::one()),
+            ),
+            SsaInstruction::Sub(dest, left, right) => (
+                dest,
+                interval_of(&intervals, left).sub(interval_of(&intervals, right)),
+                form_of(&forms, left).scaled_add(&form_of(&forms, right), -FieldElement::one()),
+            ),
+            SsaInstruction::Neg(dest, operand) => (
+                dest,
+                interval_of(&intervals, operand).neg(),
+                form_of(&forms, operand).scale(-FieldElement::one()),
+            ),
+            SsaInstruction::Mul(dest, left, right) => {
+                let (left_form, right_form) = (form_of(&forms, left), form_of(&forms, right));
+                let form = match (left_form.as_constant(), right_form.as_constant()) {
+                    (Some(factor), _) => right_form.scale(factor),
+                    (_, Some(factor)) => left_form.scale(factor),
+                    _ => Affine::atom(dest),
+                };
+                let interval = interval_of(&intervals, left).mul(interval_of(&intervals, right));
+                (dest, interval, form)
+            }
+            SsaInstruction::Square(dest, operand) => {
+                let form = match form_of(&forms, operand).as_constant() {
+                    Some(value) => Affine::constant(value * value),
+                    None => Affine::atom(dest),
+                };
+                let interval = interval_of(&intervals, operand);
+                (dest, interval.mul(interval), form)
+            }
+            SsaInstruction::Inv(dest, operand) => {
+                // a satisfying witness never inverts zero
+                match form_of(&forms, operand)
+                    .as_constant()
+                    .and_then(|value| value.inverse())
+                {
+                    Some(inverse) => (dest, Interval::exact(inverse), Affine::constant(inverse)),
+                    None => (
+                        dest,
+                        Interval {
+                            lo: 1,
+                            hi: MODULUS - 1,
+                        },
+                        Affine::atom(dest),
+                    ),
+                }
+            }
+            SsaInstruction::IsZero(dest, operand) => {
+                let operand = interval_of(&intervals, operand);
+                let interval = if operand.lo > 0 {
+                    Interval::exact(FieldElement::zero())
+                } else if operand.hi == 0 {
+                    Interval::exact(FieldElement::one())
+                } else {
+                    Interval { lo: 0, hi: 1 }
+                };
+                let form = if interval.lo == interval.hi {
+                    Affine::constant(FieldElement::new(interval.lo))
+                } else {
+                    Affine::atom(dest)
+                };
+                (dest, interval, form)
+            }
+            SsaInstruction::Assert(left, right) => {
+                let verdict = judge(
+                    &form_of(&forms, left)
+                        .scaled_add(&form_of(&forms, right), -FieldElement::one()),
+                    interval_of(&intervals, left),
+                    interval_of(&intervals, right),
+                    &intervals,
+                );
+                let span = ssa_program.spans.get(left).cloned();
+                reports.push(AssertReport {
+                    index,
+                    left: left.clone(),
+                    right: right.clone(),
+                    verdict,
+                    span,
+                });
+                continue;
+            }
+            SsaInstruction::RangeCheck(..) => continue,
+        };
+
+        // a range check on dest may be tighter than what its operands give;
+        // if the two don't overlap the range check can't pass anyway
+        let interval = match intervals.get(dest) {
+            Some(checked) if checked.overlaps(interval) => Interval {
+                lo: checked.lo.max(interval.lo),
+                hi: checked.hi.min(interval.hi),
+            },
+            Some(checked) => *checked,
+            None => interval,
+        };
+        intervals.insert(dest.clone(), interval);
+        forms.insert(dest.clone(), form);
+    }
+    reports
+}
+
+// `difference` is left - right as an affine form
+fn judge(
+    difference: &Affine,
+    left: Interval,
+    right: Interval,
+    intervals: &HashMap<SsaValue, Interval>,
+) -> AssertVerdict {
+    if let Some(constant) = difference.as_constant() {
+        return if constant.is_zero() {
+            AssertVerdict::AlwaysHolds
+        } else {
+            AssertVerdict::AlwaysFails
+        };
+    }
+    if !left.overlaps(right) {
+        return AssertVerdict::AlwaysFails;
+    }
+    // coeff * atom + constant == 0 only holds for one atom value
+    if let [(atom, coeff)] = difference.terms.iter().collect::<Vec<_>>()[..] {
+        let solution = coeff
+            .inverse()
+            .map(|inverse| -difference.constant * inverse);
+        let atom_interval = intervals.get(atom).copied().unwrap_or(Interval::FULL);
+        if let Some(solution) = solution {
+            if !atom_interval.contains(solution) {
+                return AssertVerdict::AlwaysFails;
+            }
+        }
+    }
+    AssertVerdict::Unknown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parse_str;
+    use crate::ssa::SsaBuilder;
+
+    fn verdicts(source: &str) -> Vec<AssertVerdict> {
+        let ssa_program = SsaBuilder::new()
+            .convert(parse_str(source).unwrap())
+            .unwrap();
+        analyze_asserts(&ssa_program)
+            .iter()
+            .map(|report| report.verdict)
+            .collect()
+    }
+
+    #[test]
+    fn assert_off_by_a_constant_always_fails() {
+        assert_eq!(
+            verdicts("public x\nassert (x + 1) == x\nreturn x"),
+            [AssertVerdict::AlwaysFails]
+        );
+    }
+
+    #[test]
+    fn assert_of_equal_forms_always_holds() {
+        assert_eq!(
+            verdicts("public x\nlet y = x + x\nassert y == 2 * x\nreturn y"),
+            [AssertVerdict::AlwaysHolds]
+        );
+    }
+
+    #[test]
+    fn assert_on_unconstrained_inputs_is_unknown() {
+        assert_eq!(
+            verdicts("public x\nprivate y\nassert x * y == 6\nreturn x"),
+            [AssertVerdict::Unknown]
+        );
+    }
+
+    #[test]
+    fn range_check_rules_out_a_solution() {
+        assert_eq!(
+            verdicts("public x: u8\nassert x == 300\nreturn x"),
+            [AssertVerdict::AlwaysFails]
+        );
+        assert_eq!(
+            verdicts("public x: u8\nassert x == 200\nreturn x"),
+            [AssertVerdict::Unknown]
+        );
+    }
+
+    #[test]
+    fn report_names_the_source_of_the_assert() {
+        let ssa_program = SsaBuilder::new()
+            .convert(parse_str("public x\nassert (x + 1) == x\nreturn x").unwrap())
+            .unwrap();
+        let report = &analyze_asserts(&ssa_program)[0];
+        assert!(report.to_string().ends_with("can never hold"), "{}", report);
+        assert!(report.span.is_some());
+    }
+}
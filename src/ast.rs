@@ -1,24 +1,376 @@
+use crate::field::FieldElement;
+use serde::{Deserialize, Serialize};
+
+// Byte range start..end in the source, as Token::span. `file` is the path of
+// the included file the range is in, None for the file being compiled.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+// `20..32`, or `lib.zk:20..32` in an included file
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+// an AST node together with the source range it was parsed from
+#[derive(Clone)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Self { node, span }
+    }
+}
+
+impl<T> std::ops::Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Spanned<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.node.fmt(f)?;
+        write!(f, " @ {}", self.span)
+    }
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for Spanned<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.node.fmt(f)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Program {
-    pub statements: Vec<Stmt>,
+    pub statements: Vec<Spanned<Stmt>>,
+}
+
+// a node of a parsed program, as returned by Program::node_at
+#[derive(Debug, Clone, Copy)]
+pub enum NodeRef<'a> {
+    Stmt(&'a Spanned<Stmt>),
+    Expr(&'a Spanned<Expr>),
+}
+
+impl NodeRef<'_> {
+    pub fn span(&self) -> Span {
+        match self {
+            NodeRef::Stmt(stmt) => stmt.span.clone(),
+            NodeRef::Expr(expr) => expr.span.clone(),
+        }
+    }
+}
+
+impl Program {
+    // the smallest statement or expression whose span contains the byte
+    // offset, descending into fn bodies; None between statements. Offsets
+    // are into the file being compiled, statements from includes are skipped.
+    pub fn node_at(&self, offset: usize) -> Option<NodeRef<'_>> {
+        stmt_at(&self.statements, offset)
+    }
+
+    // The AST as one s-expression, (program STMT...), for other tools to read
+    // instead of the Debug dump: every statement and expression is (KIND
+    // CHILDREN...), names and literals are bare atoms, spans are left out.
+    // Included files appear as the statements they were spliced in as.
+    pub fn to_sexpr(&self) -> String {
+        sexpr(
+            "program",
+            self.statements.iter().map(|stmt| stmt.to_sexpr()),
+        )
+    }
+}
+
+impl Stmt {
+    pub fn to_sexpr(&self) -> String {
+        match self {
+            Stmt::PublicInput { name, ty } => sexpr("public", input_atoms(name, ty)),
+            Stmt::PrivateInput { name, ty } => sexpr("private", input_atoms(name, ty)),
+            Stmt::ConstDecl { name, value } => sexpr("const", [name.clone(), value.to_string()]),
+            Stmt::ConstArray { name, values } => sexpr(
+                "const_array",
+                std::iter::once(name.clone()).chain(values.iter().map(|value| value.to_string())),
+            ),
+            Stmt::Let { name, expr } => sexpr("let", [name.clone(), expr.to_sexpr()]),
+            Stmt::AddAssign { name, expr } => sexpr("add_assign", [name.clone(), expr.to_sexpr()]),
+            Stmt::Return(expr) => sexpr("return", [expr.to_sexpr()]),
+            Stmt::Assert { left, right } => sexpr("assert", [left.to_sexpr(), right.to_sexpr()]),
+            Stmt::AssertEq(operands) => sexpr(
+                "assert_eq",
+                operands.iter().map(|operand| operand.to_sexpr()),
+            ),
+            Stmt::StaticAssert { left, op, right } => sexpr(
+                "static_assert",
+                [op.to_string(), left.to_sexpr(), right.to_sexpr()],
+            ),
+            Stmt::FnDecl { name, params, body } => sexpr(
+                "fn",
+                [name.clone(), sexpr("params", params.iter().cloned())]
+                    .into_iter()
+                    .chain(body.iter().map(|stmt| stmt.to_sexpr())),
+            ),
+        }
+    }
+}
+
+// "(head child child ...)", or "(head)" without children
+fn sexpr(head: &str, children: impl IntoIterator<Item = String>) -> String {
+    let mut out = format!("({}", head);
+    for child in children {
+        out.push(' ');
+        out.push_str(&child);
+    }
+    out.push(')');
+    out
+}
+
+fn input_atoms(name: &str, ty: &Option<InputType>) -> Vec<String> {
+    std::iter::once(name.to_string())
+        .chain(ty.map(|ty| ty.to_string()))
+        .collect()
+}
+
+fn contains(span: &Span, offset: usize) -> bool {
+    span.file.is_none() && span.start <= offset && offset < span.end
+}
+
+fn stmt_at(statements: &[Spanned<Stmt>], offset: usize) -> Option<NodeRef<'_>> {
+    let stmt = statements
+        .iter()
+        .find(|stmt| contains(&stmt.span, offset))?;
+    let inner = match &stmt.node {
+        Stmt::Let { expr, .. } | Stmt::AddAssign { expr, .. } | Stmt::Return(expr) => {
+            expr_at(expr, offset)
+        }
+        Stmt::Assert { left, right } | Stmt::StaticAssert { left, right, .. } => {
+            expr_at(left, offset).or_else(|| expr_at(right, offset))
+        }
+        Stmt::AssertEq(operands) => operands.iter().find_map(|expr| expr_at(expr, offset)),
+        Stmt::FnDecl { body, .. } => stmt_at(body, offset),
+        Stmt::PublicInput { .. }
+        | Stmt::PrivateInput { .. }
+        | Stmt::ConstDecl { .. }
+        | Stmt::ConstArray { .. } => None,
+    };
+    inner.or(Some(NodeRef::Stmt(stmt)))
+}
+
+fn expr_at(expr: &Spanned<Expr>, offset: usize) -> Option<NodeRef<'_>> {
+    if !contains(&expr.span, offset) {
+        return None;
+    }
+    let inner = match &expr.node {
+        Expr::Var(_) | Expr::Literal(_) => None,
+        Expr::Add(left, right)
+        | Expr::Sub(left, right)
+        | Expr::Mul(left, right)
+        | Expr::Ne(left, right)
+        | Expr::And(left, right)
+        | Expr::Or(left, right)
+        | Expr::Let(_, left, right) => expr_at(left, offset).or_else(|| expr_at(right, offset)),
+        Expr::Neg(inner)
+        | Expr::Pow(inner, _)
+        | Expr::Inv(inner)
+        | Expr::IsZero(inner)
+        | Expr::Not(inner) => expr_at(inner, offset),
+        Expr::Call(_, args) => args.iter().find_map(|arg| expr_at(arg, offset)),
+    };
+    inner.or(Some(NodeRef::Expr(expr)))
 }
 
 #[derive(Debug, Clone)]
 pub enum Stmt {
-    PublicInput { name: String },
-    PrivateInput { name: String },
-    ConstDecl { name: String, value: i32 },
-    Let { name: String, expr: Expr },
-    Return(Expr),
-    Assert { left: Expr, right: Expr },
+    PublicInput {
+        name: String,
+        ty: Option<InputType>,
+    },
+    PrivateInput {
+        name: String,
+        ty: Option<InputType>,
+    },
+    ConstDecl {
+        name: String,
+        value: FieldElement,
+    },
+    // lookup table, the parser resolves every NAME[index] to the selected element
+    ConstArray {
+        name: String,
+        values: Vec<FieldElement>,
+    },
+    Let {
+        name: String,
+        expr: Spanned<Expr>,
+    },
+    // name += expr, see add_assign_sum
+    AddAssign {
+        name: String,
+        expr: Spanned<Expr>,
+    },
+    Return(Spanned<Expr>),
+    Assert {
+        left: Spanned<Expr>,
+        right: Spanned<Expr>,
+    },
+    // assert_eq(a, b, c) or assert a == b == c: all operands are equal
+    AssertEq(Vec<Spanned<Expr>>),
+    // checked at compile time by const_eval::check_static_asserts, no constraint
+    StaticAssert {
+        left: Spanned<Expr>,
+        op: CompareOp,
+        right: Spanned<Expr>,
+    },
+    FnDecl {
+        name: String,
+        params: Vec<String>,
+        body: Vec<Spanned<Stmt>>,
+    },
+}
+
+// The name + expr that a `name += expr` statement spanning `span` binds to
+// name, exactly as `let name = name + expr` would
+pub fn add_assign_sum(name: &str, expr: &Spanned<Expr>, span: Span) -> Spanned<Expr> {
+    let var_span = Span {
+        end: span.start + name.len(),
+        ..span.clone()
+    };
+    let var = Spanned::new(Expr::Var(name.to_string()), var_span);
+    Spanned::new(Expr::Add(Box::new(var), Box::new(expr.clone())), span)
+}
+
+// comparison of a static_assert, on the integer values of its operands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
+    Equals,
+    NotEquals,
+}
+
+impl CompareOp {
+    pub fn holds(&self, left: i32, right: i32) -> bool {
+        match self {
+            CompareOp::Less => left < right,
+            CompareOp::LessEquals => left <= right,
+            CompareOp::Greater => left > right,
+            CompareOp::GreaterEquals => left >= right,
+            CompareOp::Equals => left == right,
+            CompareOp::NotEquals => left != right,
+        }
+    }
+}
+
+impl std::fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let symbol = match self {
+            CompareOp::Less => "<",
+            CompareOp::LessEquals => "<=",
+            CompareOp::Greater => ">",
+            CompareOp::GreaterEquals => ">=",
+            CompareOp::Equals => "==",
+            CompareOp::NotEquals => "!=",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+// annotation on an input, the value is range checked to [0, 2^bits)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputType {
+    U8,
+    U16,
+    U32,
+}
+
+impl InputType {
+    pub fn from_name(name: &str) -> Option<InputType> {
+        match name {
+            "u8" => Some(InputType::U8),
+            "u16" => Some(InputType::U16),
+            "u32" => Some(InputType::U32),
+            _ => None,
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        match self {
+            InputType::U8 => 8,
+            InputType::U16 => 16,
+            InputType::U32 => 32,
+        }
+    }
+}
+
+impl std::fmt::Display for InputType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "u{}", self.bits())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Expr {
     Var(String),
-    Literal(i32),
-    Add(Box<Expr>, Box<Expr>),
-    Mul(Box<Expr>, Box<Expr>),
+    Literal(FieldElement),
+    Add(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Sub(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Neg(Box<Spanned<Expr>>),
+    Mul(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Pow(Box<Spanned<Expr>>, u32), // base, constant exponent
+    Call(String, Vec<Spanned<Expr>>),
+    Inv(Box<Spanned<Expr>>),    // builtin inv(x), the field inverse
+    IsZero(Box<Spanned<Expr>>), // builtin is_zero(x), 1 if x == 0 else 0
+    Ne(Box<Spanned<Expr>>, Box<Spanned<Expr>>), // a != b, 1 if they differ else 0
+    // boolean operators, on operands constrained to 0 or 1 (see SsaBuilder):
+    // a and b is a * b, a or b is a + b - a * b, not a is 1 - a
+    And(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Or(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Not(Box<Spanned<Expr>>),
+    // let name = value in body, name is only bound inside body
+    Let(String, Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+}
+
+impl Expr {
+    // see Program::to_sexpr
+    pub fn to_sexpr(&self) -> String {
+        match self {
+            Expr::Var(name) => sexpr("var", [name.clone()]),
+            Expr::Literal(n) => sexpr("lit", [n.to_string()]),
+            Expr::Add(l, r) => sexpr("add", [l.to_sexpr(), r.to_sexpr()]),
+            Expr::Sub(l, r) => sexpr("sub", [l.to_sexpr(), r.to_sexpr()]),
+            Expr::Neg(e) => sexpr("neg", [e.to_sexpr()]),
+            Expr::Mul(l, r) => sexpr("mul", [l.to_sexpr(), r.to_sexpr()]),
+            Expr::Pow(base, exp) => sexpr("pow", [base.to_sexpr(), exp.to_string()]),
+            Expr::Inv(e) => sexpr("inv", [e.to_sexpr()]),
+            Expr::IsZero(e) => sexpr("is_zero", [e.to_sexpr()]),
+            Expr::Ne(l, r) => sexpr("ne", [l.to_sexpr(), r.to_sexpr()]),
+            Expr::And(l, r) => sexpr("and", [l.to_sexpr(), r.to_sexpr()]),
+            Expr::Or(l, r) => sexpr("or", [l.to_sexpr(), r.to_sexpr()]),
+            Expr::Not(e) => sexpr("not", [e.to_sexpr()]),
+            Expr::Let(name, value, body) => {
+                sexpr("let_in", [name.clone(), value.to_sexpr(), body.to_sexpr()])
+            }
+            Expr::Call(name, args) => sexpr(
+                "call",
+                std::iter::once(name.clone()).chain(args.iter().map(|arg| arg.to_sexpr())),
+            ),
+        }
+    }
 }
 
 impl std::fmt::Display for Expr {
@@ -27,7 +379,43 @@ impl std::fmt::Display for Expr {
             Expr::Var(name) => write!(f, "{}", name),
             Expr::Literal(n) => write!(f, "{}", n),
             Expr::Add(l, r) => write!(f, "({} + {})", l, r),
+            Expr::Sub(l, r) => write!(f, "({} - {})", l, r),
+            Expr::Neg(e) => write!(f, "(-{})", e),
             Expr::Mul(l, r) => write!(f, "({} * {})", l, r),
+            Expr::Pow(base, exp) => write!(f, "({} ** {})", base, exp),
+            Expr::Inv(e) => write!(f, "inv({})", e),
+            Expr::IsZero(e) => write!(f, "is_zero({})", e),
+            Expr::Ne(l, r) => write!(f, "({} != {})", l, r),
+            Expr::And(l, r) => write!(f, "({} and {})", l, r),
+            Expr::Or(l, r) => write!(f, "({} or {})", l, r),
+            Expr::Not(e) => write!(f, "(not {})", e),
+            Expr::Let(name, value, body) => write!(f, "(let {} = {} in {})", name, value, body),
+            Expr::Call(name, args) => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::parse_str;
+
+    #[test]
+    fn small_program_dumps_as_the_expected_sexpr() {
+        let source = "public a: u8\nprivate b\nconst k = 3\nfn double(v) {\nreturn v + v\n}\nlet x = a + 1\nx += b * k\nassert x != -b\nreturn double(x) ** 2";
+        assert_eq!(
+            parse_str(source).unwrap().to_sexpr(),
+            concat!(
+                "(program (public a u8) (private b) (const k 3)",
+                " (fn double (params v) (return (add (var v) (var v))))",
+                " (let x (add (var a) (lit 1)))",
+                " (add_assign x (mul (var b) (var k)))",
+                // x != -b is asserted as (x != -b) == 1
+                " (assert (ne (var x) (neg (var b))) (lit 1))",
+                " (return (pow (call double (var x)) 2)))"
+            )
+        );
+    }
+}
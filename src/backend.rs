@@ -0,0 +1,305 @@
+use crate::circuit::{write_json_file, Circuit, Gate, R1csSystem, Wire};
+use crate::field::FieldElement;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+
+// Lowers a circuit's gates into the constraint model of a proof system.
+pub trait ConstraintBackend {
+    type System;
+    type Error;
+
+    // the name --target selects it by
+    fn name(&self) -> &'static str;
+
+    fn lower(&self, circuit: &Circuit) -> Result<Self::System, Self::Error>;
+}
+
+// The constraint model main generates, selected with --target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    R1cs,
+    Plonkish,
+}
+
+impl Target {
+    pub fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "r1cs" => Some(Target::R1cs),
+            "plonkish" => Some(Target::Plonkish),
+            _ => None,
+        }
+    }
+}
+
+// One constraint per gate (more for range checks and is_zero), see Circuit::to_r1cs
+pub struct R1csBackend;
+
+impl ConstraintBackend for R1csBackend {
+    type System = R1csSystem;
+    type Error = Infallible;
+
+    fn name(&self) -> &'static str {
+        "r1cs"
+    }
+
+    fn lower(&self, circuit: &Circuit) -> Result<R1csSystem, Infallible> {
+        Ok(circuit.to_r1cs())
+    }
+}
+
+// Gates as rows of a Plonkish table, each row constrained by
+// q_l * a + q_r * b + q_o * c + q_m * a * b + q_c = 0
+// where a, b, c are wire ids. Only gates fitting a single such row, or a
+// couple of them, are supported; there are no lookups or custom gates.
+pub struct PlonkishBackend;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlonkishRow {
+    // None where the selectors ignore the cell
+    pub a: Option<usize>,
+    pub b: Option<usize>,
+    pub c: Option<usize>,
+    pub q_l: FieldElement,
+    pub q_r: FieldElement,
+    pub q_o: FieldElement,
+    pub q_m: FieldElement,
+    pub q_c: FieldElement,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlonkishTable {
+    pub rows: Vec<PlonkishRow>,
+    pub public_inputs: Vec<(String, usize)>,
+    pub private_inputs: Vec<(String, usize)>,
+    pub public_outputs: Vec<(String, usize)>,
+}
+
+impl PlonkishTable {
+    pub fn save_to_file(&self, filename: &str) -> Result<(), Box<dyn std::error::Error>> {
+        write_json_file(filename, self)
+    }
+}
+
+impl PlonkishRow {
+    fn new(a: Option<&Wire>, b: Option<&Wire>, c: Option<&Wire>) -> Self {
+        PlonkishRow {
+            a: a.map(|wire| wire.id),
+            b: b.map(|wire| wire.id),
+            c: c.map(|wire| wire.id),
+            q_l: FieldElement::zero(),
+            q_r: FieldElement::zero(),
+            q_o: FieldElement::zero(),
+            q_m: FieldElement::zero(),
+            q_c: FieldElement::zero(),
+        }
+    }
+}
+
+impl ConstraintBackend for PlonkishBackend {
+    type System = PlonkishTable;
+    type Error = PlonkishError;
+
+    fn name(&self) -> &'static str {
+        "plonkish"
+    }
+
+    fn lower(&self, circuit: &Circuit) -> Result<PlonkishTable, PlonkishError> {
+        let one = FieldElement::one();
+        let mut rows = Vec::new();
+
+        for (gate_index, gate) in circuit.gates.iter().enumerate() {
+            match gate {
+                Gate::Const { output, value } => {
+                    // a - value = 0
+                    let mut row = PlonkishRow::new(Some(output), None, None);
+                    row.q_l = one;
+                    row.q_c = -*value;
+                    rows.push(row);
+                }
+                Gate::Add {
+                    output,
+                    left,
+                    right,
+                } => {
+                    // a + b - c = 0
+                    let mut row = PlonkishRow::new(Some(left), Some(right), Some(output));
+                    row.q_l = one;
+                    row.q_r = one;
+                    row.q_o = -one;
+                    rows.push(row);
+                }
+                Gate::Sub {
+                    output,
+                    left,
+                    right,
+                } => {
+                    // a - b - c = 0
+                    let mut row = PlonkishRow::new(Some(left), Some(right), Some(output));
+                    row.q_l = one;
+                    row.q_r = -one;
+                    row.q_o = -one;
+                    rows.push(row);
+                }
+                Gate::Mul {
+                    output,
+                    left,
+                    right,
+                } => {
+                    // a * b - c = 0
+                    let mut row = PlonkishRow::new(Some(left), Some(right), Some(output));
+                    row.q_m = one;
+                    row.q_o = -one;
+                    rows.push(row);
+                }
+                Gate::Square { output, input } => {
+                    // a * a - c = 0
+                    let mut row = PlonkishRow::new(Some(input), Some(input), Some(output));
+                    row.q_m = one;
+                    row.q_o = -one;
+                    rows.push(row);
+                }
+                Gate::Assert { left, right } => {
+                    // a - b = 0
+                    let mut row = PlonkishRow::new(Some(left), Some(right), None);
+                    row.q_l = one;
+                    row.q_r = -one;
+                    rows.push(row);
+                }
+                Gate::Inv { output, input } => {
+                    // a * b - 1 = 0
+                    let mut row = PlonkishRow::new(Some(input), Some(output), None);
+                    row.q_m = one;
+                    row.q_c = -one;
+                    rows.push(row);
+                }
+                Gate::Lc { output, terms } if terms.len() <= 2 => {
+                    // coeff_0 * a + coeff_1 * b - c = 0
+                    let mut row = PlonkishRow::new(
+                        terms.first().map(|(wire, _)| wire),
+                        terms.get(1).map(|(wire, _)| wire),
+                        Some(output),
+                    );
+                    if let Some((_, coeff)) = terms.first() {
+                        row.q_l = *coeff;
+                    }
+                    if let Some((_, coeff)) = terms.get(1) {
+                        row.q_r = *coeff;
+                    }
+                    row.q_o = -one;
+                    rows.push(row);
+                }
+                Gate::IsZero {
+                    output,
+                    input,
+                    inverse,
+                } => {
+                    // a * b + c - 1 = 0, i.e. input * inverse = 1 - output
+                    let mut first = PlonkishRow::new(Some(input), Some(inverse), Some(output));
+                    first.q_m = one;
+                    first.q_o = one;
+                    first.q_c = -one;
+                    rows.push(first);
+
+                    // a * b = 0, i.e. input * output = 0
+                    let mut second = PlonkishRow::new(Some(input), Some(output), None);
+                    second.q_m = one;
+                    rows.push(second);
+                }
+                Gate::Lc { terms, .. } => {
+                    return Err(PlonkishError {
+                        gate_index,
+                        gate: gate.to_string(),
+                        reason: format!("it sums {} wires, a row reads two", terms.len()),
+                    });
+                }
+                Gate::RangeCheck { bits, .. } => {
+                    return Err(PlonkishError {
+                        gate_index,
+                        gate: gate.to_string(),
+                        reason: format!(
+                            "its {} bit wires don't fit a row without auxiliary wires",
+                            bits.len()
+                        ),
+                    });
+                }
+            }
+        }
+
+        Ok(PlonkishTable {
+            rows,
+            public_inputs: wire_ids(&circuit.public_inputs),
+            private_inputs: wire_ids(&circuit.private_inputs),
+            public_outputs: circuit.public_outputs(),
+        })
+    }
+}
+
+fn wire_ids(inputs: &[(String, Wire)]) -> Vec<(String, usize)> {
+    inputs
+        .iter()
+        .map(|(name, wire)| (name.clone(), wire.id))
+        .collect()
+}
+
+// A gate that needs more than three wires in a row, so has no Plonkish
+// lowering without auxiliary wires
+#[derive(Debug)]
+pub struct PlonkishError {
+    pub gate_index: usize,
+    pub gate: String,
+    pub reason: String, // why this kind of gate doesn't fit
+}
+
+impl std::fmt::Display for PlonkishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Gate {} ({}) has no Plonkish lowering, {}",
+            self.gate_index, self.gate, self.reason
+        )
+    }
+}
+
+impl std::error::Error for PlonkishError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile_str;
+
+    #[test]
+    fn mul_gate_sets_only_the_mul_and_output_selectors() {
+        let circuit = compile_str("public x\nprivate y\nreturn x * y").unwrap();
+        let table = PlonkishBackend.lower(&circuit).unwrap();
+        let (x, y) = (
+            circuit.public_inputs[0].1.id,
+            circuit.private_inputs[0].1.id,
+        );
+
+        assert_eq!(
+            table.rows,
+            [PlonkishRow {
+                a: Some(x),
+                b: Some(y),
+                c: Some(circuit.output_wire.id),
+                q_l: FieldElement::zero(),
+                q_r: FieldElement::zero(),
+                q_o: -FieldElement::one(),
+                q_m: FieldElement::one(),
+                q_c: FieldElement::zero(),
+            }]
+        );
+        assert_eq!(table.public_outputs, circuit.to_r1cs().public_outputs);
+    }
+
+    #[test]
+    fn unsupported_gates_say_why() {
+        let circuit = compile_str("public x: u8\nreturn x").unwrap();
+        let err = PlonkishBackend.lower(&circuit).unwrap_err();
+        assert!(err.to_string().contains("8 bit wires"), "{}", err);
+
+        let circuit = compile_str("public a\npublic b\npublic c\nreturn a + b + c").unwrap();
+        let err = PlonkishBackend.lower(&circuit).unwrap_err();
+        assert!(err.to_string().contains("sums 3 wires"), "{}", err);
+    }
+}
@@ -0,0 +1,104 @@
+// Export for the bellman/bellperson stack, only built with the `bellman`
+// feature. The output is Rust source, so this crate doesn't depend on bellman;
+// the crate the source goes into needs `bellman` (or `bellperson`) and `ff`.
+
+use crate::circuit::{Circuit, R1csSystem, Wire};
+use crate::export::{constraint_terms, ExportError};
+
+impl Circuit {
+    pub fn to_bellman(&self, struct_name: &str) -> Result<String, ExportError> {
+        self.to_r1cs().to_bellman(struct_name)
+    }
+}
+
+impl R1csSystem {
+    // A struct implementing bellman's `Circuit` trait over any PrimeField,
+    // with one `enforce` call per constraint. Variables are allocated as in
+    // proof_variables. Its `witness` holds the wire values by variable index,
+    // as in the .witness file, and is None when only generating parameters.
+    // Fails on a coefficient that only means something over Goldilocks.
+    pub fn to_bellman(&self, struct_name: &str) -> Result<String, ExportError> {
+        let constraints = constraint_terms(self, "S")?;
+        let mut lines = vec![
+            "use bellman::{Circuit, ConstraintSystem, SynthesisError};".to_string(),
+            "use ff::PrimeField;".to_string(),
+            String::new(),
+            format!("pub struct {}<S: PrimeField> {{", struct_name),
+            "    pub witness: Option<Vec<S>>,".to_string(),
+            "}".to_string(),
+            String::new(),
+            format!("impl<S: PrimeField> Circuit<S> for {}<S> {{", struct_name),
+            "    fn synthesize<CS: ConstraintSystem<S>>(self, cs: &mut CS) -> Result<(), SynthesisError> {".to_string(),
+            "        let witness = self.witness;".to_string(),
+            "        let value = |id: usize| {".to_string(),
+            "            witness.as_ref().map(|values| values[id]).ok_or(SynthesisError::AssignmentMissing)".to_string(),
+            "        };".to_string(),
+            String::new(),
+            format!("        let w{} = CS::one();", Wire::ONE.id),
+        ];
+
+        for (id, label, public) in self.proof_variables() {
+            let alloc = if public { "alloc_input" } else { "alloc" };
+            lines.push(format!(
+                "        let w{} = cs.{}(|| \"{}\", || value({}))?;",
+                id, alloc, label, id
+            ));
+        }
+
+        for (index, terms) in constraints.iter().enumerate() {
+            lines.push(String::new());
+            lines.push("        cs.enforce(".to_string());
+            lines.push(format!("            || \"c{}\",", index));
+            for lc in terms {
+                lines.push(format!("            |lc| lc{},", lc));
+            }
+            lines.push("        );".to_string());
+        }
+
+        lines.push("        Ok(())".to_string());
+        lines.push("    }".to_string());
+        lines.push("}".to_string());
+        Ok(lines.join("\n") + "\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::compile_str;
+    use crate::export::read_terms;
+    use crate::field::FieldElement;
+
+    #[test]
+    fn exported_constraints_match_the_r1cs() {
+        let r1cs = compile_str("public x\nprivate y\nreturn x * y - 3")
+            .unwrap()
+            .to_r1cs();
+        let source = r1cs.to_bellman("Product").unwrap();
+        assert!(source.contains("cs.alloc_input(|| \"x\""));
+        assert!(source.contains("cs.alloc(|| \"y\""));
+
+        let exported: Vec<_> = source
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("|lc| lc"))
+            .map(|terms| read_terms(terms.trim_end_matches(','), "S"))
+            .collect();
+        assert_eq!(exported.len(), 3 * r1cs.constraints.len());
+        for (constraint, lcs) in r1cs.constraints.iter().zip(exported.chunks(3)) {
+            assert_eq!(
+                lcs,
+                [
+                    constraint.a.clone(),
+                    constraint.b.clone(),
+                    constraint.c.clone()
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn goldilocks_only_coefficient_is_rejected() {
+        let circuit = compile_str("public x\nreturn x + 5000000000").unwrap();
+        let err = circuit.to_bellman("Big").unwrap_err();
+        assert_eq!(err.coefficient, FieldElement::new(5000000000));
+    }
+}
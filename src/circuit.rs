@@ -1,35 +1,195 @@
+use crate::ast::Span;
+use crate::field::{FieldElement, MODULUS};
+use crate::listing::{aligned_lines, ListingRow};
 use crate::ssa::{SsaInstruction, SsaProgram, SsaValue};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Wire {
     pub id: usize,
 }
 
+impl Wire {
+    // wire 0 always carries the constant 1, R1CS uses it for constant terms
+    pub const ONE: Wire = Wire { id: 0 };
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Gate {
     Const {
         output: Wire,
-        value: i32,
+        value: FieldElement,
     },
     Add {
         output: Wire,
         left: Wire,
         right: Wire,
     },
+    Sub {
+        output: Wire,
+        left: Wire,
+        right: Wire,
+    },
     Mul {
         output: Wire,
         left: Wire,
         right: Wire,
     },
-    Assert {
+    // output = input * input, for backends that price squaring below a Mul;
+    // in R1CS it is the same input * input = output constraint
+    Square {
         output: Wire,
+        input: Wire,
+    },
+    // constrains left == right, it computes no wire
+    Assert {
         left: Wire,
         right: Wire,
     },
+    // output is witnessed as input^-1, constrained by input * output = 1
+    Inv {
+        output: Wire,
+        input: Wire,
+    },
+    // output = sum of coeff * wire, one constraint however many terms
+    Lc {
+        output: Wire,
+        terms: Vec<(Wire, FieldElement)>,
+    },
+    // bits are witnessed as the binary decomposition of input, constrained by
+    // bit * bit = bit for each bit and (sum of 2^i * bit_i) * 1 = input
+    RangeCheck {
+        input: Wire,
+        bits: Vec<Wire>,
+    },
+    // output = 1 if input == 0 else 0; inverse is witnessed as input^-1 (or 0)
+    // and the pair is constrained by input * inverse = 1 - output, input * output = 0
+    IsZero {
+        output: Wire,
+        input: Wire,
+        inverse: Wire,
+    },
+}
+
+// how the witness calculator computes a wire the prover supplies rather than
+// one the constraints determine, see Gate::hint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HintOp {
+    // input^-1, with no value for 0
+    Inverse,
+    // input^-1, or 0 for 0
+    InverseOrZero,
+}
+
+impl HintOp {
+    // None when the op has no value for input
+    pub fn evaluate(&self, input: FieldElement) -> Option<FieldElement> {
+        match self {
+            HintOp::Inverse => input.inverse(),
+            HintOp::InverseOrZero => Some(input.inverse().unwrap_or(FieldElement::zero())),
+        }
+    }
+}
+
+impl Gate {
+    // the name a gate is counted under in CircuitStats, and the one a
+    // WitnessCalculator evaluator is registered for
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Gate::Const { .. } => "const",
+            Gate::Add { .. } => "add",
+            Gate::Sub { .. } => "sub",
+            Gate::Mul { .. } => "mul",
+            Gate::Square { .. } => "square",
+            Gate::Assert { .. } => "assert",
+            Gate::Inv { .. } => "inv",
+            Gate::Lc { .. } => "lc",
+            Gate::RangeCheck { .. } => "range_check",
+            Gate::IsZero { .. } => "is_zero",
+        }
+    }
+
+    // the value the gate computes, None for asserts and range checks, which
+    // only constrain; an is_zero's inverse is auxiliary, see output_wires
+    pub fn output_wire(&self) -> Option<Wire> {
+        match self {
+            Gate::Const { output, .. }
+            | Gate::Add { output, .. }
+            | Gate::Mul { output, .. }
+            | Gate::Square { output, .. }
+            | Gate::Sub { output, .. }
+            | Gate::Inv { output, .. }
+            | Gate::Lc { output, .. }
+            | Gate::IsZero { output, .. } => Some(output.clone()),
+            Gate::RangeCheck { .. } | Gate::Assert { .. } => None,
+        }
+    }
+
+    // the wire the prover supplies and how the witness calculator computes it,
+    // for gates whose constraints check a value rather than determine it
+    pub fn hint(&self) -> Option<(HintOp, &Wire)> {
+        match self {
+            Gate::Inv { output, .. } => Some((HintOp::Inverse, output)),
+            Gate::IsZero { inverse, .. } => Some((HintOp::InverseOrZero, inverse)),
+            _ => None,
+        }
+    }
+
+    // every wire the gate assigns, auxiliary witness wires included
+    pub fn output_wires(&self) -> Vec<Wire> {
+        self.output_refs().into_iter().cloned().collect()
+    }
+
+    pub fn input_wires(&self) -> Vec<Wire> {
+        self.input_refs().into_iter().cloned().collect()
+    }
+
+    pub(crate) fn output_refs(&self) -> Vec<&Wire> {
+        match self {
+            Gate::Const { output, .. }
+            | Gate::Add { output, .. }
+            | Gate::Mul { output, .. }
+            | Gate::Square { output, .. }
+            | Gate::Sub { output, .. }
+            | Gate::Inv { output, .. }
+            | Gate::Lc { output, .. } => vec![output],
+            Gate::IsZero {
+                output, inverse, ..
+            } => vec![output, inverse],
+            Gate::RangeCheck { bits, .. } => bits.iter().collect(),
+            Gate::Assert { .. } => vec![],
+        }
+    }
+
+    pub(crate) fn input_refs(&self) -> Vec<&Wire> {
+        match self {
+            Gate::Const { .. } => vec![],
+            Gate::Add { left, right, .. }
+            | Gate::Sub { left, right, .. }
+            | Gate::Mul { left, right, .. }
+            | Gate::Assert { left, right, .. } => vec![left, right],
+            Gate::Inv { input, .. }
+            | Gate::Square { input, .. }
+            | Gate::IsZero { input, .. }
+            | Gate::RangeCheck { input, .. } => vec![input],
+            Gate::Lc { terms, .. } => terms.iter().map(|(wire, _)| wire).collect(),
+        }
+    }
 }
 
+// whether an input is public or private, see Circuit::ordered_inputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputKind {
+    Public,
+    Private,
+}
+
+// bumped whenever the saved layout of Circuit changes
+pub const CIRCUIT_FORMAT_VERSION: u64 = 3;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Circuit {
     pub public_inputs: Vec<(String, Wire)>,
@@ -38,52 +198,618 @@ pub struct Circuit {
     pub output_wire: Wire,
 }
 
+// where a wire's value comes from, see CircuitBuilder::from_ssa_with_metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WireProvenance {
+    pub wire: Wire,
+    // the SSA values on the wire, several for a constant shared by interning
+    pub ssa_values: Vec<SsaValue>,
+    // source range of the expression or input declaration defining the
+    // first of them that has one
+    pub span: Option<Span>,
+}
+
+// the gate and source range an R1CS constraint comes from, see Circuit::source_map
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstraintSource {
+    pub constraint: usize,
+    pub gate: usize,
+    pub span: Option<Span>,
+}
+
+// the gate range over which a wire holds a value, see Circuit::liveness
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WireLiveness {
+    pub wire: Wire,
+    // index of the gate producing it, None for ONE and the inputs
+    pub def: Option<usize>,
+    // index of the last gate reading it, gates.len() for the output wire
+    // (it is read once all gates ran), None if nothing reads it
+    pub last_use: Option<usize>,
+}
+
+// size figures of a circuit, see Circuit::stats
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CircuitStats {
+    // gate count per gate kind, kinds without gates are omitted
+    pub gates: BTreeMap<&'static str, usize>,
+    pub total_gates: usize,
+    pub constraints: usize,
+    pub wires: usize,
+    pub public_inputs: usize,
+    pub private_inputs: usize,
+}
+
+// nonzero structure of the R1CS matrices, see R1csSystem::sparsity
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SparsityInfo {
+    pub rows: usize, // constraints
+    pub cols: usize, // variables
+    pub a: MatrixSparsity,
+    pub b: MatrixSparsity,
+    pub c: MatrixSparsity,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MatrixSparsity {
+    // (row, col) of every nonzero entry, row-major
+    pub nonzeros: Vec<(usize, usize)>,
+    pub nonzero_count: usize,
+    // nonzero_count / (rows * cols), 0 for an empty matrix
+    pub density: f64,
+    pub max_row_nonzeros: usize,
+    pub empty_rows: usize,
+}
+
+// sparse linear combination: (wire id, coefficient) pairs, one per wire, zeros omitted
+pub type LinearCombination = Vec<(usize, FieldElement)>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SparseConstraint {
+    pub a: LinearCombination,
+    pub b: LinearCombination,
+    pub c: LinearCombination,
+}
+
+// dense form, one coefficient per wire
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct R1csConstraint {
-    pub a: Vec<i32>,
-    pub b: Vec<i32>,
-    pub c: Vec<i32>,
+    pub a: Vec<FieldElement>,
+    pub b: Vec<FieldElement>,
+    pub c: Vec<FieldElement>,
+}
+
+impl SparseConstraint {
+    pub fn new() -> Self {
+        Self {
+            a: Vec::new(),
+            b: Vec::new(),
+            c: Vec::new(),
+        }
+    }
+
+    pub fn to_dense(&self, num_variables: usize) -> R1csConstraint {
+        let densify = |lc: &LinearCombination| {
+            let mut row = vec![FieldElement::zero(); num_variables];
+            for (wire, coeff) in lc {
+                row[*wire] = *coeff;
+            }
+            row
+        };
+
+        R1csConstraint {
+            a: densify(&self.a),
+            b: densify(&self.b),
+            c: densify(&self.c),
+        }
+    }
+}
+
+impl Default for SparseConstraint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// a gate kind as a name, range_check as RangeCheck
+fn gate_label(kind: &str) -> String {
+    kind.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+// `1·w1 - 1·w2 + 3·ONE`, coefficients above p / 2 written as negatives; 0 if empty
+fn explain_lc(lc: &LinearCombination) -> String {
+    let mut text = String::new();
+    for (index, (wire, coeff)) in lc.iter().enumerate() {
+        let negative = coeff.as_u64() > MODULUS / 2;
+        let magnitude = if negative { -*coeff } else { *coeff };
+        let sign = match (index, negative) {
+            (0, false) => "",
+            (0, true) => "-",
+            (_, false) => " + ",
+            (_, true) => " - ",
+        };
+        let name = if *wire == Wire::ONE.id {
+            "ONE".to_string()
+        } else {
+            format!("w{}", wire)
+        };
+        text.push_str(&format!("{}{}·{}", sign, magnitude, name));
+    }
+    if text.is_empty() {
+        text.push('0');
+    }
+    text
+}
+
+// adds coeff * wire to the combination, merging with an existing term for the same wire
+fn add_term(lc: &mut LinearCombination, wire: usize, coeff: FieldElement) {
+    if let Some(pos) = lc.iter().position(|(id, _)| *id == wire) {
+        lc[pos].1 = lc[pos].1 + coeff;
+        if lc[pos].1 == FieldElement::zero() {
+            lc.remove(pos);
+        }
+    } else if coeff != FieldElement::zero() {
+        lc.push((wire, coeff));
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct R1csSystem {
     pub num_constraints: usize,
     pub num_variables: usize,
-    pub constraints: Vec<R1csConstraint>,
+    pub constraints: Vec<SparseConstraint>,
     pub public_inputs: Vec<(String, usize)>,
     pub private_inputs: Vec<(String, usize)>,
     pub output_wire: usize,
+    // public signals besides the public inputs, the output wire as "out"
+    #[serde(default)]
+    pub public_outputs: Vec<(String, usize)>,
+    // set by `reordered`: the circuit wire id behind each variable index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wire_order: Option<Vec<usize>>,
+}
+
+// serializes straight into a buffered file instead of building the JSON string in memory
+pub(crate) fn write_json_file<T: Serialize + ?Sized>(
+    filename: &str,
+    value: &T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = std::fs::File::create(filename)?;
+    let mut writer = std::io::BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writer.flush()?;
+    Ok(())
 }
 
 impl R1csSystem {
     pub fn save_to_file(&self, filename: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let json = serde_json::to_string_pretty(self)?;
-        std::fs::write(filename, json)?;
+        write_json_file(filename, self)
+    }
+
+    // circom's constraints JSON: every constraint is [A, B, C] where each
+    // linear combination is a sparse map of wire id -> decimal coefficient.
+    // The coefficients are Goldilocks elements (-1 is p - 1), so the prime is
+    // written alongside them for tools that would assume BN254.
+    pub fn to_circom_json(&self) -> serde_json::Value {
+        let sparse = |lc: &LinearCombination| -> serde_json::Map<String, serde_json::Value> {
+            lc.iter()
+                .map(|(id, coeff)| (id.to_string(), serde_json::Value::String(coeff.to_string())))
+                .collect()
+        };
+
+        let constraints: Vec<serde_json::Value> = self
+            .constraints
+            .iter()
+            .map(|constraint| {
+                serde_json::json!([
+                    sparse(&constraint.a),
+                    sparse(&constraint.b),
+                    sparse(&constraint.c)
+                ])
+            })
+            .collect();
+
+        serde_json::json!({
+            "prime": MODULUS.to_string(),
+            "constraints": constraints
+        })
+    }
+
+    // circom's .sym format: one `label_id,wire_id,component_id,name` line per
+    // wire. Wire 0 is the constant-one signal, labelled `one` as in circom.
+    pub fn to_circom_sym(&self) -> String {
+        let mut labels = vec![None; self.num_variables];
+        labels[Wire::ONE.id] = Some("one".to_string());
+        for (name, id) in self.public_inputs.iter().chain(&self.private_inputs) {
+            labels[*id] = Some(format!("main.{}", name));
+        }
+        if labels[self.output_wire].is_none() {
+            labels[self.output_wire] = Some("main.out".to_string());
+        }
+
+        labels
+            .into_iter()
+            .enumerate()
+            .map(|(id, label)| {
+                let name = label.unwrap_or_else(|| format!("main.w{}", id));
+                format!("{},{},0,{}\n", id, id, name)
+            })
+            .collect()
+    }
+
+    // evaluates every constraint against the witness, wires missing from the
+    // witness count as zero
+    pub fn evaluate(&self, witness: &[FieldElement]) -> Vec<ConstraintEval> {
+        let dot = |lc: &LinearCombination| {
+            lc.iter().fold(FieldElement::zero(), |acc, (wire, coeff)| {
+                let value = witness.get(*wire).copied().unwrap_or(FieldElement::zero());
+                acc + *coeff * value
+            })
+        };
+
+        self.constraints
+            .iter()
+            .enumerate()
+            .map(|(index, constraint)| {
+                let a = dot(&constraint.a);
+                let b = dot(&constraint.b);
+                let c = dot(&constraint.c);
+
+                let mut wires: Vec<usize> = constraint
+                    .a
+                    .iter()
+                    .chain(&constraint.b)
+                    .chain(&constraint.c)
+                    .map(|(wire, _)| *wire)
+                    .collect();
+                wires.sort();
+                wires.dedup();
+
+                ConstraintEval {
+                    index,
+                    a,
+                    b,
+                    c,
+                    satisfied: a * b == c,
+                    wires,
+                }
+            })
+            .collect()
+    }
+
+    // The variables a proving backend allocates, in allocation order: the
+    // public outputs and public inputs (declaration order) as proof inputs,
+    // then private inputs and every other variable a constraint reads, by id.
+    // The ONE variable is left out. Entries are (id, label, public).
+    pub fn proof_variables(&self) -> Vec<(usize, String, bool)> {
+        let mut labels: Vec<Option<(String, bool)>> = vec![None; self.num_variables];
+        for (name, id) in self.public_outputs.iter().chain(&self.public_inputs) {
+            labels[*id].get_or_insert((name.clone(), true));
+        }
+        for (name, id) in &self.private_inputs {
+            labels[*id].get_or_insert((name.clone(), false));
+        }
+        for constraint in &self.constraints {
+            for (id, _) in constraint
+                .a
+                .iter()
+                .chain(&constraint.b)
+                .chain(&constraint.c)
+            {
+                labels[*id].get_or_insert((format!("w{}", id), false));
+            }
+        }
+
+        let mut variables: Vec<(usize, String, bool)> = Vec::new();
+        for (_, id) in self.public_outputs.iter().chain(&self.public_inputs) {
+            if !variables.iter().any(|(allocated, _, _)| allocated == id) {
+                variables.push((*id, labels[*id].clone().unwrap_or_default().0, true));
+            }
+        }
+        for (id, label) in labels.into_iter().enumerate() {
+            if let Some((label, false)) = label {
+                if id != Wire::ONE.id {
+                    variables.push((id, label, false));
+                }
+            }
+        }
+        variables
+    }
+
+    // positions only, coefficients are left out
+    pub fn sparsity(&self) -> SparsityInfo {
+        let rows = self.constraints.len();
+        let cols = self.num_variables;
+        let matrix = |row_of: fn(&SparseConstraint) -> &LinearCombination| {
+            let mut nonzeros = Vec::new();
+            let mut max_row_nonzeros = 0;
+            let mut empty_rows = 0;
+            for (row, constraint) in self.constraints.iter().enumerate() {
+                let mut cols: Vec<usize> =
+                    row_of(constraint).iter().map(|(wire, _)| *wire).collect();
+                cols.sort();
+                max_row_nonzeros = max_row_nonzeros.max(cols.len());
+                if cols.is_empty() {
+                    empty_rows += 1;
+                }
+                nonzeros.extend(cols.into_iter().map(|col| (row, col)));
+            }
+            let cells = rows * cols;
+            MatrixSparsity {
+                nonzero_count: nonzeros.len(),
+                density: if cells == 0 {
+                    0.0
+                } else {
+                    nonzeros.len() as f64 / cells as f64
+                },
+                nonzeros,
+                max_row_nonzeros,
+                empty_rows,
+            }
+        };
+
+        SparsityInfo {
+            rows,
+            cols,
+            a: matrix(|constraint| &constraint.a),
+            b: matrix(|constraint| &constraint.b),
+            c: matrix(|constraint| &constraint.c),
+        }
+    }
+
+    // Drops constraints whose A, B and C rows equal those of an earlier
+    // constraint, term order within a row aside. The remaining constraints
+    // keep their order. Returns the indices the dropped ones had.
+    pub fn dedup_constraints(&mut self) -> Vec<usize> {
+        let sorted = |lc: &LinearCombination| {
+            let mut lc = lc.clone();
+            lc.sort_by_key(|(wire, _)| *wire);
+            lc
+        };
+
+        let mut seen = HashSet::new();
+        let mut dropped = Vec::new();
+        let mut index = 0;
+        self.constraints.retain(|constraint| {
+            let keep = seen.insert((
+                sorted(&constraint.a),
+                sorted(&constraint.b),
+                sorted(&constraint.c),
+            ));
+            if !keep {
+                dropped.push(index);
+            }
+            index += 1;
+            keep
+        });
+        self.num_constraints = self.constraints.len();
+        dropped
+    }
+
+    // Renumbers variables so that variable i is circuit wire order[i], as
+    // produced by Circuit::canonical_order. Wires not in the order must not
+    // appear in any constraint.
+    pub fn reordered(&self, order: &[usize]) -> R1csSystem {
+        let index_of: HashMap<usize, usize> = order
+            .iter()
+            .enumerate()
+            .map(|(index, wire)| (*wire, index))
+            .collect();
+        let remap = |lc: &LinearCombination| -> LinearCombination {
+            lc.iter()
+                .map(|(wire, coeff)| (index_of[wire], *coeff))
+                .collect()
+        };
+        let remap_inputs = |inputs: &Vec<(String, usize)>| -> Vec<(String, usize)> {
+            inputs
+                .iter()
+                .map(|(name, wire)| (name.clone(), index_of[wire]))
+                .collect()
+        };
+
+        R1csSystem {
+            num_constraints: self.num_constraints,
+            num_variables: order.len(),
+            constraints: self
+                .constraints
+                .iter()
+                .map(|constraint| SparseConstraint {
+                    a: remap(&constraint.a),
+                    b: remap(&constraint.b),
+                    c: remap(&constraint.c),
+                })
+                .collect(),
+            public_inputs: remap_inputs(&self.public_inputs),
+            private_inputs: remap_inputs(&self.private_inputs),
+            output_wire: index_of[&self.output_wire],
+            public_outputs: remap_inputs(&self.public_outputs),
+            wire_order: Some(order.to_vec()),
+        }
+    }
+
+    pub fn save_circom_files(
+        &self,
+        json_filename: &str,
+        sym_filename: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        write_json_file(json_filename, &self.to_circom_json())?;
+        std::fs::write(sym_filename, self.to_circom_sym())?;
         Ok(())
     }
 }
 
+// one constraint of a satisfiability check: the values of A·w, B·w and C·w
+#[derive(Debug, Clone)]
+pub struct ConstraintEval {
+    pub index: usize,
+    pub a: FieldElement,
+    pub b: FieldElement,
+    pub c: FieldElement,
+    pub satisfied: bool,
+    pub wires: Vec<usize>,
+}
+
+impl std::fmt::Display for ConstraintEval {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let wires: Vec<String> = self.wires.iter().map(|id| format!("w{}", id)).collect();
+        write!(
+            f,
+            "constraint {}: {} * {} {} {} [{}] (wires: {})",
+            self.index,
+            self.a,
+            self.b,
+            if self.satisfied { "==" } else { "!=" },
+            self.c,
+            if self.satisfied { "ok" } else { "FAILED" },
+            wires.join(", ")
+        )
+    }
+}
+
 pub struct CircuitBuilder {
     gates: Vec<Gate>,
     wire_counter: usize,
-    ssa_to_wire: HashMap<SsaValue, Wire>,
+    // ordered maps, so no hash iteration order can ever reach wire numbering
+    ssa_to_wire: BTreeMap<SsaValue, Wire>,
     public_inputs: Vec<(String, Wire)>,
     private_inputs: Vec<(String, Wire)>,
+    // when set, every constant value gets one shared Const gate and wire
+    intern_constants: bool,
+    // keyed by the value's canonical u64
+    constant_wires: BTreeMap<u64, Wire>,
+    // building fails as soon as there are more gates than this
+    max_gates: Option<usize>,
+}
+
+impl Default for CircuitBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CircuitBuilder {
     pub fn new() -> Self {
         Self {
             gates: Vec::new(),
-            wire_counter: 0,
-            ssa_to_wire: HashMap::new(),
+            wire_counter: Wire::ONE.id + 1,
+            ssa_to_wire: BTreeMap::new(),
             public_inputs: Vec::new(),
             private_inputs: Vec::new(),
+            intern_constants: false,
+            constant_wires: BTreeMap::new(),
+            max_gates: None,
         }
     }
 
+    // from_ssa, but SSA values holding the same constant share a single wire
+    // instead of each getting a Const gate of its own
+    pub fn from_ssa_interning_constants(ssa_program: SsaProgram) -> Circuit {
+        let builder = CircuitBuilder {
+            intern_constants: true,
+            ..CircuitBuilder::new()
+        };
+        builder.build_unlimited(ssa_program).0
+    }
+
+    // Wires are numbered deterministically: 0 is the constant ONE wire, then
+    // public inputs and private inputs in declaration order, then gate wires
+    // in program order as instructions are converted. SSA temps are likewise
+    // numbered t0, t1, ... in program order, so the same source and -O level
+    // always give the same circuit.
     pub fn from_ssa(ssa_program: SsaProgram) -> Circuit {
-        let mut builder = CircuitBuilder::new();
+        CircuitBuilder::new().build_unlimited(ssa_program).0
+    }
+
+    // from_ssa, plus where each wire carrying an SSA value comes from, by wire
+    // id; ONE and auxiliary wires like range check bits are not listed
+    pub fn from_ssa_with_metadata(ssa_program: SsaProgram) -> (Circuit, Vec<WireProvenance>) {
+        Self::try_from_ssa_with_metadata(ssa_program, None).expect(NO_GATE_LIMIT)
+    }
+
+    // from_ssa_interning_constants with provenance as in from_ssa_with_metadata
+    pub fn from_ssa_interning_constants_with_metadata(
+        ssa_program: SsaProgram,
+    ) -> (Circuit, Vec<WireProvenance>) {
+        Self::try_from_ssa_interning_constants_with_metadata(ssa_program, None)
+            .expect(NO_GATE_LIMIT)
+    }
+
+    // from_ssa_with_metadata, but with Some(max_gates) the conversion stops
+    // with an error at the first instruction taking the circuit past it
+    pub fn try_from_ssa_with_metadata(
+        ssa_program: SsaProgram,
+        max_gates: Option<usize>,
+    ) -> Result<(Circuit, Vec<WireProvenance>), GateLimitError> {
+        let builder = CircuitBuilder {
+            max_gates,
+            ..CircuitBuilder::new()
+        };
+        builder.build_with_metadata(ssa_program)
+    }
+
+    // from_ssa_interning_constants_with_metadata with a gate limit, as in
+    // try_from_ssa_with_metadata
+    pub fn try_from_ssa_interning_constants_with_metadata(
+        ssa_program: SsaProgram,
+        max_gates: Option<usize>,
+    ) -> Result<(Circuit, Vec<WireProvenance>), GateLimitError> {
+        let builder = CircuitBuilder {
+            intern_constants: true,
+            max_gates,
+            ..CircuitBuilder::new()
+        };
+        builder.build_with_metadata(ssa_program)
+    }
+
+    fn build_with_metadata(
+        self,
+        ssa_program: SsaProgram,
+    ) -> Result<(Circuit, Vec<WireProvenance>), GateLimitError> {
+        let spans = ssa_program.spans.clone();
+        let return_value = ssa_program.return_value.clone();
+        let (circuit, ssa_to_wire) = self.build(ssa_program)?;
+
+        let mut provenance: BTreeMap<usize, WireProvenance> = BTreeMap::new();
+        let mut carry = |wire: &Wire, value: &SsaValue| {
+            let entry = provenance.entry(wire.id).or_insert_with(|| WireProvenance {
+                wire: wire.clone(),
+                ssa_values: Vec::new(),
+                span: None,
+            });
+            entry.ssa_values.push(value.clone());
+            if entry.span.is_none() {
+                entry.span = spans.get(value).cloned();
+            }
+        };
+        for (value, wire) in &ssa_to_wire {
+            carry(wire, value);
+        }
+        // a returned input is copied to an output wire of its own
+        if ssa_to_wire.get(&return_value) != Some(&circuit.output_wire) {
+            carry(&circuit.output_wire, &return_value);
+        }
+
+        Ok((circuit, provenance.into_values().collect()))
+    }
+
+    fn build_unlimited(self, ssa_program: SsaProgram) -> (Circuit, BTreeMap<SsaValue, Wire>) {
+        self.build(ssa_program).expect(NO_GATE_LIMIT)
+    }
+
+    // the circuit, and the wire every SSA value ended up on
+    fn build(
+        self,
+        ssa_program: SsaProgram,
+    ) -> Result<(Circuit, BTreeMap<SsaValue, Wire>), GateLimitError> {
+        let mut builder = self;
 
         for input in &ssa_program.public_inputs {
             let wire = builder.get_or_create_wire(input);
@@ -99,19 +825,68 @@ impl CircuitBuilder {
 
         for instr in &ssa_program.instructions {
             builder.convert_instruction(instr);
+            let span = instr
+                .listing_parts()
+                .0
+                .and_then(|dest| ssa_program.spans.get(dest).cloned());
+            builder.check_gate_limit(|| instr.to_string(), span)?;
         }
 
-        let output_wire = builder.get_or_create_wire(&ssa_program.return_value);
+        let mut output_wire = builder.get_or_create_wire(&ssa_program.return_value);
+        // the output is a public signal, so a returned input is copied to a
+        // wire of its own instead of making a private input public
+        let is_input = |wire: &Wire| {
+            builder
+                .public_inputs
+                .iter()
+                .chain(&builder.private_inputs)
+                .any(|(_, input)| input == wire)
+        };
+        if is_input(&output_wire) {
+            let copy = builder.new_wire();
+            builder.gates.push(Gate::Lc {
+                output: copy.clone(),
+                terms: vec![(output_wire, FieldElement::one())],
+            });
+            output_wire = copy;
+            builder.check_gate_limit(
+                || format!("return {}", ssa_program.return_value),
+                ssa_program.spans.get(&ssa_program.return_value).cloned(),
+            )?;
+        }
 
-        Circuit {
+        let circuit = Circuit {
             public_inputs: builder.public_inputs,
             private_inputs: builder.private_inputs,
             gates: builder.gates,
             output_wire,
+        };
+        #[cfg(debug_assertions)]
+        if let Err(err) = circuit.validate() {
+            panic!("CircuitBuilder produced an invalid circuit: {}", err);
+        }
+        Ok((circuit, builder.ssa_to_wire))
+    }
+
+    fn check_gate_limit(
+        &self,
+        instruction: impl FnOnce() -> String,
+        span: Option<Span>,
+    ) -> Result<(), GateLimitError> {
+        match self.max_gates {
+            Some(max_gates) if self.gates.len() > max_gates => Err(GateLimitError {
+                max_gates,
+                instruction: instruction(),
+                span,
+            }),
+            _ => Ok(()),
         }
     }
 }
 
+// the builders without a max_gates have nothing to fail on
+const NO_GATE_LIMIT: &str = "building without a gate limit cannot fail";
+
 impl CircuitBuilder {
     fn new_wire(&mut self) -> Wire {
         let wire = Wire {
@@ -131,22 +906,54 @@ impl CircuitBuilder {
         }
     }
 
+    // a fresh Const gate, or the shared one when interning constants
+    fn constant_wire(&mut self, value: FieldElement) -> Wire {
+        if let Some(wire) = self.constant_wires.get(&value.as_u64()) {
+            return wire.clone();
+        }
+        let wire = self.new_wire();
+        self.gates.push(Gate::Const {
+            output: wire.clone(),
+            value,
+        });
+        if self.intern_constants {
+            self.constant_wires.insert(value.as_u64(), wire.clone());
+        }
+        wire
+    }
+
+    // output = -1 * operand, a linear combination rather than a product
+    fn emit_negation(&mut self, output: Wire, operand: Wire) {
+        self.gates.push(Gate::Lc {
+            output,
+            terms: vec![(operand, -FieldElement::one())],
+        });
+    }
+
     fn convert_instruction(&mut self, instr: &SsaInstruction) -> Wire {
         match instr {
             SsaInstruction::Const(dest, value) => {
+                let dest_wire = self.constant_wire(*value);
+                self.ssa_to_wire.insert(dest.clone(), dest_wire.clone());
+                dest_wire
+            }
+            SsaInstruction::Add(dest, left, right) => {
                 let dest_wire = self.get_or_create_wire(dest);
-                let gate = Gate::Const {
+                let left_wire = self.get_or_create_wire(left);
+                let right_wire = self.get_or_create_wire(right);
+                let gate = Gate::Add {
                     output: dest_wire.clone(),
-                    value: *value,
+                    left: left_wire,
+                    right: right_wire,
                 };
                 self.gates.push(gate);
                 dest_wire
             }
-            SsaInstruction::Add(dest, left, right) => {
+            SsaInstruction::Sub(dest, left, right) => {
                 let dest_wire = self.get_or_create_wire(dest);
                 let left_wire = self.get_or_create_wire(left);
                 let right_wire = self.get_or_create_wire(right);
-                let gate = Gate::Add {
+                let gate = Gate::Sub {
                     output: dest_wire.clone(),
                     left: left_wire,
                     right: right_wire,
@@ -154,6 +961,34 @@ impl CircuitBuilder {
                 self.gates.push(gate);
                 dest_wire
             }
+            SsaInstruction::Neg(dest, operand) => {
+                let dest_wire = self.get_or_create_wire(dest);
+                let operand_wire = self.get_or_create_wire(operand);
+                self.emit_negation(dest_wire.clone(), operand_wire);
+                dest_wire
+            }
+            SsaInstruction::Inv(dest, operand) => {
+                let dest_wire = self.get_or_create_wire(dest);
+                let input_wire = self.get_or_create_wire(operand);
+                let gate = Gate::Inv {
+                    output: dest_wire.clone(),
+                    input: input_wire,
+                };
+                self.gates.push(gate);
+                dest_wire
+            }
+            SsaInstruction::IsZero(dest, operand) => {
+                let dest_wire = self.get_or_create_wire(dest);
+                let input_wire = self.get_or_create_wire(operand);
+                let inverse_wire = self.new_wire();
+                let gate = Gate::IsZero {
+                    output: dest_wire.clone(),
+                    input: input_wire,
+                    inverse: inverse_wire,
+                };
+                self.gates.push(gate);
+                dest_wire
+            }
             SsaInstruction::Mul(dest, left, right) => {
                 let dest_wire = self.get_or_create_wire(dest);
                 let left_wire = self.get_or_create_wire(left);
@@ -166,74 +1001,741 @@ impl CircuitBuilder {
                 self.gates.push(gate);
                 dest_wire
             }
+            SsaInstruction::Square(dest, operand) => {
+                let dest_wire = self.get_or_create_wire(dest);
+                let input_wire = self.get_or_create_wire(operand);
+                self.gates.push(Gate::Square {
+                    output: dest_wire.clone(),
+                    input: input_wire,
+                });
+                dest_wire
+            }
+            SsaInstruction::RangeCheck(value, num_bits) => {
+                let input_wire = self.get_or_create_wire(value);
+                let bits: Vec<Wire> = (0..*num_bits).map(|_| self.new_wire()).collect();
+                self.gates.push(Gate::RangeCheck {
+                    input: input_wire.clone(),
+                    bits,
+                });
+                input_wire
+            }
             SsaInstruction::Assert(left, right) => {
                 let left_wire = self.get_or_create_wire(left);
                 let right_wire = self.get_or_create_wire(right);
-                let zero_wire = self.new_wire();
                 let gate = Gate::Assert {
-                    output: zero_wire.clone(),
-                    left: left_wire,
+                    left: left_wire.clone(),
                     right: right_wire,
                 };
                 self.gates.push(gate);
-                zero_wire
+                left_wire
             }
         }
     }
 }
 
 impl Circuit {
+    // saved as {"format_version": ..., <circuit fields>}
     pub fn save_to_file(&self, filename: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let json = serde_json::to_string_pretty(self)?;
-        std::fs::write(filename, json)?;
+        #[derive(Serialize)]
+        struct Envelope<'a> {
+            format_version: u64,
+            #[serde(flatten)]
+            circuit: &'a Circuit,
+        }
+
+        write_json_file(
+            filename,
+            &Envelope {
+                format_version: CIRCUIT_FORMAT_VERSION,
+                circuit: self,
+            },
+        )
+    }
+
+    // the version is checked before the circuit itself is parsed, so files
+    // written by another compiler version fail with a FormatVersionError
+    // instead of a confusing field error
+    pub fn load_from_file(filename: &str) -> Result<Circuit, Box<dyn std::error::Error>> {
+        let file = std::fs::File::open(filename)?;
+        let mut value: serde_json::Value = serde_json::from_reader(std::io::BufReader::new(file))?;
+
+        let found = value
+            .as_object_mut()
+            .and_then(|fields| fields.remove("format_version"))
+            .and_then(|version| version.as_u64());
+        if found != Some(CIRCUIT_FORMAT_VERSION) {
+            return Err(Box::new(FormatVersionError {
+                found,
+                expected: CIRCUIT_FORMAT_VERSION,
+            }));
+        }
+
+        Ok(serde_json::from_value(value)?)
+    }
+
+    // every wire a gate reads must be the ONE wire, an input, or the output of an earlier gate
+    pub fn topological_check(&self) -> Result<(), TopologyError> {
+        let mut defined: HashSet<&Wire> = HashSet::new();
+        defined.insert(&Wire::ONE);
+        defined.extend(self.public_inputs.iter().map(|(_, wire)| wire));
+        defined.extend(self.private_inputs.iter().map(|(_, wire)| wire));
+
+        for (gate_index, gate) in self.gates.iter().enumerate() {
+            if let Some(wire) = gate
+                .input_wires()
+                .into_iter()
+                .find(|wire| !defined.contains(wire))
+            {
+                return Err(TopologyError { gate_index, wire });
+            }
+            defined.extend(gate.output_refs());
+        }
+
         Ok(())
     }
 
-    pub fn to_r1cs(&self) -> R1csSystem {
-        // get max wire id + 1
-        let num_wires = self
-            .gates
+    // Structural invariants every circuit the builder produces holds, run after
+    // from_ssa in debug builds: inputs have distinct wires other than ONE, no
+    // wire is written twice (asserts write none, so none of them claims a
+    // wire), every wire read is allocated by an input or some gate, and so is
+    // the output. Gate order isn't looked at, that is topological_check.
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        let mut allocated: HashSet<&Wire> = HashSet::new();
+        allocated.insert(&Wire::ONE);
+        for (name, wire) in self.public_inputs.iter().chain(&self.private_inputs) {
+            if !allocated.insert(wire) {
+                return Err(InvariantError::SharedInputWire(name.clone(), wire.clone()));
+            }
+        }
+        for (gate_index, gate) in self.gates.iter().enumerate() {
+            for wire in gate.output_refs() {
+                if !allocated.insert(wire) {
+                    return Err(InvariantError::WireWrittenTwice {
+                        gate_index,
+                        wire: wire.clone(),
+                    });
+                }
+            }
+        }
+
+        for (gate_index, gate) in self.gates.iter().enumerate() {
+            if let Some(wire) = gate
+                .input_refs()
+                .into_iter()
+                .find(|wire| !allocated.contains(wire))
+            {
+                return Err(InvariantError::UnallocatedWire {
+                    gate_index,
+                    wire: wire.clone(),
+                });
+            }
+        }
+        if !allocated.contains(&self.output_wire) {
+            return Err(InvariantError::MissingOutput(self.output_wire.clone()));
+        }
+
+        Ok(())
+    }
+
+    // Unlike topological_check this ignores gate order, it only looks for a
+    // wire that (transitively) depends on itself. Hand-built or edited
+    // circuits can contain one; the witness calculator would leave it unset.
+    pub fn check_acyclic(&self) -> Result<(), CycleError> {
+        // wire -> wires its producing gate reads
+        let mut dependencies: HashMap<&Wire, Vec<&Wire>> = HashMap::new();
+        for gate in &self.gates {
+            for output in gate.output_refs() {
+                dependencies
+                    .entry(output)
+                    .or_default()
+                    .extend(gate.input_refs());
+            }
+        }
+
+        // depth-first search, a wire met again while still on the path closes a cycle
+        let mut finished: HashSet<&Wire> = HashSet::new();
+        let mut path: Vec<&Wire> = Vec::new();
+        let mut roots: Vec<&Wire> = dependencies.keys().copied().collect();
+        roots.sort_by_key(|wire| wire.id);
+
+        for root in roots {
+            if finished.contains(root) {
+                continue;
+            }
+            // (wire, index of the next dependency to visit)
+            let mut stack: Vec<(&Wire, usize)> = vec![(root, 0)];
+            path.push(root);
+
+            while let Some((wire, next)) = stack.last_mut() {
+                let deps = dependencies.get(wire).map_or(&[][..], Vec::as_slice);
+                if let Some(&dep) = deps.get(*next) {
+                    *next += 1;
+                    if let Some(start) = path.iter().position(|on_path| *on_path == dep) {
+                        let mut cycle: Vec<Wire> =
+                            path[start..].iter().map(|w| (*w).clone()).collect();
+                        cycle.push(dep.clone());
+                        return Err(CycleError { cycle });
+                    }
+                    if !finished.contains(dep) {
+                        stack.push((dep, 0));
+                        path.push(dep);
+                    }
+                } else {
+                    finished.insert(wire);
+                    stack.pop();
+                    path.pop();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // inputs that neither the output nor any assert depends on; their values are
+    // never checked, so a prover can pick them freely
+    pub fn unconstrained_inputs(&self) -> Vec<String> {
+        let mut reachable: HashSet<Wire> = HashSet::new();
+        reachable.insert(self.output_wire.clone());
+
+        // gates are in topological order, so one backwards pass sees every consumer first
+        for gate in self.gates.iter().rev() {
+            if matches!(gate, Gate::Assert { .. })
+                || gate
+                    .output_refs()
+                    .iter()
+                    .any(|wire| reachable.contains(*wire))
+            {
+                reachable.extend(gate.input_wires());
+            }
+        }
+
+        self.public_inputs
             .iter()
-            .flat_map(|gate| match gate {
-                Gate::Const { output, .. } => vec![output.id],
+            .chain(&self.private_inputs)
+            .filter(|(_, wire)| !reachable.contains(wire))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    // Fuses chains of Add gates into single Lc gates. An Add whose output is
+    // read only once, by another Add, is folded into that Add's terms, so
+    // a + b + c + d becomes one constraint instead of three.
+    pub fn fuse_additions(&self) -> Circuit {
+        let mut uses: HashMap<&Wire, usize> = HashMap::new();
+        let mut read_by_non_add: HashSet<&Wire> = HashSet::new();
+        for gate in &self.gates {
+            if !matches!(gate, Gate::Add { .. }) {
+                read_by_non_add.extend(gate.input_refs());
+            }
+            for wire in gate.input_refs() {
+                *uses.entry(wire).or_insert(0) += 1;
+            }
+        }
+        let absorbable = |wire: &Wire| {
+            uses.get(wire) == Some(&1)
+                && !read_by_non_add.contains(wire)
+                && *wire != self.output_wire
+        };
+
+        // terms of Add gates that were absorbed and not emitted
+        let mut pending: HashMap<Wire, Vec<(Wire, FieldElement)>> = HashMap::new();
+        let mut gates = Vec::new();
+
+        for gate in &self.gates {
+            let Gate::Add {
+                output,
+                left,
+                right,
+            } = gate
+            else {
+                gates.push(gate.clone());
+                continue;
+            };
+
+            let mut terms: Vec<(Wire, FieldElement)> = Vec::new();
+            let mut fused = false;
+            for operand in [left, right] {
+                match pending.remove(operand) {
+                    Some(operand_terms) => {
+                        fused = true;
+                        for (wire, coeff) in operand_terms {
+                            add_wire_term(&mut terms, wire, coeff);
+                        }
+                    }
+                    None => add_wire_term(&mut terms, operand.clone(), FieldElement::one()),
+                }
+            }
+
+            if absorbable(output) {
+                pending.insert(output.clone(), terms);
+            } else if fused {
+                gates.push(Gate::Lc {
+                    output: output.clone(),
+                    terms,
+                });
+            } else {
+                gates.push(gate.clone());
+            }
+        }
+
+        Circuit {
+            public_inputs: self.public_inputs.clone(),
+            private_inputs: self.private_inputs.clone(),
+            gates,
+            output_wire: self.output_wire.clone(),
+        }
+    }
+
+    // Replaces gates whose inputs are all constant (Const outputs or the ONE
+    // wire) by Const gates, then drops Const gates nothing reads anymore. This
+    // catches what the SSA folder cannot see, like fused Lc gates. Inverses of
+    // zero are left to the witness.
+    pub fn fold_constants(&self) -> Circuit {
+        let mut constants: HashMap<Wire, FieldElement> = HashMap::new();
+        constants.insert(Wire::ONE, FieldElement::one());
+        let mut gates = Vec::new();
+
+        for gate in &self.gates {
+            let value = |wire: &Wire| constants.get(wire).copied();
+            let folded: Option<Vec<(Wire, FieldElement)>> = match gate {
+                Gate::Const { output, value } => {
+                    constants.insert(output.clone(), *value);
+                    gates.push(gate.clone());
+                    continue;
+                }
                 Gate::Add {
                     output,
                     left,
                     right,
-                } => vec![output.id, left.id, right.id],
-                Gate::Mul {
+                } => value(left)
+                    .zip(value(right))
+                    .map(|(left, right)| vec![(output.clone(), left + right)]),
+                Gate::Sub {
                     output,
                     left,
                     right,
-                } => vec![output.id, left.id, right.id],
-                Gate::Assert {
+                } => value(left)
+                    .zip(value(right))
+                    .map(|(left, right)| vec![(output.clone(), left - right)]),
+                Gate::Mul {
                     output,
                     left,
                     right,
-                } => vec![output.id, left.id, right.id],
-            })
-            .chain(self.public_inputs.iter().map(|(_, wire)| wire.id))
-            .chain(self.private_inputs.iter().map(|(_, wire)| wire.id))
-            .chain(std::iter::once(self.output_wire.id))
+                } => value(left)
+                    .zip(value(right))
+                    .map(|(left, right)| vec![(output.clone(), left * right)]),
+                Gate::Square { output, input } => {
+                    value(input).map(|input| vec![(output.clone(), input * input)])
+                }
+                Gate::Lc { output, terms } => terms
+                    .iter()
+                    .try_fold(FieldElement::zero(), |sum, (wire, coeff)| {
+                        value(wire).map(|value| sum + *coeff * value)
+                    })
+                    .map(|sum| vec![(output.clone(), sum)]),
+                Gate::Inv { output, input } => value(input)
+                    .and_then(|input| input.inverse())
+                    .map(|inverse| vec![(output.clone(), inverse)]),
+                Gate::IsZero {
+                    output,
+                    input,
+                    inverse,
+                } => value(input).map(|input| match input.inverse() {
+                    Some(inverse_val) => vec![
+                        (output.clone(), FieldElement::zero()),
+                        (inverse.clone(), inverse_val),
+                    ],
+                    None => vec![
+                        (output.clone(), FieldElement::one()),
+                        (inverse.clone(), FieldElement::zero()),
+                    ],
+                }),
+                // checks are kept, folding them away would drop the constraint
+                Gate::Assert { .. } | Gate::RangeCheck { .. } => None,
+            };
+
+            match &folded {
+                Some(assignments) => {
+                    gates.extend(assignments.iter().map(|(output, value)| Gate::Const {
+                        output: output.clone(),
+                        value: *value,
+                    }))
+                }
+                None => gates.push(gate.clone()),
+            }
+            // a gate left in place still computes the same value for later gates
+            constants.extend(folded.unwrap_or_default());
+        }
+
+        let read: HashSet<Wire> = gates.iter().flat_map(Gate::input_wires).collect();
+        gates.retain(|gate| match gate {
+            Gate::Const { output, .. } => read.contains(output) || *output == self.output_wire,
+            _ => true,
+        });
+
+        Circuit {
+            public_inputs: self.public_inputs.clone(),
+            private_inputs: self.private_inputs.clone(),
+            gates,
+            output_wire: self.output_wire.clone(),
+        }
+    }
+
+    // Renumbers the wires the circuit uses to 0..n, keeping their relative
+    // order (so ONE stays 0 and the inputs stay first), since the R1CS has
+    // num_wires variables however many ids are unused. Also returns the
+    // old -> new wire of every renumbered wire.
+    pub fn compact_wires(&self) -> (Circuit, HashMap<Wire, Wire>) {
+        let mut used: Vec<usize> = self
+            .gates
+            .iter()
+            .flat_map(|gate| gate.output_refs().into_iter().chain(gate.input_refs()))
+            .chain(self.public_inputs.iter().map(|(_, wire)| wire))
+            .chain(self.private_inputs.iter().map(|(_, wire)| wire))
+            .chain([&Wire::ONE, &self.output_wire])
+            .map(|wire| wire.id)
+            .collect();
+        used.sort();
+        used.dedup();
+
+        let mapping: HashMap<Wire, Wire> = used
+            .into_iter()
+            .enumerate()
+            .map(|(new_id, old_id)| (Wire { id: old_id }, Wire { id: new_id }))
+            .collect();
+        let renumber = |wire: &Wire| mapping[wire].clone();
+        let renumber_inputs = |inputs: &[(String, Wire)]| -> Vec<(String, Wire)> {
+            inputs
+                .iter()
+                .map(|(name, wire)| (name.clone(), renumber(wire)))
+                .collect()
+        };
+
+        let circuit = Circuit {
+            public_inputs: renumber_inputs(&self.public_inputs),
+            private_inputs: renumber_inputs(&self.private_inputs),
+            gates: self
+                .gates
+                .iter()
+                .map(|gate| map_gate_wires(gate, renumber))
+                .collect(),
+            output_wire: renumber(&self.output_wire),
+        };
+        (circuit, mapping)
+    }
+
+    // Composes two circuits, feeding this one into `other`. Each (from, to) in
+    // `wire_mapping` drives the input `to` of `other` with the wire `from` of
+    // this circuit; `to` stops being an input. The remaining wires of `other`
+    // are renumbered above this circuit's wires (ONE stays ONE), its gates run
+    // after this circuit's gates and its output is the merged output.
+    // Unconnected inputs of both circuits remain inputs and need distinct names.
+    pub fn merge(
+        self,
+        other: Circuit,
+        wire_mapping: &[(Wire, Wire)],
+    ) -> Result<Circuit, MergeError> {
+        let mut defined: HashSet<&Wire> = HashSet::new();
+        defined.insert(&Wire::ONE);
+        defined.extend(self.public_inputs.iter().map(|(_, wire)| wire));
+        defined.extend(self.private_inputs.iter().map(|(_, wire)| wire));
+        defined.extend(self.gates.iter().flat_map(Gate::output_refs));
+
+        let other_inputs: HashSet<&Wire> = other
+            .public_inputs
+            .iter()
+            .chain(&other.private_inputs)
+            .map(|(_, wire)| wire)
+            .collect();
+
+        let mut renumbered: HashMap<Wire, Wire> = HashMap::new();
+        for (from, to) in wire_mapping {
+            if !defined.contains(from) {
+                return Err(MergeError::UnknownWire(from.clone()));
+            }
+            if !other_inputs.contains(to) {
+                return Err(MergeError::NotAnInput(to.clone()));
+            }
+            if renumbered.insert(to.clone(), from.clone()).is_some() {
+                return Err(MergeError::InputMappedTwice(to.clone()));
+            }
+        }
+
+        let offset = self.max_wire_id();
+        let renumber = |wire: &Wire| match renumbered.get(wire) {
+            Some(from) => from.clone(),
+            None if *wire == Wire::ONE => Wire::ONE,
+            None => Wire {
+                id: wire.id + offset,
+            },
+        };
+        let unconnected = |inputs: &[(String, Wire)]| -> Vec<(String, Wire)> {
+            inputs
+                .iter()
+                .filter(|(_, wire)| !renumbered.contains_key(wire))
+                .map(|(name, wire)| (name.clone(), renumber(wire)))
+                .collect()
+        };
+
+        let mut public_inputs = self.public_inputs;
+        public_inputs.extend(unconnected(&other.public_inputs));
+        let mut private_inputs = self.private_inputs;
+        private_inputs.extend(unconnected(&other.private_inputs));
+
+        let mut names = HashSet::new();
+        for (name, _) in public_inputs.iter().chain(&private_inputs) {
+            if !names.insert(name) {
+                return Err(MergeError::DuplicateInput(name.clone()));
+            }
+        }
+
+        let mut gates = self.gates;
+        gates.extend(
+            other
+                .gates
+                .iter()
+                .map(|gate| map_gate_wires(gate, renumber)),
+        );
+
+        Ok(Circuit {
+            public_inputs,
+            private_inputs,
+            gates,
+            output_wire: renumber(&other.output_wire),
+        })
+    }
+
+    // Every input with its wire, public ones then private ones, each in
+    // declaration order: the order the witness calculator assigns them in
+    // and canonical_order numbers them in, for callers laying out a witness
+    pub fn ordered_inputs(&self) -> Vec<(InputKind, String, Wire)> {
+        let public = self
+            .public_inputs
+            .iter()
+            .map(|(name, wire)| (InputKind::Public, name.clone(), wire.clone()));
+        let private = self
+            .private_inputs
+            .iter()
+            .map(|(name, wire)| (InputKind::Private, name.clone(), wire.clone()));
+        public.chain(private).collect()
+    }
+
+    // wire ids run from 0 to num_wires - 1, the R1CS variable count
+    pub fn num_wires(&self) -> usize {
+        self.max_wire_id() + 1
+    }
+
+    fn max_wire_id(&self) -> usize {
+        self.gates
+            .iter()
+            .flat_map(|gate| gate.output_refs().into_iter().chain(gate.input_refs()))
+            .chain(self.public_inputs.iter().map(|(_, wire)| wire))
+            .chain(self.private_inputs.iter().map(|(_, wire)| wire))
+            .chain(std::iter::once(&self.output_wire))
+            .map(|wire| wire.id)
             .max()
-            .unwrap_or(0)
-            + 1;
+            .unwrap_or(Wire::ONE.id)
+    }
 
-        let mut constraints = Vec::new();
+    // "index: gate" lines aligned as in listing::aligned_lines; with `edge`
+    // set, a circuit of more than 2 * edge gates shows only its first and last
+    // `edge` gates around a count of the rest
+    pub fn gate_lines(&self, edge: Option<usize>) -> Vec<String> {
+        let row = |(index, gate): (usize, &Gate)| {
+            let (assigned, rest) = gate.listing_parts();
+            ListingRow {
+                index,
+                assigned,
+                rest,
+            }
+        };
+        match edge {
+            Some(edge) if self.gates.len() > 2 * edge => {
+                let hidden = self.gates.len() - 2 * edge;
+                let rows: Vec<ListingRow> = self
+                    .gates
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| *index < edge || *index >= edge + hidden)
+                    .map(row)
+                    .collect();
+                let mut lines = aligned_lines(&rows);
+                lines.insert(edge, format!("... ({} more gates) ...", hidden));
+                lines
+            }
+            _ => {
+                let rows: Vec<ListingRow> = self.gates.iter().enumerate().map(row).collect();
+                aligned_lines(&rows)
+            }
+        }
+    }
+
+    // every gate as in gate_lines, then the output wire
+    pub fn to_aligned_string(&self) -> String {
+        let mut lines = self.gate_lines(None);
+        lines.push(format!("output: {}", self.output_wire));
+        lines.join("\n")
+    }
 
+    // constraints and wires are those of to_r1cs
+    pub fn stats(&self) -> CircuitStats {
+        let mut gates = BTreeMap::new();
         for gate in &self.gates {
-            let constraint = match gate {
-                Gate::Const { output, value } => {
-                    // 0 * value = output
-                    let mut a = vec![0; num_wires];
-                    let mut b = vec![0; num_wires];
-                    let mut c = vec![0; num_wires];
+            *gates.entry(gate.kind()).or_insert(0) += 1;
+        }
+        let r1cs = self.to_r1cs();
+
+        CircuitStats {
+            gates,
+            total_gates: self.gates.len(),
+            constraints: r1cs.num_constraints,
+            wires: r1cs.num_variables,
+            public_inputs: self.public_inputs.len(),
+            private_inputs: self.private_inputs.len(),
+        }
+    }
+
+    // The number of constraints to_r1cs emits (before dedup_constraints),
+    // counted from the gates without building any: one per gate, however many
+    // terms an Lc has, plus one per bit for a range check and two for is_zero,
+    // but none for a hint or for a Const gate unless it is the output
+    pub fn constraint_count_estimate(&self) -> usize {
+        self.gates
+            .iter()
+            .map(|gate| self.gate_constraint_count(gate))
+            .sum()
+    }
+
+    fn gate_constraint_count(&self, gate: &Gate) -> usize {
+        match gate {
+            Gate::Const { output, .. } if *output != self.output_wire => 0,
+            Gate::RangeCheck { bits, .. } => bits.len() + 1,
+            Gate::IsZero { .. } => 2,
+            _ => 1,
+        }
+    }
 
-                    a[0] = 1;
-                    b[0] = *value; // Constant term
-                    c[output.id] = 1;
+    // the index of the gate behind each constraint of to_r1cs, in constraint
+    // order; renumbering wires leaves it unchanged
+    pub fn constraint_gates(&self) -> Vec<usize> {
+        self.gates
+            .iter()
+            .enumerate()
+            .flat_map(|(index, gate)| std::iter::repeat_n(index, self.gate_constraint_count(gate)))
+            .collect()
+    }
+
+    // Source range of every gate: that of its output wire, or for asserts and
+    // range checks that of the first wire read, as in check_constant_asserts.
+    // `provenance` is what the circuit was built with, before compact_wires.
+    pub fn gate_spans(&self, provenance: &[WireProvenance]) -> Vec<Option<Span>> {
+        let spans: HashMap<&Wire, Span> = provenance
+            .iter()
+            .filter_map(|entry| entry.span.clone().map(|span| (&entry.wire, span)))
+            .collect();
+        self.gates
+            .iter()
+            .map(|gate| {
+                let wire = match gate.output_wire() {
+                    Some(output) => Some(output),
+                    None => gate.input_wires().first().cloned(),
+                };
+                wire.and_then(|wire| spans.get(&wire).cloned())
+            })
+            .collect()
+    }
+
+    // the source range behind each constraint of to_r1cs, with `gate_spans`
+    // as returned by gate_spans
+    pub fn source_map(&self, gate_spans: &[Option<Span>]) -> Vec<ConstraintSource> {
+        self.constraint_gates()
+            .into_iter()
+            .enumerate()
+            .map(|(constraint, gate)| ConstraintSource {
+                constraint,
+                gate,
+                span: gate_spans.get(gate).cloned().flatten(),
+            })
+            .collect()
+    }
 
-                    R1csConstraint { a, b, c }
+    // One line per constraint of `r1cs`, in the same order, describing it
+    // through the gate it comes from, e.g. for a product
+    // `Mul gate w5 = w3 * w4 → (1·w3)(1·w4) = (1·w5)`. `r1cs` is what was
+    // emitted for this circuit: to_r1cs, possibly reordered and deduplicated,
+    // with `gates[i]` the index of the gate behind its constraint i (see
+    // constraint_gates). Wires are numbered as in `r1cs`, wire 0 named ONE,
+    // and constants read by a gate show up as coefficients on ONE, as they do
+    // in the constraint.
+    pub fn explain_r1cs(&self, r1cs: &R1csSystem, gates: &[usize]) -> Vec<String> {
+        let index_of: Option<HashMap<usize, usize>> = r1cs.wire_order.as_ref().map(|order| {
+            order
+                .iter()
+                .enumerate()
+                .map(|(index, wire)| (*wire, index))
+                .collect()
+        });
+        gates
+            .iter()
+            .zip(&r1cs.constraints)
+            .map(|(gate, constraint)| {
+                let gate = &self.gates[*gate];
+                let gate = match &index_of {
+                    Some(index_of) => map_gate_wires(gate, |wire| Wire {
+                        id: index_of[&wire.id],
+                    }),
+                    None => gate.clone(),
+                };
+                format!(
+                    "{} gate {} → ({})({}) = ({})",
+                    gate_label(gate.kind()),
+                    gate,
+                    explain_lc(&constraint.a),
+                    explain_lc(&constraint.b),
+                    explain_lc(&constraint.c)
+                )
+            })
+            .collect()
+    }
+
+    // the public signals besides the public inputs: the output wire, named
+    // "out" as in the .sym file
+    pub fn public_outputs(&self) -> Vec<(String, usize)> {
+        vec![("out".to_string(), self.output_wire.id)]
+    }
+
+    pub fn to_r1cs(&self) -> R1csSystem {
+        let num_wires = self.num_wires();
+
+        // A constant wire read by a gate becomes a coefficient on ONE in that
+        // gate's constraint, so its Const gate only needs a constraint of its
+        // own when it is the output: x * 3 is the single 3x * 1 = out.
+        let constants: HashMap<usize, FieldElement> = self
+            .gates
+            .iter()
+            .filter_map(|gate| match gate {
+                Gate::Const { output, value } => Some((output.id, *value)),
+                _ => None,
+            })
+            .collect();
+        let read = |lc: &mut LinearCombination, wire: &Wire, coeff: FieldElement| match constants
+            .get(&wire.id)
+        {
+            Some(value) => add_term(lc, Wire::ONE.id, coeff * *value),
+            None => add_term(lc, wire.id, coeff),
+        };
+
+        let mut constraints = Vec::new();
+
+        for gate in &self.gates {
+            let mut constraint = SparseConstraint::new();
+            match gate {
+                Gate::Const { output, .. } if *output != self.output_wire => continue,
+                Gate::Const { output, value } => {
+                    // 1 * value = output
+                    add_term(&mut constraint.a, Wire::ONE.id, FieldElement::one());
+                    add_term(&mut constraint.b, Wire::ONE.id, *value); // Constant term
+                    add_term(&mut constraint.c, output.id, FieldElement::one());
                 }
                 Gate::Mul {
                     output,
@@ -241,15 +1743,15 @@ impl Circuit {
                     right,
                 } => {
                     // left * right = output
-                    let mut a = vec![0; num_wires];
-                    let mut b = vec![0; num_wires];
-                    let mut c = vec![0; num_wires];
-
-                    a[left.id] = 1;
-                    b[right.id] = 1;
-                    c[output.id] = 1;
-
-                    R1csConstraint { a, b, c }
+                    read(&mut constraint.a, left, FieldElement::one());
+                    read(&mut constraint.b, right, FieldElement::one());
+                    add_term(&mut constraint.c, output.id, FieldElement::one());
+                }
+                Gate::Square { output, input } => {
+                    // input * input = output
+                    read(&mut constraint.a, input, FieldElement::one());
+                    read(&mut constraint.b, input, FieldElement::one());
+                    add_term(&mut constraint.c, output.id, FieldElement::one());
                 }
                 Gate::Add {
                     output,
@@ -257,35 +1759,77 @@ impl Circuit {
                     right,
                 } => {
                     // (left + right) * 1 = output
-                    let mut a = vec![0; num_wires];
-                    let mut b = vec![0; num_wires];
-                    let mut c = vec![0; num_wires];
-
-                    a[left.id] = 1;
-                    a[right.id] = 1;
-                    b[0] = 1; // multiply by 1
-                    c[output.id] = 1;
-
-                    R1csConstraint { a, b, c }
+                    read(&mut constraint.a, left, FieldElement::one());
+                    read(&mut constraint.a, right, FieldElement::one());
+                    add_term(&mut constraint.b, Wire::ONE.id, FieldElement::one()); // multiply by 1
+                    add_term(&mut constraint.c, output.id, FieldElement::one());
                 }
-                Gate::Assert {
+                Gate::Sub {
                     output,
                     left,
                     right,
                 } => {
-                    // (left - right) * 1 = output (should be 0)
-                    let mut a = vec![0; num_wires];
-                    let mut b = vec![0; num_wires];
-                    let mut c = vec![0; num_wires];
+                    // (left - right) * 1 = output
+                    read(&mut constraint.a, left, FieldElement::one());
+                    read(&mut constraint.a, right, -FieldElement::one()); // p - 1
+                    add_term(&mut constraint.b, Wire::ONE.id, FieldElement::one()); // multiply by 1
+                    add_term(&mut constraint.c, output.id, FieldElement::one());
+                }
+                Gate::Assert { left, right } => {
+                    // (left - right) * 1 = 0, C stays empty
+                    add_term(&mut constraint.b, Wire::ONE.id, FieldElement::one()); // multiply by 1
+                    read(&mut constraint.a, left, FieldElement::one());
+                    read(&mut constraint.a, right, -FieldElement::one()); // p - 1
+                }
+                Gate::Lc { output, terms } => {
+                    // (sum of coeff * wire) * 1 = output
+                    for (wire, coeff) in terms {
+                        read(&mut constraint.a, wire, *coeff);
+                    }
+                    add_term(&mut constraint.b, Wire::ONE.id, FieldElement::one()); // multiply by 1
+                    add_term(&mut constraint.c, output.id, FieldElement::one());
+                }
+                Gate::RangeCheck { input, bits } => {
+                    // bit * bit = bit
+                    for bit in bits {
+                        let mut boolean = SparseConstraint::new();
+                        add_term(&mut boolean.a, bit.id, FieldElement::one());
+                        add_term(&mut boolean.b, bit.id, FieldElement::one());
+                        add_term(&mut boolean.c, bit.id, FieldElement::one());
+                        constraints.push(boolean);
+                    }
 
-                    a[left.id] = 1;
-                    a[right.id] = -1;
-                    b[0] = 1; // multiply by 1
-                    c[output.id] = 1;
+                    // (sum of 2^i * bit_i) * 1 = input
+                    for (i, bit) in bits.iter().enumerate() {
+                        add_term(&mut constraint.a, bit.id, FieldElement::new(1 << i));
+                    }
+                    add_term(&mut constraint.b, Wire::ONE.id, FieldElement::one()); // multiply by 1
+                    read(&mut constraint.c, input, FieldElement::one());
+                }
+                Gate::Inv { output, input } => {
+                    // input * output = 1
+                    read(&mut constraint.a, input, FieldElement::one());
+                    add_term(&mut constraint.b, output.id, FieldElement::one());
+                    add_term(&mut constraint.c, Wire::ONE.id, FieldElement::one());
+                }
+                Gate::IsZero {
+                    output,
+                    input,
+                    inverse,
+                } => {
+                    // input * inverse = 1 - output
+                    let mut first = SparseConstraint::new();
+                    read(&mut first.a, input, FieldElement::one());
+                    add_term(&mut first.b, inverse.id, FieldElement::one());
+                    add_term(&mut first.c, Wire::ONE.id, FieldElement::one());
+                    add_term(&mut first.c, output.id, -FieldElement::one());
+                    constraints.push(first);
 
-                    R1csConstraint { a, b, c }
+                    // input * output = 0
+                    read(&mut constraint.a, input, FieldElement::one());
+                    add_term(&mut constraint.b, output.id, FieldElement::one());
                 }
-            };
+            }
             constraints.push(constraint);
         }
 
@@ -304,35 +1848,1536 @@ impl Circuit {
                 .map(|(name, wire)| (name.clone(), wire.id))
                 .collect(),
             output_wire: self.output_wire.id,
+            public_outputs: self.public_outputs(),
+            wire_order: None,
+        }
+    }
+
+    // Wire ids in the layout Groth16 tooling expects: ONE, the output, public
+    // inputs, private inputs, then every other wire the gates use, by id. Unused ids
+    // are dropped, so the result is also compact.
+    pub fn canonical_order(&self) -> Vec<usize> {
+        let mut order = vec![Wire::ONE.id];
+        // a loaded circuit may still return an input wire directly
+        if !self
+            .public_inputs
+            .iter()
+            .chain(&self.private_inputs)
+            .any(|(_, wire)| *wire == self.output_wire)
+        {
+            order.push(self.output_wire.id);
+        }
+        order.extend(self.public_inputs.iter().map(|(_, wire)| wire.id));
+        order.extend(self.private_inputs.iter().map(|(_, wire)| wire.id));
+
+        let mut rest: Vec<usize> = self
+            .gates
+            .iter()
+            .flat_map(|gate| gate.output_refs().into_iter().chain(gate.input_refs()))
+            .chain(std::iter::once(&self.output_wire))
+            .map(|wire| wire.id)
+            .filter(|id| !order.contains(id))
+            .collect();
+        rest.sort();
+        rest.dedup();
+
+        order.extend(rest);
+        order
+    }
+
+    // Def and last use of every wire the circuit mentions, by wire id. After
+    // gate last_use, a wire's slot can hold a wire defined by a later gate.
+    pub fn liveness(&self) -> Vec<WireLiveness> {
+        let mut liveness: BTreeMap<usize, WireLiveness> = BTreeMap::new();
+
+        for (_, wire) in self.public_inputs.iter().chain(&self.private_inputs) {
+            liveness_entry(&mut liveness, wire);
+        }
+        for (index, gate) in self.gates.iter().enumerate() {
+            for wire in gate.input_refs() {
+                liveness_entry(&mut liveness, wire).last_use = Some(index);
+            }
+            for wire in gate.output_refs() {
+                liveness_entry(&mut liveness, wire).def.get_or_insert(index);
+            }
+        }
+        liveness_entry(&mut liveness, &self.output_wire).last_use = Some(self.gates.len());
+
+        liveness.into_values().collect()
+    }
+
+    // The most wires live at once while evaluating the gates in order: a wire
+    // is live from its def (the start for inputs) through its last use, and a
+    // wire nothing reads only at its def.
+    pub fn peak_live_wires(&self) -> usize {
+        // live[i] - live[i - 1], for i up to gates.len() where the output is read
+        let mut delta = vec![0isize; self.gates.len() + 2];
+        for entry in self.liveness() {
+            let start = entry.def.unwrap_or(0);
+            let end = entry.last_use.unwrap_or(start).max(start);
+            delta[start] += 1;
+            delta[end + 1] -= 1;
+        }
+        let mut live = 0;
+        let mut peak = 0;
+        for change in delta {
+            live += change;
+            peak = peak.max(live);
+        }
+        peak as usize
+    }
+
+    // Reorders the gates so fewer wires are live at once, for backends that
+    // keep only live wires in memory. Gates are emitted depth first from the
+    // gates whose outputs nothing reads, in their original order, each right
+    // after the gates computing its operands; of two operands the one needing
+    // more live wires (its Sethi-Ullman number) is computed first. Every gate
+    // still comes after the gates it reads, so values are unchanged, but a
+    // failing witness may now stop at a different assert. If the new order
+    // would not lower peak_live_wires, the circuit is returned as it is.
+    pub fn schedule_for_memory(&self) -> Circuit {
+        let producers: HashMap<&Wire, usize> = self
+            .gates
+            .iter()
+            .enumerate()
+            .flat_map(|(index, gate)| {
+                gate.output_refs()
+                    .into_iter()
+                    .map(move |wire| (wire, index))
+            })
+            .collect();
+        // the gates producing a gate's operands, each once, in operand order
+        let operand_gates: Vec<Vec<usize>> = self
+            .gates
+            .iter()
+            .map(|gate| {
+                let mut operands: Vec<usize> = Vec::new();
+                for wire in gate.input_refs() {
+                    if let Some(&producer) = producers.get(wire) {
+                        if !operands.contains(&producer) {
+                            operands.push(producer);
+                        }
+                    }
+                }
+                operands
+            })
+            .collect();
+
+        // gates are in topological order, so operands are numbered first
+        let mut need = vec![1usize; self.gates.len()];
+        for index in 0..self.gates.len() {
+            let mut operand_needs: Vec<usize> = operand_gates[index]
+                .iter()
+                .map(|&operand| need[operand])
+                .collect();
+            operand_needs.sort_by(|a, b| b.cmp(a));
+            need[index] = operand_needs
+                .iter()
+                .enumerate()
+                .map(|(position, need)| need + position)
+                .max()
+                .unwrap_or(1)
+                .max(1);
+        }
+
+        let mut read: Vec<bool> = vec![false; self.gates.len()];
+        for operands in &operand_gates {
+            for &operand in operands {
+                read[operand] = true;
+            }
+        }
+
+        let mut order = Vec::with_capacity(self.gates.len());
+        let mut emitted = vec![false; self.gates.len()];
+        for sink in (0..self.gates.len()).filter(|&index| !read[index]) {
+            // (gate, whether its operands were pushed already)
+            let mut stack = vec![(sink, false)];
+            while let Some((index, expanded)) = stack.pop() {
+                if emitted[index] {
+                    continue;
+                }
+                if expanded {
+                    emitted[index] = true;
+                    order.push(index);
+                    continue;
+                }
+                stack.push((index, true));
+                let mut operands: Vec<usize> = operand_gates[index]
+                    .iter()
+                    .copied()
+                    .filter(|&operand| !emitted[operand])
+                    .collect();
+                // popped in reverse, so the neediest operand runs first
+                operands.sort_by(|&a, &b| need[a].cmp(&need[b]).then(b.cmp(&a)));
+                stack.extend(operands.into_iter().map(|operand| (operand, false)));
+            }
+        }
+
+        let scheduled = Circuit {
+            public_inputs: self.public_inputs.clone(),
+            private_inputs: self.private_inputs.clone(),
+            gates: order
+                .iter()
+                .map(|&index| self.gates[index].clone())
+                .collect(),
+            output_wire: self.output_wire.clone(),
+        };
+        if scheduled.peak_live_wires() < self.peak_live_wires() {
+            scheduled
+        } else {
+            self.clone()
+        }
+    }
+
+    // Gate indices grouped by depth: a gate sits one layer above the deepest
+    // gate producing one of its inputs, so gates within a layer never read each
+    // other's outputs. Layers and the indices inside them are in gate order.
+    pub fn gate_layers(&self) -> Vec<Vec<usize>> {
+        // wire -> layer of the gate that produced it, inputs and ONE are absent
+        let mut wire_layer: HashMap<&Wire, usize> = HashMap::new();
+        let mut layers: Vec<Vec<usize>> = Vec::new();
+
+        for (gate_index, gate) in self.gates.iter().enumerate() {
+            let layer = gate
+                .input_refs()
+                .into_iter()
+                .filter_map(|wire| wire_layer.get(wire))
+                .map(|layer| layer + 1)
+                .max()
+                .unwrap_or(0);
+            for output in gate.output_refs() {
+                wire_layer.insert(output, layer);
+            }
+            if layers.len() <= layer {
+                layers.resize(layer + 1, Vec::new());
+            }
+            layers[layer].push(gate_index);
         }
+
+        layers
+    }
+}
+
+// the same gate with every wire, read or written, passed through `map`
+fn map_gate_wires(gate: &Gate, map: impl Fn(&Wire) -> Wire) -> Gate {
+    match gate {
+        Gate::Const { output, value } => Gate::Const {
+            output: map(output),
+            value: *value,
+        },
+        Gate::Add {
+            output,
+            left,
+            right,
+        } => Gate::Add {
+            output: map(output),
+            left: map(left),
+            right: map(right),
+        },
+        Gate::Mul {
+            output,
+            left,
+            right,
+        } => Gate::Mul {
+            output: map(output),
+            left: map(left),
+            right: map(right),
+        },
+        Gate::Square { output, input } => Gate::Square {
+            output: map(output),
+            input: map(input),
+        },
+        Gate::Sub {
+            output,
+            left,
+            right,
+        } => Gate::Sub {
+            output: map(output),
+            left: map(left),
+            right: map(right),
+        },
+        Gate::Assert { left, right } => Gate::Assert {
+            left: map(left),
+            right: map(right),
+        },
+        Gate::Inv { output, input } => Gate::Inv {
+            output: map(output),
+            input: map(input),
+        },
+        Gate::Lc { output, terms } => Gate::Lc {
+            output: map(output),
+            terms: terms
+                .iter()
+                .map(|(wire, coeff)| (map(wire), *coeff))
+                .collect(),
+        },
+        Gate::RangeCheck { input, bits } => Gate::RangeCheck {
+            input: map(input),
+            bits: bits.iter().map(&map).collect(),
+        },
+        Gate::IsZero {
+            output,
+            input,
+            inverse,
+        } => Gate::IsZero {
+            output: map(output),
+            input: map(input),
+            inverse: map(inverse),
+        },
+    }
+}
+
+fn liveness_entry<'a>(
+    liveness: &'a mut BTreeMap<usize, WireLiveness>,
+    wire: &Wire,
+) -> &'a mut WireLiveness {
+    liveness.entry(wire.id).or_insert_with(|| WireLiveness {
+        wire: wire.clone(),
+        def: None,
+        last_use: None,
+    })
+}
+
+// like add_term, for the (Wire, coeff) terms of an Lc gate
+fn add_wire_term(terms: &mut Vec<(Wire, FieldElement)>, wire: Wire, coeff: FieldElement) {
+    if let Some(pos) = terms.iter().position(|(existing, _)| *existing == wire) {
+        terms[pos].1 = terms[pos].1 + coeff;
+        if terms[pos].1.is_zero() {
+            terms.remove(pos);
+        }
+    } else if !coeff.is_zero() {
+        terms.push((wire, coeff));
+    }
+}
+
+// The circuit outgrew the max_gates of CircuitBuilder::try_from_ssa_with_metadata
+#[derive(Debug)]
+pub struct GateLimitError {
+    pub max_gates: usize,
+    // the SSA instruction whose gates went past the limit
+    pub instruction: String,
+    // source of the value it defines, if it has one
+    pub span: Option<Span>,
+}
+
+impl std::fmt::Display for GateLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Circuit has more than {} gates after converting `{}`",
+            self.max_gates, self.instruction
+        )?;
+        if let Some(span) = &self.span {
+            write!(f, " from {}", span)?;
+        }
+        Ok(())
     }
 }
 
+impl std::error::Error for GateLimitError {}
+
+#[derive(Debug)]
+pub struct TopologyError {
+    pub gate_index: usize,
+    pub wire: Wire,
+}
+
+impl std::fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Gate {} reads {} before it is defined",
+            self.gate_index, self.wire
+        )
+    }
+}
+
+impl std::error::Error for TopologyError {}
+
+#[derive(Debug)]
+pub struct CycleError {
+    pub cycle: Vec<Wire>, // starts and ends with the same wire
+}
+
+impl std::fmt::Display for CycleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let wires: Vec<String> = self.cycle.iter().map(|wire| wire.to_string()).collect();
+        write!(f, "Wires depend on each other: {}", wires.join(" -> "))
+    }
+}
+
+impl std::error::Error for CycleError {}
+
+#[derive(Debug)]
+pub struct FormatVersionError {
+    pub found: Option<u64>, // None if the file has no format_version
+    pub expected: u64,
+}
+
+impl std::fmt::Display for FormatVersionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.found {
+            Some(found) => write!(
+                f,
+                "Circuit file has format version {}, this compiler reads version {}; recompile the source",
+                found, self.expected
+            ),
+            None => write!(
+                f,
+                "Circuit file has no format_version, it was written by an older compiler; recompile the source"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FormatVersionError {}
+
+#[derive(Debug)]
+pub enum InvariantError {
+    SharedInputWire(String, Wire), // the input, its wire is ONE or another input's
+    WireWrittenTwice { gate_index: usize, wire: Wire },
+    UnallocatedWire { gate_index: usize, wire: Wire }, // read, but no input or gate has it
+    MissingOutput(Wire),
+}
+
+impl std::fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InvariantError::SharedInputWire(name, wire) => {
+                write!(f, "Input {} is on {}, which is already taken", name, wire)
+            }
+            InvariantError::WireWrittenTwice { gate_index, wire } => write!(
+                f,
+                "Gate {} writes {}, which is already written",
+                gate_index, wire
+            ),
+            InvariantError::UnallocatedWire { gate_index, wire } => write!(
+                f,
+                "Gate {} reads {}, which no input or gate allocates",
+                gate_index, wire
+            ),
+            InvariantError::MissingOutput(wire) => {
+                write!(f, "Output wire {} is not allocated", wire)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvariantError {}
+
+#[derive(Debug)]
+pub enum MergeError {
+    UnknownWire(Wire),      // mapped from a wire the first circuit doesn't define
+    NotAnInput(Wire),       // mapped to a wire that isn't an input of the second circuit
+    InputMappedTwice(Wire), // second circuit's input
+    DuplicateInput(String), // both circuits keep an input with this name
+}
+
+impl std::fmt::Display for MergeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MergeError::UnknownWire(wire) => {
+                write!(f, "Wire {} is not defined in the first circuit", wire)
+            }
+            MergeError::NotAnInput(wire) => {
+                write!(f, "Wire {} is not an input of the second circuit", wire)
+            }
+            MergeError::InputMappedTwice(wire) => {
+                write!(f, "Input {} of the second circuit is connected twice", wire)
+            }
+            MergeError::DuplicateInput(name) => {
+                write!(f, "Both circuits have an input named {}", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
 impl std::fmt::Display for Wire {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "w{}", self.id)
     }
 }
 
-impl std::fmt::Display for Gate {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+// A gate as listed: the wires it assigns, if any, and what they are assigned,
+// split so listings can line up the " = " of every gate
+impl Gate {
+    fn listing_parts(&self) -> (Option<String>, String) {
         match self {
-            Gate::Const { output, value } => write!(f, "{} = {}", output, value),
+            Gate::Const { output, value } => (Some(output.to_string()), value.to_string()),
             Gate::Add {
                 output,
                 left,
                 right,
-            } => write!(f, "{} = {} + {}", output, left, right),
+            } => (Some(output.to_string()), format!("{} + {}", left, right)),
             Gate::Mul {
                 output,
                 left,
                 right,
-            } => write!(f, "{} = {} * {}", output, left, right),
-            Gate::Assert {
+            } => (Some(output.to_string()), format!("{} * {}", left, right)),
+            Gate::Square { output, input } => {
+                (Some(output.to_string()), format!("square({})", input))
+            }
+            Gate::Sub {
                 output,
                 left,
                 right,
-            } => write!(f, "{} = {} - {}", output, left, right),
+            } => (Some(output.to_string()), format!("{} - {}", left, right)),
+            Gate::Assert { left, right } => (None, format!("assert {} == {}", left, right)),
+            Gate::Inv { output, input } => (Some(output.to_string()), format!("inv({})", input)),
+            Gate::RangeCheck { input, bits } => match (bits.first(), bits.last()) {
+                (Some(first), Some(last)) => (
+                    Some(format!("{}..{}", first, last)),
+                    format!("bits({}) [{} bits]", input, bits.len()),
+                ),
+                _ => (None, format!("range_check({}) [0 bits]", input)),
+            },
+            Gate::Lc { output, terms } => {
+                let terms: Vec<String> = terms
+                    .iter()
+                    .map(|(wire, coeff)| {
+                        if *coeff == FieldElement::one() {
+                            wire.to_string()
+                        } else {
+                            format!("{}*{}", coeff, wire)
+                        }
+                    })
+                    .collect();
+                (Some(output.to_string()), terms.join(" + "))
+            }
+            Gate::IsZero {
+                output,
+                input,
+                inverse,
+            } => (
+                Some(output.to_string()),
+                format!("is_zero({}) [inverse {}]", input, inverse),
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Gate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.listing_parts() {
+            (Some(assigned), rest) => write!(f, "{} = {}", assigned, rest),
+            (None, rest) => write!(f, "{}", rest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{compile, compile_str};
+    use crate::optimizer::OptLevel;
+    use crate::witness::{InputFile, WitnessCalculator};
+
+    fn wire(id: usize) -> Wire {
+        Wire { id }
+    }
+
+    // public x on wire 1, private y on wire 2, out = x * y on wire 3
+    fn product() -> Circuit {
+        Circuit {
+            public_inputs: vec![("x".to_string(), wire(1))],
+            private_inputs: vec![("y".to_string(), wire(2))],
+            gates: vec![Gate::Mul {
+                output: wire(3),
+                left: wire(1),
+                right: wire(2),
+            }],
+            output_wire: wire(3),
+        }
+    }
+
+    #[test]
+    fn compiled_circuits_are_valid() {
+        assert!(product().validate().is_ok());
+        let circuit = compile_str("public x\nprivate y\nassert x == y\nreturn x * y + 1").unwrap();
+        assert!(circuit.validate().is_ok());
+    }
+
+    #[test]
+    fn input_sharing_a_wire_is_invalid() {
+        let mut circuit = product();
+        circuit.private_inputs[0].1 = wire(1);
+        assert!(matches!(
+            circuit.validate(),
+            Err(InvariantError::SharedInputWire(name, w)) if name == "y" && w == wire(1)
+        ));
+
+        let mut circuit = product();
+        circuit.public_inputs[0].1 = Wire::ONE;
+        assert!(matches!(
+            circuit.validate(),
+            Err(InvariantError::SharedInputWire(name, w)) if name == "x" && w == Wire::ONE
+        ));
+    }
+
+    #[test]
+    fn wire_written_twice_is_invalid() {
+        let mut circuit = product();
+        circuit.gates.push(Gate::Const {
+            output: wire(3),
+            value: FieldElement::one(),
+        });
+        assert!(matches!(
+            circuit.validate(),
+            Err(InvariantError::WireWrittenTwice { gate_index: 1, wire: w }) if w == wire(3)
+        ));
+
+        // an input is written by the inputs file, so a gate can't write it
+        let mut circuit = product();
+        circuit.gates.insert(
+            0,
+            Gate::Const {
+                output: wire(2),
+                value: FieldElement::one(),
+            },
+        );
+        assert!(matches!(
+            circuit.validate(),
+            Err(InvariantError::WireWrittenTwice { gate_index: 0, wire: w }) if w == wire(2)
+        ));
+    }
+
+    #[test]
+    fn reading_an_unallocated_wire_is_invalid() {
+        let mut circuit = product();
+        circuit.gates.push(Gate::Assert {
+            left: wire(3),
+            right: wire(9),
+        });
+        assert!(matches!(
+            circuit.validate(),
+            Err(InvariantError::UnallocatedWire { gate_index: 1, wire: w }) if w == wire(9)
+        ));
+    }
+
+    #[test]
+    fn unallocated_output_is_invalid() {
+        let mut circuit = product();
+        circuit.output_wire = wire(4);
+        assert!(matches!(
+            circuit.validate(),
+            Err(InvariantError::MissingOutput(w)) if w == wire(4)
+        ));
+    }
+
+    #[test]
+    fn inverse_hint_is_checked_by_its_constraint() {
+        let circuit = compile_str("public x\nreturn inv(x)").unwrap();
+        let gate = circuit
+            .gates
+            .iter()
+            .find(|gate| gate.hint().is_some())
+            .unwrap();
+        assert_eq!(gate.hint(), Some((HintOp::Inverse, &circuit.output_wire)));
+
+        let mut calculator = WitnessCalculator::new();
+        let inputs = InputFile::builder().public("x", 4).build();
+        calculator.calculate_witness(&circuit, inputs).unwrap();
+        let mut witness = calculator.witness_vector();
+        let out = circuit.output_wire.id;
+        assert_eq!(
+            witness[out] * FieldElement::new(4),
+            FieldElement::one(),
+            "the prover computes out = x^-1"
+        );
+
+        let r1cs = circuit.to_r1cs();
+        assert!(r1cs.evaluate(&witness).iter().all(|eval| eval.satisfied));
+
+        witness[out] = witness[out] + FieldElement::one();
+        assert!(!r1cs.evaluate(&witness).iter().all(|eval| eval.satisfied));
+    }
+
+    #[test]
+    fn add_explanation_multiplies_by_one() {
+        let circuit = compile("public x\nprivate y\nreturn x + y", OptLevel::O0).unwrap();
+        let r1cs = circuit.to_r1cs();
+        let explained = circuit.explain_r1cs(&r1cs, &circuit.constraint_gates());
+        assert_eq!(
+            explained,
+            ["Add gate w3 = w1 + w2 → (1·w1 + 1·w2)(1·ONE) = (1·w3)"]
+        );
+    }
+
+    #[test]
+    fn explanation_uses_the_emitted_wire_order() {
+        let circuit = compile(
+            "public x\nprivate y\nlet t = x * y\nreturn t * y",
+            OptLevel::O0,
+        )
+        .unwrap();
+        let order = circuit.canonical_order();
+        let r1cs = circuit.to_r1cs().reordered(&order);
+        let explained = circuit.explain_r1cs(&r1cs, &circuit.constraint_gates());
+        // the output w4 moves to w1 and the inputs after it
+        assert_eq!(
+            explained,
+            [
+                "Mul gate w4 = w2 * w3 → (1·w2)(1·w3) = (1·w4)",
+                "Mul gate w1 = w4 * w3 → (1·w4)(1·w3) = (1·w1)",
+            ]
+        );
+    }
+
+    #[test]
+    fn ordered_inputs_keep_declaration_order() {
+        let circuit = compile(
+            "private b\npublic z\nprivate a\npublic y\nreturn z * y + a * b",
+            OptLevel::O0,
+        )
+        .unwrap();
+        let inputs: Vec<(InputKind, String, usize)> = circuit
+            .ordered_inputs()
+            .into_iter()
+            .map(|(kind, name, wire)| (kind, name, wire.id))
+            .collect();
+        assert_eq!(
+            inputs,
+            [
+                (InputKind::Public, "z".to_string(), 1),
+                (InputKind::Public, "y".to_string(), 2),
+                (InputKind::Private, "b".to_string(), 3),
+                (InputKind::Private, "a".to_string(), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn circom_json_of_a_single_mul() {
+        let circuit = compile("public x\nprivate y\nreturn x * y", OptLevel::O0).unwrap();
+        let r1cs = circuit.to_r1cs();
+        assert_eq!(
+            r1cs.to_circom_json(),
+            serde_json::json!({
+                "prime": "18446744069414584321",
+                "constraints": [[{ "1": "1" }, { "2": "1" }, { "3": "1" }]]
+            })
+        );
+        assert_eq!(
+            r1cs.to_circom_sym(),
+            "0,0,0,one\n1,1,0,main.x\n2,2,0,main.y\n3,3,0,main.out\n"
+        );
+    }
+
+    #[test]
+    fn sparse_constraint_keeps_only_its_terms() {
+        let inputs: Vec<String> = (0..1000).map(|i| format!("y{}", i)).collect();
+        let declarations: String = inputs
+            .iter()
+            .map(|name| format!("private {}\n", name))
+            .collect();
+        let sum: String = inputs
+            .iter()
+            .map(|name| format!("s += {}\n", name))
+            .collect();
+        let source = format!("public x\n{}let s = 0\n{}return s * x", declarations, sum);
+        let r1cs = compile(&source, OptLevel::O0).unwrap().to_r1cs();
+        let product = r1cs.constraints.last().unwrap();
+        assert_eq!(
+            (product.a.len(), product.b.len(), product.c.len()),
+            (1, 1, 1)
+        );
+
+        // the dense rows hold a coefficient for every variable, all but one zero
+        let dense = product.to_dense(r1cs.num_variables);
+        for (sparse, row) in [
+            (&product.a, &dense.a),
+            (&product.b, &dense.b),
+            (&product.c, &dense.c),
+        ] {
+            assert_eq!(row.len(), r1cs.num_variables);
+            assert!(r1cs.num_variables > 1000);
+            let nonzero: LinearCombination = row
+                .iter()
+                .enumerate()
+                .filter(|(_, coeff)| !coeff.is_zero())
+                .map(|(wire, coeff)| (wire, *coeff))
+                .collect();
+            assert_eq!(&nonzero, sparse);
         }
     }
+
+    // a file in the system temp dir, unique to this process and test
+    fn temp_file(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("circuit-compiler-{}-{}", std::process::id(), name))
+            .display()
+            .to_string()
+    }
+
+    #[test]
+    fn streamed_files_match_the_serialized_json() {
+        let source = format!(
+            "public x\nlet acc = x\n{}return acc",
+            "acc += acc * x\n".repeat(2000)
+        );
+        let circuit = compile(&source, OptLevel::O0).unwrap();
+        assert!(circuit.gates.len() >= 4000);
+
+        let r1cs = circuit.to_r1cs();
+        let r1cs_file = temp_file("streamed.r1cs");
+        r1cs.save_to_file(&r1cs_file).unwrap();
+        let saved = std::fs::read_to_string(&r1cs_file).unwrap();
+        assert_eq!(saved, serde_json::to_string_pretty(&r1cs).unwrap());
+
+        // saving what was loaded back writes the same bytes again
+        let circuit_file = temp_file("streamed.json");
+        circuit.save_to_file(&circuit_file).unwrap();
+        let saved = std::fs::read(&circuit_file).unwrap();
+        Circuit::load_from_file(&circuit_file)
+            .unwrap()
+            .save_to_file(&circuit_file)
+            .unwrap();
+        assert_eq!(std::fs::read(&circuit_file).unwrap(), saved);
+
+        std::fs::remove_file(r1cs_file).unwrap();
+        std::fs::remove_file(circuit_file).unwrap();
+    }
+
+    #[test]
+    fn compiling_twice_gives_identical_json() {
+        let source = "public a\nprivate b\nfn square(x) {\nreturn x * x\n}\nlet s = square(a + 1)\nlet t = s * b + a\nassert t != 0\nreturn t * s";
+        let json = || {
+            let circuit = compile_str(source).unwrap();
+            (
+                serde_json::to_string(&circuit).unwrap(),
+                serde_json::to_string(&circuit.to_r1cs()).unwrap(),
+            )
+        };
+        assert_eq!(json(), json());
+    }
+
+    #[test]
+    fn out_of_order_gate_fails_the_topological_check() {
+        // out = x * y, then a gate reading it moved in front of the mul
+        let mut circuit = product();
+        circuit.gates.insert(
+            0,
+            Gate::Add {
+                output: wire(4),
+                left: wire(3),
+                right: wire(1),
+            },
+        );
+        circuit.output_wire = wire(4);
+        let err = circuit.topological_check().unwrap_err();
+        assert_eq!(err.gate_index, 0);
+        assert_eq!(err.wire, wire(3));
+
+        circuit.gates.swap(0, 1);
+        assert!(circuit.topological_check().is_ok());
+    }
+
+    #[test]
+    fn evaluation_flags_the_constraint_a_broken_witness_breaks() {
+        let circuit = compile(
+            "public x\nprivate y\nlet p = x * y\nreturn p + x",
+            OptLevel::O0,
+        )
+        .unwrap();
+        let r1cs = circuit.to_r1cs();
+        let mut calculator = WitnessCalculator::new();
+        let inputs = InputFile::builder().public("x", 3).private("y", 5).build();
+        calculator.calculate_witness(&circuit, inputs).unwrap();
+        let mut witness = calculator.witness_vector();
+        assert!(r1cs.evaluate(&witness).iter().all(|eval| eval.satisfied));
+
+        // a wrong product breaks x * y = p, and p + x = out with it
+        let Gate::Mul { output, .. } = &circuit.gates[0] else {
+            panic!("expected the mul first, got {}", circuit.gates[0]);
+        };
+        witness[output.id] = FieldElement::new(16);
+        let evals = r1cs.evaluate(&witness);
+        let failed: Vec<usize> = evals
+            .iter()
+            .filter(|eval| !eval.satisfied)
+            .map(|eval| eval.index)
+            .collect();
+        assert_eq!(failed, [0, 1]);
+        assert_eq!(
+            evals[0].to_string(),
+            format!(
+                "constraint 0: 3 * 5 != 16 [FAILED] (wires: w1, w2, w{})",
+                output.id
+            )
+        );
+    }
+
+    #[test]
+    fn unused_private_input_is_unconstrained() {
+        let circuit = compile(
+            "public x\nprivate y\nprivate z\nassert z == 1\nreturn x * x",
+            OptLevel::O0,
+        )
+        .unwrap();
+        assert_eq!(circuit.unconstrained_inputs(), ["y"]);
+    }
+
+    #[test]
+    fn sum_of_four_fuses_into_one_constraint() {
+        let source = "public a\npublic b\npublic c\npublic d\nreturn a + b + c + d";
+        let unfused = compile(source, OptLevel::O0).unwrap();
+        assert_eq!(unfused.to_r1cs().constraints.len(), 3);
+        let fused = compile_str(source).unwrap();
+        assert_eq!(fused.to_r1cs().constraints.len(), 1);
+        assert!(matches!(&fused.gates[..], [Gate::Lc { terms, .. }] if terms.len() == 4));
+    }
+
+    #[test]
+    fn mismatched_format_version_fails_to_load() {
+        let filename = temp_file("old-version.json");
+        product().save_to_file(&filename).unwrap();
+        let saved = std::fs::read_to_string(&filename).unwrap();
+        let current = format!("\"format_version\": {}", CIRCUIT_FORMAT_VERSION);
+        assert!(saved.contains(&current));
+
+        std::fs::write(&filename, saved.replace(&current, "\"format_version\": 1")).unwrap();
+        let err = Circuit::load_from_file(&filename).unwrap_err();
+        let err = err.downcast_ref::<FormatVersionError>().unwrap();
+        assert_eq!(err.found, Some(1));
+        assert_eq!(err.expected, CIRCUIT_FORMAT_VERSION);
+
+        std::fs::write(&filename, saved.replace(&format!("{},", current), "")).unwrap();
+        let err = Circuit::load_from_file(&filename).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FormatVersionError>().unwrap().found,
+            None
+        );
+        std::fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn hand_built_cycle_is_rejected() {
+        // w3 = x * w4 and w4 = w3 + y, each waiting on the other
+        let circuit = Circuit {
+            public_inputs: vec![("x".to_string(), wire(1))],
+            private_inputs: vec![("y".to_string(), wire(2))],
+            gates: vec![
+                Gate::Mul {
+                    output: wire(3),
+                    left: wire(1),
+                    right: wire(4),
+                },
+                Gate::Add {
+                    output: wire(4),
+                    left: wire(3),
+                    right: wire(2),
+                },
+            ],
+            output_wire: wire(4),
+        };
+        let err = circuit.check_acyclic().unwrap_err();
+        assert_eq!(err.cycle.first(), err.cycle.last());
+        assert!(
+            err.cycle.contains(&wire(3)) && err.cycle.contains(&wire(4)),
+            "{}",
+            err
+        );
+        assert!(
+            !err.cycle.contains(&wire(1)) && !err.cycle.contains(&wire(2)),
+            "{}",
+            err
+        );
+        assert!(product().check_acyclic().is_ok());
+    }
+
+    #[test]
+    fn reordered_witness_puts_public_signals_after_one() {
+        let circuit = compile(
+            "private y\npublic x\nlet t = x * y + 1\nreturn t * x",
+            OptLevel::O0,
+        )
+        .unwrap();
+        let mut calculator = WitnessCalculator::new();
+        let inputs = InputFile::builder().public("x", 3).private("y", 5).build();
+        let output = calculator.calculate_witness(&circuit, inputs).unwrap();
+
+        let order = circuit.canonical_order();
+        let witness = calculator.ordered_witness(&order);
+        // ONE, then the public output and inputs, then the private inputs
+        assert_eq!(
+            witness[..4],
+            [
+                FieldElement::one(),
+                output,
+                FieldElement::new(3),
+                FieldElement::new(5)
+            ]
+        );
+        let r1cs = circuit.to_r1cs().reordered(&order);
+        assert!(r1cs.evaluate(&witness).iter().all(|eval| eval.satisfied));
+    }
+
+    #[test]
+    fn constant_only_circuit_folds_to_one_const() {
+        // (2 * 3 + 2) + 1 through a Mul, an Add and a fused Lc reading ONE
+        let circuit = Circuit {
+            public_inputs: vec![],
+            private_inputs: vec![],
+            gates: vec![
+                Gate::Const {
+                    output: wire(1),
+                    value: FieldElement::new(2),
+                },
+                Gate::Const {
+                    output: wire(2),
+                    value: FieldElement::new(3),
+                },
+                Gate::Mul {
+                    output: wire(3),
+                    left: wire(1),
+                    right: wire(2),
+                },
+                Gate::Add {
+                    output: wire(4),
+                    left: wire(3),
+                    right: wire(1),
+                },
+                Gate::Lc {
+                    output: wire(5),
+                    terms: vec![
+                        (wire(4), FieldElement::one()),
+                        (Wire::ONE, FieldElement::one()),
+                    ],
+                },
+            ],
+            output_wire: wire(5),
+        };
+        let folded = circuit.fold_constants();
+        assert!(
+            matches!(
+                &folded.gates[..],
+                [Gate::Const { output, value }] if *output == wire(5) && *value == FieldElement::new(9)
+            ),
+            "{:?}",
+            folded.gates
+        );
+    }
+
+    #[test]
+    fn x_times_x_is_a_square_gate() {
+        let circuit = compile_str("public x\nreturn x * x").unwrap();
+        assert!(
+            matches!(&circuit.gates[..], [Gate::Square { input, .. }] if *input == circuit.public_inputs[0].1),
+            "{:?}",
+            circuit.gates
+        );
+        // still the one x * x = out constraint
+        let constraint = &circuit.to_r1cs().constraints[0];
+        assert_eq!(constraint.a, constraint.b);
+    }
+
+    #[test]
+    fn minus_five_is_a_single_const_gate() {
+        for opt_level in [OptLevel::O0, OptLevel::O1] {
+            let circuit = compile("return -5", opt_level).unwrap();
+            assert!(
+                matches!(
+                    &circuit.gates[..],
+                    [Gate::Const { value, .. }] if value.as_u64() == crate::field::MODULUS - 5
+                ),
+                "{:?}",
+                circuit.gates
+            );
+        }
+    }
+
+    #[test]
+    fn square_merged_after_add_witnesses_the_squared_sum() {
+        let add = compile("public a\nprivate b\nreturn a + b", OptLevel::O0).unwrap();
+        let square = compile("public s\nreturn s * s", OptLevel::O0).unwrap();
+        let mapping = [(add.output_wire.clone(), square.public_inputs[0].1.clone())];
+        let merged = add.merge(square, &mapping).unwrap();
+        assert!(merged.validate().is_ok());
+        let names: Vec<String> = merged
+            .ordered_inputs()
+            .into_iter()
+            .map(|(_, name, _)| name)
+            .collect();
+        assert_eq!(names, ["a", "b"]);
+
+        let mut calculator = WitnessCalculator::new();
+        let inputs = InputFile::builder().public("a", 2).private("b", 3).build();
+        assert_eq!(
+            calculator.calculate_witness(&merged, inputs).unwrap(),
+            FieldElement::new(25)
+        );
+        let witness = calculator.witness_vector();
+        assert!(merged
+            .to_r1cs()
+            .evaluate(&witness)
+            .iter()
+            .all(|eval| eval.satisfied));
+    }
+
+    #[test]
+    fn sub_computes_a_wire_and_assert_only_constrains() {
+        let circuit = compile(
+            "public x\nprivate y\nassert x == y\nreturn x - y",
+            OptLevel::O0,
+        )
+        .unwrap();
+        let [assert, sub] = &circuit.gates[..] else {
+            panic!("expected an assert and a sub, got {:?}", circuit.gates);
+        };
+        assert!(matches!(assert, Gate::Assert { .. }));
+        assert!(assert.output_refs().is_empty());
+        assert!(matches!(sub, Gate::Sub { output, .. } if *output == circuit.output_wire));
+
+        let r1cs = circuit.to_r1cs();
+        // both are (x - y) * 1, but only the sub's row has an output in C
+        assert_eq!(r1cs.constraints[0].a, r1cs.constraints[1].a);
+        assert!(r1cs.constraints[0].c.is_empty());
+        assert_eq!(
+            r1cs.constraints[1].c,
+            [(circuit.output_wire.id, FieldElement::one())]
+        );
+
+        let mut calculator = WitnessCalculator::new();
+        let inputs = InputFile::builder().public("x", 5).private("y", 7).build();
+        assert!(calculator.calculate_witness(&circuit, inputs).is_err());
+        let inputs = InputFile::builder().public("x", 5).private("y", 5).build();
+        assert_eq!(
+            calculator.calculate_witness(&circuit, inputs).unwrap(),
+            FieldElement::zero()
+        );
+    }
+
+    #[test]
+    fn repeated_assert_constraint_is_deduplicated() {
+        let circuit = compile(
+            "public x\nprivate y\nassert x == y\nlet t = x * y\nassert x == y\nreturn t",
+            OptLevel::O0,
+        )
+        .unwrap();
+        let mut r1cs = circuit.to_r1cs();
+        assert_eq!(r1cs.num_constraints, 3);
+        assert_eq!(r1cs.dedup_constraints(), [2]);
+        assert_eq!(r1cs.num_constraints, 2);
+        assert_eq!(r1cs.constraints.len(), 2);
+        // the product keeps its place after the first assert
+        assert!(r1cs.constraints[0].c.is_empty());
+        assert_eq!(
+            r1cs.constraints[1].c,
+            [(circuit.output_wire.id, FieldElement::one())]
+        );
+    }
+
+    #[test]
+    fn sparsity_of_a_product_and_a_sum() {
+        // (x * y) * 1 = t, then (t + x) * 1 = out, over 5 variables
+        let circuit = compile(
+            "public x\nprivate y\nlet t = x * y\nreturn t + x",
+            OptLevel::O0,
+        )
+        .unwrap();
+        let info = circuit.to_r1cs().sparsity();
+        assert_eq!((info.rows, info.cols), (2, 5));
+        assert_eq!(info.a.nonzeros, [(0, 1), (1, 1), (1, 3)]);
+        assert_eq!(info.b.nonzeros, [(0, 2), (1, 0)]);
+        assert_eq!(info.c.nonzeros, [(0, 3), (1, 4)]);
+        assert_eq!(
+            (
+                info.a.nonzero_count,
+                info.b.nonzero_count,
+                info.c.nonzero_count
+            ),
+            (3, 2, 2)
+        );
+        assert_eq!(info.a.max_row_nonzeros, 2);
+        assert_eq!(info.a.density, 0.3);
+        assert_eq!(info.c.empty_rows, 0);
+    }
+
+    #[test]
+    fn output_wire_is_a_public_signal() {
+        let circuit = compile("public x\nprivate y\nreturn x * y + 1", OptLevel::O0).unwrap();
+        let r1cs = circuit.to_r1cs();
+        assert_eq!(r1cs.output_wire, circuit.output_wire.id);
+        assert_eq!(
+            r1cs.public_outputs,
+            [("out".to_string(), circuit.output_wire.id)]
+        );
+        assert!(!r1cs
+            .private_inputs
+            .iter()
+            .any(|(_, id)| *id == circuit.output_wire.id));
+    }
+
+    #[test]
+    fn interning_shares_one_const_gate_per_value() {
+        let source = "public x\nlet a = x * 3\nlet b = a * 3\nlet c = b + 3\nreturn c * 7";
+        let ssa = crate::ssa::SsaBuilder::new()
+            .convert(crate::compiler::parse_str(source).unwrap())
+            .unwrap();
+        let consts = |circuit: &Circuit| -> Vec<FieldElement> {
+            circuit
+                .gates
+                .iter()
+                .filter_map(|gate| match gate {
+                    Gate::Const { value, .. } => Some(*value),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(consts(&CircuitBuilder::from_ssa(ssa.clone())).len(), 4);
+        assert_eq!(
+            consts(&CircuitBuilder::from_ssa_interning_constants(ssa)),
+            [FieldElement::new(3), FieldElement::new(7)]
+        );
+    }
+
+    #[test]
+    fn circuit_with_gaps_compacts_to_a_contiguous_range() {
+        let source = "public x\nprivate y\nlet a = 2 * 3\nlet b = x + a + y\nreturn b * x";
+        let ssa = crate::ssa::SsaBuilder::new()
+            .convert(crate::compiler::parse_str(source).unwrap())
+            .unwrap();
+        // folding and fusing drop gates and leave their wire ids unused
+        let gapped = CircuitBuilder::from_ssa_interning_constants(ssa)
+            .fuse_additions()
+            .fold_constants();
+        let ids = |circuit: &Circuit| -> Vec<usize> {
+            let mut ids: Vec<usize> = circuit
+                .gates
+                .iter()
+                .flat_map(|gate| gate.output_wires().into_iter().chain(gate.input_wires()))
+                .chain(circuit.public_inputs.iter().map(|(_, wire)| wire.clone()))
+                .chain(circuit.private_inputs.iter().map(|(_, wire)| wire.clone()))
+                .chain([Wire::ONE, circuit.output_wire.clone()])
+                .map(|wire| wire.id)
+                .collect();
+            ids.sort();
+            ids.dedup();
+            ids
+        };
+        assert!(
+            ids(&gapped).len() < gapped.num_wires(),
+            "{:?}",
+            ids(&gapped)
+        );
+
+        let (compacted, mapping) = gapped.compact_wires();
+        assert_eq!(
+            ids(&compacted),
+            (0..compacted.num_wires()).collect::<Vec<_>>()
+        );
+        assert_eq!(compacted.num_wires(), ids(&gapped).len());
+        assert_eq!(mapping[&Wire::ONE], Wire::ONE);
+        assert_eq!(mapping[&gapped.output_wire], compacted.output_wire);
+
+        let output = |circuit: &Circuit| {
+            let inputs = InputFile::builder().public("x", 3).private("y", 5).build();
+            WitnessCalculator::new()
+                .calculate_witness(circuit, inputs)
+                .unwrap()
+        };
+        assert_eq!(output(&compacted), output(&gapped));
+        assert_eq!(output(&compacted), FieldElement::new(42));
+    }
+
+    #[test]
+    fn product_constraint_maps_to_the_return_statement() {
+        let source = "public a\nprivate b\nreturn a * b";
+        let program = crate::compiler::parse_str(source).unwrap();
+        let statement = program.statements.last().unwrap().span.clone();
+        let ssa = crate::ssa::SsaBuilder::new().convert(program).unwrap();
+        let (circuit, provenance) = CircuitBuilder::from_ssa_with_metadata(ssa);
+        let source_map = circuit.source_map(&circuit.gate_spans(&provenance));
+
+        assert_eq!(source_map.len(), circuit.to_r1cs().num_constraints);
+        let [entry] = &source_map[..] else {
+            panic!("expected one constraint, found {:?}", source_map);
+        };
+        assert!(matches!(circuit.gates[entry.gate], Gate::Mul { .. }));
+        let span = entry.span.as_ref().expect("the constraint has a span");
+        // the returned expression, inside the return statement on line 3
+        assert_eq!(&source[span.start..span.end], "a * b");
+        assert!(statement.start <= span.start && span.end <= statement.end);
+        assert_eq!(&source[statement.start..statement.end], "return a * b");
+    }
+
+    #[test]
+    fn output_wire_provenance_points_at_the_return_expression() {
+        let source = "public x\nprivate y\nlet z = x + y\nreturn z * x + 1";
+        let ssa = crate::ssa::SsaBuilder::new()
+            .convert(crate::compiler::parse_str(source).unwrap())
+            .unwrap();
+        let return_value = ssa.return_value.clone();
+        let (circuit, provenance) = CircuitBuilder::from_ssa_with_metadata(ssa);
+
+        let output = provenance
+            .iter()
+            .find(|entry| entry.wire == circuit.output_wire)
+            .expect("the output wire has provenance");
+        assert!(output.ssa_values.contains(&return_value));
+        let span = output.span.as_ref().expect("the output wire has a span");
+        assert_eq!(&source[span.start..span.end], "z * x + 1");
+
+        // the wire of the let points at its own expression
+        let z = provenance
+            .iter()
+            .find(|entry| entry.ssa_values.iter().any(|value| value.name == "z"))
+            .unwrap();
+        let span = z.span.as_ref().unwrap();
+        assert_eq!(&source[span.start..span.end], "x + y");
+    }
+
+    #[test]
+    fn thousand_gate_listing_elides_the_middle() {
+        let source = format!(
+            "public x\nlet acc = x\n{}return acc",
+            "acc += acc * x\n".repeat(500)
+        );
+        let circuit = compile(&source, OptLevel::O0).unwrap();
+        assert_eq!(circuit.gates.len(), 1000);
+
+        let lines = circuit.gate_lines(Some(5));
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[5], "... (990 more gates) ...");
+        let index = |line: &str| {
+            line.split(':')
+                .next()
+                .unwrap()
+                .trim()
+                .parse::<usize>()
+                .unwrap()
+        };
+        let shown: Vec<usize> = lines
+            .iter()
+            .filter(|line| !line.starts_with("..."))
+            .map(|line| index(line))
+            .collect();
+        assert_eq!(shown, [0, 1, 2, 3, 4, 995, 996, 997, 998, 999]);
+
+        assert_eq!(circuit.gate_lines(None).len(), 1000);
+        assert_eq!(circuit.gate_lines(Some(500)).len(), 1000);
+    }
+
+    #[test]
+    fn scheduling_never_raises_peak_liveness() {
+        // all four leaves before either product: five wires live at once
+        // in program order, x and three products once each product follows
+        // its leaves
+        let leaves_first = compile(
+            "public x\nlet a = x * 2\nlet b = x * 3\nlet c = x * 4\nlet d = x * 5\nlet ab = a * b\nlet cd = c * d\nreturn ab * cd",
+            OptLevel::O0,
+        )
+        .unwrap();
+        let scheduled = leaves_first.schedule_for_memory();
+        assert!(scheduled.peak_live_wires() < leaves_first.peak_live_wires());
+        for circuit in [&leaves_first, &scheduled] {
+            let inputs = InputFile::builder().public("x", 1).build();
+            let output = WitnessCalculator::new()
+                .calculate_witness(circuit, inputs)
+                .unwrap();
+            assert_eq!(output, FieldElement::new(120));
+        }
+
+        let mut sources = vec![];
+        for entry in std::fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "zk") {
+                sources.push(std::fs::read_to_string(&path).unwrap());
+            }
+        }
+        for source in &sources {
+            // includes are relative to the example, skip what doesn't compile from here
+            let Ok(circuit) = compile(source, OptLevel::O1) else {
+                continue;
+            };
+            let scheduled = circuit.schedule_for_memory();
+            assert!(
+                scheduled.peak_live_wires() <= circuit.peak_live_wires(),
+                "{}",
+                source
+            );
+            // the same gates in an order that still defines every wire before
+            // its use, so every wire gets the value it had
+            scheduled.topological_check().unwrap();
+            let sorted = |circuit: &Circuit| {
+                let mut gates: Vec<String> =
+                    circuit.gates.iter().map(|gate| gate.to_string()).collect();
+                gates.sort();
+                gates
+            };
+            assert_eq!(sorted(&scheduled), sorted(&circuit), "{}", source);
+        }
+    }
+
+    #[test]
+    fn intermediate_wire_lives_until_its_last_reader() {
+        // gate 0: t = x * y, gate 1: u = t + x, gate 2: v = u * t, gate 3: out = v + y
+        let circuit = compile(
+            "public x\nprivate y\nlet t = x * y\nlet u = t + x\nlet v = u * t\nreturn v + y",
+            OptLevel::O0,
+        )
+        .unwrap();
+        let Gate::Mul { output: t, .. } = &circuit.gates[0] else {
+            panic!("expected t = x * y first, got {}", circuit.gates[0]);
+        };
+        let liveness = circuit.liveness();
+        let of = |wire: &Wire| liveness.iter().find(|entry| entry.wire == *wire).unwrap();
+        assert_eq!((of(t).def, of(t).last_use), (Some(0), Some(2)));
+        // inputs are defined before any gate, the output is read after all of them
+        assert_eq!(of(&wire(2)).def, None);
+        assert_eq!(of(&wire(2)).last_use, Some(3));
+        assert_eq!(of(&circuit.output_wire).last_use, Some(4));
+    }
+
+    #[test]
+    fn constraint_estimate_matches_the_r1cs() {
+        for source in [
+            "public x\nprivate y\nreturn x * y",
+            "public a\npublic b\npublic c\nreturn a + b + c * 2",
+            "public x: u8\nprivate y\nassert x != y\nreturn is_zero(x - y) + inv(y)",
+            "public a\npublic b\nreturn (a and b) or not a",
+            "return 5",
+        ] {
+            for opt_level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+                let circuit = compile(source, opt_level).unwrap();
+                assert_eq!(
+                    circuit.constraint_count_estimate(),
+                    circuit.to_r1cs().num_constraints,
+                    "{} at {:?}",
+                    source,
+                    opt_level
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn wire_accessors_cover_every_gate_variant() {
+        let ids =
+            |wires: Vec<Wire>| -> Vec<usize> { wires.into_iter().map(|wire| wire.id).collect() };
+        let one = FieldElement::one();
+        // (gate, output_wire, output_wires, input_wires)
+        type Case = (Gate, Option<usize>, Vec<usize>, Vec<usize>);
+        let cases: Vec<Case> = vec![
+            (
+                Gate::Const {
+                    output: wire(9),
+                    value: one,
+                },
+                Some(9),
+                vec![9],
+                vec![],
+            ),
+            (
+                Gate::Add {
+                    output: wire(9),
+                    left: wire(1),
+                    right: wire(2),
+                },
+                Some(9),
+                vec![9],
+                vec![1, 2],
+            ),
+            (
+                Gate::Sub {
+                    output: wire(9),
+                    left: wire(1),
+                    right: wire(2),
+                },
+                Some(9),
+                vec![9],
+                vec![1, 2],
+            ),
+            (
+                Gate::Mul {
+                    output: wire(9),
+                    left: wire(1),
+                    right: wire(2),
+                },
+                Some(9),
+                vec![9],
+                vec![1, 2],
+            ),
+            (
+                Gate::Square {
+                    output: wire(9),
+                    input: wire(1),
+                },
+                Some(9),
+                vec![9],
+                vec![1],
+            ),
+            (
+                Gate::Assert {
+                    left: wire(1),
+                    right: wire(2),
+                },
+                None,
+                vec![],
+                vec![1, 2],
+            ),
+            (
+                Gate::Inv {
+                    output: wire(9),
+                    input: wire(1),
+                },
+                Some(9),
+                vec![9],
+                vec![1],
+            ),
+            (
+                Gate::Lc {
+                    output: wire(9),
+                    terms: vec![(wire(1), one), (wire(3), -one)],
+                },
+                Some(9),
+                vec![9],
+                vec![1, 3],
+            ),
+            (
+                Gate::RangeCheck {
+                    input: wire(1),
+                    bits: vec![wire(7), wire(8)],
+                },
+                None,
+                vec![7, 8],
+                vec![1],
+            ),
+            (
+                Gate::IsZero {
+                    output: wire(9),
+                    input: wire(1),
+                    inverse: wire(8),
+                },
+                Some(9),
+                vec![9, 8],
+                vec![1],
+            ),
+        ];
+        assert_eq!(cases.len(), 10, "one case per Gate variant");
+        for (gate, output, outputs, inputs) in cases {
+            assert_eq!(gate.output_wire().map(|wire| wire.id), output, "{}", gate);
+            assert_eq!(ids(gate.output_wires()), outputs, "{}", gate);
+            assert_eq!(ids(gate.input_wires()), inputs, "{}", gate);
+        }
+    }
+
+    #[test]
+    fn times_a_constant_is_one_linear_constraint() {
+        let circuit = compile("public x\nreturn x * 3", OptLevel::O0).unwrap();
+        // the Const gate is still there, it just costs no constraint
+        assert_eq!(circuit.gates.len(), 2);
+        let r1cs = circuit.to_r1cs();
+        assert_eq!(r1cs.constraints.len(), 1);
+        let constraint = &r1cs.constraints[0];
+        // x * (3 * ONE) = out
+        assert_eq!(constraint.a, [(1, FieldElement::one())]);
+        assert_eq!(constraint.b, [(Wire::ONE.id, FieldElement::new(3))]);
+        assert_eq!(
+            constraint.c,
+            [(circuit.output_wire.id, FieldElement::one())]
+        );
+    }
 }
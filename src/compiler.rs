@@ -0,0 +1,58 @@
+use crate::ast::Program;
+use crate::circuit::{Circuit, CircuitBuilder};
+use crate::const_eval::check_static_asserts;
+use crate::error::CompilerError;
+use crate::lexer::Lexer;
+use crate::optimizer::{self, OptLevel};
+use crate::parser::Parser;
+use crate::ssa::SsaBuilder;
+use std::collections::HashMap;
+
+// Lexes and parses a source string; like every stage here it reports bad
+// input as an error, never a panic, so it can be driven by a fuzzer.
+pub fn parse_str(source: &str) -> Result<Program, CompilerError> {
+    let tokens = Lexer::new(source).tokenize()?;
+    Ok(Parser::new(tokens).parse()?)
+}
+
+// compile at the default optimization level
+pub fn compile_str(source: &str) -> Result<Circuit, CompilerError> {
+    compile(source, OptLevel::O1)
+}
+
+// The whole pipeline from source to a checked circuit, the same passes the
+// command line runs. The stages in between are logged at debug level and the
+// result at info, for the caller's logger to show or drop.
+pub fn compile(source: &str, opt_level: OptLevel) -> Result<Circuit, CompilerError> {
+    let program = parse_str(source)?;
+    log::debug!("AST:\n{:#?}", program);
+    check_static_asserts(&program, &HashMap::new())?;
+    let ssa_program = SsaBuilder::new().convert(program)?;
+    log::debug!("SSA IR:\n{}", ssa_program.to_aligned_string());
+
+    let optimized_ssa = optimizer::optimize(ssa_program, opt_level);
+    optimizer::check_constant_asserts(&optimized_ssa)?;
+    log::debug!(
+        "Optimized SSA ({:?}):\n{}",
+        opt_level,
+        optimized_ssa.to_aligned_string()
+    );
+    let circuit = if opt_level == OptLevel::O0 {
+        CircuitBuilder::from_ssa(optimized_ssa)
+    } else {
+        CircuitBuilder::from_ssa_interning_constants(optimized_ssa)
+            .fuse_additions()
+            .fold_constants()
+            .compact_wires()
+            .0
+    };
+    log::debug!("Circuit:\n{}", circuit.to_aligned_string());
+
+    circuit.topological_check()?;
+    log::info!(
+        "Compiled to {} gates, {} constraints",
+        circuit.gates.len(),
+        circuit.constraint_count_estimate()
+    );
+    Ok(circuit)
+}
@@ -0,0 +1,180 @@
+use crate::ast::{add_assign_sum, Expr, Program, Span, Stmt};
+use crate::field::FieldElement;
+use std::collections::HashMap;
+
+// Evaluates an expression built only from literals. This is integer
+// arithmetic in i32, not field arithmetic: the callers need exponents, array
+// indexes and static_assert orderings, which field elements don't have.
+// Returns None if the expression reads a variable, calls a function, takes an
+// inverse, leaves i32 range (a literal or any intermediate value), or gives
+// and/or/not an operand other than 0 or 1.
+pub fn eval_const(expr: &Expr) -> Option<i32> {
+    eval_const_in(expr, &HashMap::new())
+}
+
+// eval_const, where variables bound to Some value in `env` are constants too
+pub(crate) fn eval_const_in(expr: &Expr, env: &HashMap<String, Option<i32>>) -> Option<i32> {
+    let eval = |expr: &Expr| eval_const_in(expr, env);
+    match expr {
+        Expr::Literal(n) => n.to_i32(),
+        Expr::Var(name) => env.get(name).copied().flatten(),
+        Expr::IsZero(inner) => Some((eval(inner)? == 0) as i32),
+        // inverses aren't integers, they only exist in the field
+        Expr::Call(_, _) | Expr::Inv(_) => None,
+        Expr::Add(left, right) => eval(left)?.checked_add(eval(right)?),
+        Expr::Sub(left, right) => eval(left)?.checked_sub(eval(right)?),
+        Expr::Neg(inner) => eval(inner)?.checked_neg(),
+        Expr::Mul(left, right) => eval(left)?.checked_mul(eval(right)?),
+        Expr::Pow(base, exponent) => eval(base)?.checked_pow(*exponent),
+        Expr::Ne(left, right) => Some((eval(left)? != eval(right)?) as i32),
+        // operands other than 0 and 1 fail the circuit's boolean check
+        Expr::And(left, right) => Some(eval_boolean(left, env)? & eval_boolean(right, env)?),
+        Expr::Or(left, right) => Some(eval_boolean(left, env)? | eval_boolean(right, env)?),
+        Expr::Not(inner) => Some(1 - eval_boolean(inner, env)?),
+        Expr::Let(name, value, body) => {
+            let mut inner = env.clone();
+            inner.insert(name.clone(), eval(value));
+            eval_const_in(body, &inner)
+        }
+    }
+}
+
+// an operand of and/or/not, None unless it is 0 or 1
+fn eval_boolean(expr: &Expr, env: &HashMap<String, Option<i32>>) -> Option<i32> {
+    eval_const_in(expr, env).filter(|value| *value == 0 || *value == 1)
+}
+
+// Checks every static_assert of the program. Its operands may read consts,
+// lets computed from constants, and public inputs pinned to a value.
+pub fn check_static_asserts(
+    program: &Program,
+    pins: &HashMap<String, FieldElement>,
+) -> Result<(), StaticAssertError> {
+    // name -> its value if known at compile time; None shadows an earlier constant.
+    // Operands are compared as i32 (see eval_const), so < and > mean what they say.
+    let mut env: HashMap<String, Option<i32>> = HashMap::new();
+    for stmt in &program.statements {
+        match &stmt.node {
+            Stmt::PublicInput { name, .. } => {
+                env.insert(
+                    name.clone(),
+                    pins.get(name).and_then(|value| value.to_i32()),
+                );
+            }
+            Stmt::PrivateInput { name, .. } => {
+                env.insert(name.clone(), None);
+            }
+            Stmt::ConstDecl { name, value } => {
+                env.insert(name.clone(), value.to_i32());
+            }
+            Stmt::Let { name, expr } => {
+                env.insert(name.clone(), eval_const_in(expr, &env));
+            }
+            Stmt::AddAssign { name, expr } => {
+                let sum = add_assign_sum(name, expr, stmt.span.clone());
+                env.insert(name.clone(), eval_const_in(&sum, &env));
+            }
+            Stmt::StaticAssert { left, op, right } => {
+                let value = |expr| {
+                    eval_const_in(expr, &env).ok_or_else(|| StaticAssertError {
+                        message: format!(
+                            "static_assert operand {} is not a compile-time constant",
+                            expr
+                        ),
+                        span: stmt.span.clone(),
+                    })
+                };
+                let (left_val, right_val) = (value(left)?, value(right)?);
+                if !op.holds(left_val, right_val) {
+                    return Err(StaticAssertError {
+                        message: format!(
+                            "static_assert {} {} {} failed: {} {} {} is false",
+                            left, op, right, left_val, op, right_val
+                        ),
+                        span: stmt.span.clone(),
+                    });
+                }
+            }
+            Stmt::ConstArray { .. }
+            | Stmt::Return(_)
+            | Stmt::Assert { .. }
+            | Stmt::AssertEq(_)
+            | Stmt::FnDecl { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct StaticAssertError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl std::fmt::Display for StaticAssertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at {}", self.message, self.span)
+    }
+}
+
+impl std::error::Error for StaticAssertError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parse_str;
+
+    // the expression of `return <source>`
+    fn expr(source: &str) -> Expr {
+        let program = parse_str(&format!("return {}", source)).unwrap();
+        match &program.statements.last().unwrap().node {
+            Stmt::Return(expr) => expr.node.clone(),
+            other => panic!("expected return, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nested_constant_expressions_evaluate() {
+        assert_eq!(eval_const(&expr("(2 + 3) * (7 - 4)")), Some(15));
+        assert_eq!(eval_const(&expr("((1 + 1) ** 3 - 2) * -(4 - 6)")), Some(12));
+        assert_eq!(
+            eval_const(&expr("let a = 3 in let b = a * a in b - a")),
+            Some(6)
+        );
+        assert_eq!(eval_const(&expr("not (2 != 2) and (1 or 0)")), Some(1));
+        assert_eq!(eval_const(&expr("is_zero(5 - 5)")), Some(1));
+    }
+
+    #[test]
+    fn variables_and_inverses_are_not_constant() {
+        let program = parse_str("public x\nreturn (1 + 2) * (x - 1)").unwrap();
+        match &program.statements.last().unwrap().node {
+            Stmt::Return(expr) => assert_eq!(eval_const(&expr.node), None),
+            other => panic!("expected return, found {:?}", other),
+        }
+        assert_eq!(eval_const(&expr("inv(2 + 2)")), None);
+    }
+
+    #[test]
+    fn leaving_i32_range_is_not_constant() {
+        assert_eq!(eval_const(&expr("(65536 * 65536) - 1")), None);
+        assert_eq!(eval_const(&expr("2 ** 31")), None);
+        assert_eq!(eval_const(&expr("2 ** 30 + (2 ** 30 - 1)")), Some(i32::MAX));
+    }
+
+    #[test]
+    fn false_static_assert_fails_at_compile_time() {
+        let program = parse_str("static_assert 5 < 3\nreturn 1").unwrap();
+        let err = check_static_asserts(&program, &HashMap::new()).unwrap_err();
+        assert_eq!(err.message, "static_assert 5 < 3 failed: 5 < 3 is false");
+        assert_eq!((err.span.start, err.span.end), (0, 19));
+
+        // a pinned public input is a constant too
+        let program = parse_str("public n\nstatic_assert n < 100\nreturn n").unwrap();
+        let pins = HashMap::from([("n".to_string(), FieldElement::new(150))]);
+        let err = check_static_asserts(&program, &pins).unwrap_err();
+        assert!(err.message.contains("150 < 100 is false"), "{}", err);
+        let pins = HashMap::from([("n".to_string(), FieldElement::new(50))]);
+        assert!(check_static_asserts(&program, &pins).is_ok());
+    }
+}
@@ -0,0 +1,140 @@
+// Randomized check that two circuits compute the same function, for testing
+// optimizer changes: both are run on the same input assignments and have to
+// agree on every one of them. Passing is evidence, not a proof.
+
+use crate::circuit::Circuit;
+use crate::field::FieldElement;
+use crate::witness::{InputFile, WitnessCalculator};
+use std::collections::{BTreeMap, HashMap};
+use std::mem::discriminant;
+
+// the seed circuits_equivalent draws its inputs with
+pub const DEFAULT_SEED: u64 = 0x5eed;
+
+pub fn circuits_equivalent(a: &Circuit, b: &Circuit, samples: usize) -> bool {
+    circuits_equivalent_with_seed(a, b, samples, DEFAULT_SEED)
+}
+
+// Runs both circuits on `samples` random assignments of their inputs. They are
+// equivalent if each assignment either satisfies both, with the same output,
+// or fails in both with the same kind of WitnessError, and at least one
+// assignment satisfies them: two circuits that reject everything drawn say
+// nothing about the function they compute. An input only one of them declares
+// (one pruned by --prune-inputs, say) is given a value all the same; one that
+// is public in a circuit and private in the other makes them differ.
+pub fn circuits_equivalent_with_seed(a: &Circuit, b: &Circuit, samples: usize, seed: u64) -> bool {
+    // name -> whether it is public; BTreeMap so the values drawn per name
+    // don't depend on hash order
+    let mut inputs: BTreeMap<&str, bool> = BTreeMap::new();
+    for circuit in [a, b] {
+        let declared = circuit
+            .public_inputs
+            .iter()
+            .map(|(name, _)| (name, true))
+            .chain(circuit.private_inputs.iter().map(|(name, _)| (name, false)));
+        for (name, public) in declared {
+            if *inputs.entry(name).or_insert(public) != public {
+                return false;
+            }
+        }
+    }
+
+    let mut rng = SplitMix64(seed);
+    let mut calculator = WitnessCalculator::new();
+    let mut satisfied = false;
+    for _ in 0..samples {
+        let mut public = HashMap::new();
+        let mut private = HashMap::new();
+        for (name, is_public) in &inputs {
+            let values = if *is_public {
+                &mut public
+            } else {
+                &mut private
+            };
+            values.insert(name.to_string(), rng.next_input());
+        }
+        let row = InputFile::from_public_and_private(public, private);
+
+        let result_a = calculator
+            .calculate_witness(a, row.clone())
+            .map_err(|e| discriminant(&e));
+        let result_b = calculator
+            .calculate_witness(b, row)
+            .map_err(|e| discriminant(&e));
+        if result_a != result_b {
+            return false;
+        }
+        satisfied |= result_a.is_ok();
+    }
+    satisfied
+}
+
+// https://prng.di.unimi.it/splitmix64.c, small and good enough for test inputs
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform field elements would fail every range check and equality
+    // assert, so small values, zero and -1 are drawn as often as large ones
+    fn next_input(&mut self) -> FieldElement {
+        let value = self.next_u64();
+        match value % 4 {
+            0 => FieldElement::new(value >> 2),
+            1 => FieldElement::new((value >> 2) % 256),
+            2 => FieldElement::new((value >> 2) % 4),
+            _ => -FieldElement::new((value >> 2) % 2),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::optimizer::OptLevel;
+
+    fn circuit(source: &str, opt_level: OptLevel) -> Circuit {
+        compile(source, opt_level).unwrap()
+    }
+
+    #[test]
+    fn optimized_and_unoptimized_circuits_are_equivalent() {
+        let source = "public x\nprivate y\nlet a = x * 3 + 0\nlet b = a * y\nassert b == b\nreturn b + inv(y)";
+        let unoptimized = circuit(source, OptLevel::O0);
+        let optimized = circuit(source, OptLevel::O2);
+        assert!(optimized.gates.len() < unoptimized.gates.len());
+        assert!(circuits_equivalent(&unoptimized, &optimized, 200));
+    }
+
+    #[test]
+    fn different_outputs_are_not_equivalent() {
+        let a = circuit("public x\nreturn x * x", OptLevel::O0);
+        let b = circuit("public x\nreturn x + x", OptLevel::O0);
+        assert!(!circuits_equivalent(&a, &b, 200));
+    }
+
+    #[test]
+    fn failing_for_different_reasons_is_not_equivalent() {
+        // both fail exactly when x is 0: one inverting it, one on the assert
+        let a = circuit("public x\nreturn inv(x)", OptLevel::O0);
+        let b = circuit(
+            "public x\nassert is_zero(x) == 0\nreturn inv(x)",
+            OptLevel::O0,
+        );
+        assert!(circuits_equivalent(&a, &a, 200));
+        assert!(!circuits_equivalent(&a, &b, 200));
+    }
+
+    #[test]
+    fn circuits_that_never_pass_are_not_equivalent() {
+        let a = circuit("public x\nreturn inv(x - x)", OptLevel::O0);
+        assert!(!circuits_equivalent(&a, &a, 200));
+    }
+}
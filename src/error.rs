@@ -0,0 +1,179 @@
+use crate::const_eval::StaticAssertError;
+use crate::lexer::LexError;
+use crate::optimizer::ConstantAssertError;
+use crate::parser::ParseError;
+use crate::ssa::SsaError;
+use crate::witness::WitnessError;
+
+// The error of any compiler stage, so a whole pipeline can be driven with `?`.
+// Circuit holds the circuit-level errors (TopologyError, CycleError,
+// MergeError, FormatVersionError) and can be downcast to them.
+#[derive(Debug)]
+pub enum CompilerError {
+    Lex(LexError),
+    Parse(ParseError),
+    StaticAssert(StaticAssertError),
+    Ssa(SsaError),
+    ConstantAssert(ConstantAssertError),
+    Circuit(Box<dyn std::error::Error>),
+    Witness(WitnessError),
+}
+
+impl std::fmt::Display for CompilerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CompilerError::Lex(err) => write!(f, "Lex error: {}", err),
+            CompilerError::Parse(err) => write!(f, "Parse error: {}", err),
+            CompilerError::StaticAssert(err) => write!(f, "Static assert error: {}", err),
+            CompilerError::Ssa(err) => write!(f, "SSA error: {}", err),
+            CompilerError::ConstantAssert(err) => write!(f, "Assert error: {}", err),
+            CompilerError::Circuit(err) => write!(f, "Circuit error: {}", err),
+            CompilerError::Witness(err) => write!(f, "Witness error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CompilerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompilerError::Lex(err) => Some(err),
+            CompilerError::Parse(err) => Some(err),
+            CompilerError::StaticAssert(err) => Some(err),
+            CompilerError::Ssa(err) => Some(err),
+            CompilerError::ConstantAssert(err) => Some(err),
+            CompilerError::Circuit(err) => Some(err.as_ref()),
+            CompilerError::Witness(err) => Some(err),
+        }
+    }
+}
+
+impl From<LexError> for CompilerError {
+    fn from(err: LexError) -> Self {
+        CompilerError::Lex(err)
+    }
+}
+
+impl From<ParseError> for CompilerError {
+    fn from(err: ParseError) -> Self {
+        CompilerError::Parse(err)
+    }
+}
+
+impl From<StaticAssertError> for CompilerError {
+    fn from(err: StaticAssertError) -> Self {
+        CompilerError::StaticAssert(err)
+    }
+}
+
+impl From<SsaError> for CompilerError {
+    fn from(err: SsaError) -> Self {
+        CompilerError::Ssa(err)
+    }
+}
+
+impl From<ConstantAssertError> for CompilerError {
+    fn from(err: ConstantAssertError) -> Self {
+        CompilerError::ConstantAssert(err)
+    }
+}
+
+impl From<WitnessError> for CompilerError {
+    fn from(err: WitnessError) -> Self {
+        CompilerError::Witness(err)
+    }
+}
+
+impl From<crate::circuit::TopologyError> for CompilerError {
+    fn from(err: crate::circuit::TopologyError) -> Self {
+        CompilerError::Circuit(Box::new(err))
+    }
+}
+
+impl From<crate::circuit::CycleError> for CompilerError {
+    fn from(err: crate::circuit::CycleError) -> Self {
+        CompilerError::Circuit(Box::new(err))
+    }
+}
+
+impl From<crate::circuit::MergeError> for CompilerError {
+    fn from(err: crate::circuit::MergeError) -> Self {
+        CompilerError::Circuit(Box::new(err))
+    }
+}
+
+impl From<crate::circuit::FormatVersionError> for CompilerError {
+    fn from(err: crate::circuit::FormatVersionError) -> Self {
+        CompilerError::Circuit(Box::new(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{TopologyError, Wire};
+    use crate::compiler::compile_str;
+    use crate::witness::{InputFile, WitnessCalculator};
+
+    #[test]
+    fn each_stage_maps_to_its_variant() {
+        assert!(matches!(
+            compile_str("return 1 $"),
+            Err(CompilerError::Lex(_))
+        ));
+        assert!(matches!(
+            compile_str("return"),
+            Err(CompilerError::Parse(_))
+        ));
+        assert!(matches!(
+            compile_str("static_assert 1 > 2\nreturn 1"),
+            Err(CompilerError::StaticAssert(_))
+        ));
+        assert!(matches!(
+            compile_str("public x"),
+            Err(CompilerError::Ssa(SsaError::NoReturn))
+        ));
+        assert!(matches!(
+            compile_str("public x\nassert 1 == 2\nreturn x"),
+            Err(CompilerError::ConstantAssert(_))
+        ));
+
+        let topology = TopologyError {
+            gate_index: 0,
+            wire: Wire { id: 7 },
+        };
+        let err = CompilerError::from(topology);
+        assert!(matches!(&err, CompilerError::Circuit(inner) if inner.is::<TopologyError>()));
+
+        let circuit = compile_str("public x\nreturn x * x").unwrap();
+        let witness = WitnessCalculator::new().calculate_witness(&circuit, InputFile::default());
+        let err = CompilerError::from(witness.unwrap_err());
+        assert!(matches!(err, CompilerError::Witness(_)));
+        assert!(err.to_string().starts_with("Witness error: "));
+    }
+
+    #[test]
+    fn empty_program_is_no_return_not_a_panic() {
+        for source in ["", "\n\n", "public x\nprivate y"] {
+            let result = std::panic::catch_unwind(|| compile_str(source));
+            assert!(
+                matches!(result, Ok(Err(CompilerError::Ssa(SsaError::NoReturn)))),
+                "{:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn return_only_program_is_a_constant_circuit() {
+        let circuit = compile_str("return 6 * 7").unwrap();
+        assert!(circuit.public_inputs.is_empty() && circuit.private_inputs.is_empty());
+        assert!(matches!(
+            &circuit.gates[..],
+            [crate::circuit::Gate::Const { .. }]
+        ));
+        let output = WitnessCalculator::new()
+            .calculate_witness(&circuit, InputFile::default())
+            .unwrap();
+        assert_eq!(output, crate::field::FieldElement::new(42));
+    }
+}
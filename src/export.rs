@@ -0,0 +1,90 @@
+// Helpers for the exporters that write Rust source for other proof systems.
+// That source is generic over the proof system's field, so a coefficient only
+// carries over if it means the same number in every field: a small signed
+// integer does, anything else is a Goldilocks value and is rejected rather
+// than silently meaning something else over BN254 or BLS12-381. Witness values
+// still wrap at the target field's modulus, so the exported circuit proves the
+// same relation only while values stay small.
+
+use crate::circuit::{LinearCombination, R1csSystem};
+use crate::field::FieldElement;
+
+#[derive(Debug)]
+pub struct ExportError {
+    pub constraint: usize,
+    pub coefficient: FieldElement,
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Constraint {} has coefficient {}, which is only meaningful over Goldilocks; \
+             exported circuits are generic over their field",
+            self.constraint, self.coefficient
+        )
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+// the a, b and c terms of each constraint as " + (F::from(n), wN)", where F
+// is the generated code's field type parameter
+pub(crate) fn constraint_terms(
+    r1cs: &R1csSystem,
+    field: &str,
+) -> Result<Vec<[String; 3]>, ExportError> {
+    r1cs.constraints
+        .iter()
+        .enumerate()
+        .map(|(index, constraint)| {
+            let terms = |lc: &LinearCombination| -> Result<String, ExportError> {
+                lc.iter()
+                    .map(|(wire, coeff)| {
+                        let scalar = scalar(*coeff, field).ok_or(ExportError {
+                            constraint: index,
+                            coefficient: *coeff,
+                        })?;
+                        Ok(format!(" + ({}, w{})", scalar, wire))
+                    })
+                    .collect()
+            };
+            Ok([
+                terms(&constraint.a)?,
+                terms(&constraint.b)?,
+                terms(&constraint.c)?,
+            ])
+        })
+        .collect()
+}
+
+fn scalar(value: FieldElement, field: &str) -> Option<String> {
+    match value.to_i32()? {
+        n if n < 0 => Some(format!("-{}::from({}u64)", field, n.unsigned_abs())),
+        n => Some(format!("{}::from({}u64)", field, n)),
+    }
+}
+
+// reads constraint_terms' output back into (coefficient, wire) pairs, for the
+// exporters' tests
+#[cfg(test)]
+pub(crate) fn read_terms(terms: &str, field: &str) -> LinearCombination {
+    terms
+        .split(" + (")
+        .skip(1)
+        .map(|term| {
+            let (scalar, wire) = term.trim_end_matches(')').split_once(", w").unwrap();
+            let (negative, digits) = match scalar.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, scalar),
+            };
+            let digits = digits
+                .strip_prefix(&format!("{}::from(", field))
+                .unwrap()
+                .trim_end_matches("u64)");
+            let value = FieldElement::new(digits.parse().unwrap());
+            let coeff = if negative { -value } else { value };
+            (wire.parse().unwrap(), coeff)
+        })
+        .collect()
+}
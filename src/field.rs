@@ -0,0 +1,346 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+// Goldilocks prime: 2^64 - 2^32 + 1
+pub const MODULUS: u64 = 0xffff_ffff_0000_0001;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FieldElement(u64);
+
+impl FieldElement {
+    // size of to_le_bytes, every element takes the same width
+    pub const BYTES: usize = 8;
+
+    pub fn new(value: u64) -> Self {
+        Self(value % MODULUS)
+    }
+
+    // any signed integer into [0, p), -k being p - k; the one place a signed
+    // value becomes a field element, for literals and input files alike
+    pub fn normalize(value: i64) -> Self {
+        let magnitude = Self::new(value.unsigned_abs());
+        if value < 0 {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    pub fn zero() -> Self {
+        Self(0)
+    }
+
+    pub fn one() -> Self {
+        Self(1)
+    }
+
+    // canonical representative in [0, p)
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    pub fn to_le_bytes(&self) -> [u8; Self::BYTES] {
+        self.0.to_le_bytes()
+    }
+
+    // None unless the bytes hold a canonical value, below p
+    pub fn from_le_bytes(bytes: [u8; Self::BYTES]) -> Option<Self> {
+        let value = u64::from_le_bytes(bytes);
+        (value < MODULUS).then_some(Self(value))
+    }
+
+    // the i32 this element represents, reading p - k as -k; None if it is out of i32 range
+    pub fn to_i32(self) -> Option<i32> {
+        if self.0 <= i32::MAX as u64 {
+            Some(self.0 as i32)
+        } else if MODULUS - self.0 <= 1 << 31 {
+            Some((-((MODULUS - self.0) as i64)) as i32)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    // extended Euclid over (p, self); zero has no inverse
+    pub fn inverse(&self) -> Option<Self> {
+        if self.is_zero() {
+            return None;
+        }
+
+        let (mut t, mut new_t) = (0i128, 1i128);
+        let (mut r, mut new_r) = (MODULUS as i128, self.0 as i128);
+        while new_r != 0 {
+            let quotient = r / new_r;
+            (t, new_t) = (new_t, t - quotient * new_t);
+            (r, new_r) = (new_r, r - quotient * new_r);
+        }
+
+        if t < 0 {
+            t += MODULUS as i128;
+        }
+        Some(Self(t as u64))
+    }
+
+    // square-and-multiply, O(log exp) multiplications; x^0 = 1, also for x = 0
+    pub fn pow(&self, mut exp: u64) -> Self {
+        let mut base = *self;
+        let mut result = Self::one();
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            exp >>= 1;
+        }
+        result
+    }
+}
+
+impl From<i32> for FieldElement {
+    fn from(value: i32) -> Self {
+        FieldElement::normalize(value as i64)
+    }
+}
+
+impl Add for FieldElement {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let sum = self.0 as u128 + other.0 as u128;
+        Self((sum % MODULUS as u128) as u64)
+    }
+}
+
+impl Sub for FieldElement {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self + (-other)
+    }
+}
+
+impl Mul for FieldElement {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let product = self.0 as u128 * other.0 as u128;
+        Self((product % MODULUS as u128) as u64)
+    }
+}
+
+impl Neg for FieldElement {
+    type Output = Self;
+
+    // -x = p - x, with -0 = 0
+    fn neg(self) -> Self {
+        if self.0 == 0 {
+            self
+        } else {
+            Self(MODULUS - self.0)
+        }
+    }
+}
+
+impl std::fmt::Display for FieldElement {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseFieldElementError {
+    pub input: String,
+}
+
+impl std::fmt::Display for ParseFieldElementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Invalid field element: {:?}", self.input)
+    }
+}
+
+impl std::error::Error for ParseFieldElementError {}
+
+// parses a decimal digit string of any length, reducing modulo p
+impl FromStr for FieldElement {
+    type Err = ParseFieldElementError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseFieldElementError {
+                input: s.to_string(),
+            });
+        }
+
+        let ten = FieldElement::new(10);
+        Ok(s.bytes().fold(FieldElement::zero(), |acc, digit| {
+            acc * ten + FieldElement::new((digit - b'0') as u64)
+        }))
+    }
+}
+
+// field elements can exceed 2^53, so JSON carries them as decimal strings
+impl Serialize for FieldElement {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// accepts decimal strings as well as plain integers, so hand-written input
+// files can use either form
+impl<'de> Deserialize<'de> for FieldElement {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(FieldElementVisitor)
+    }
+}
+
+struct FieldElementVisitor;
+
+impl serde::de::Visitor<'_> for FieldElementVisitor {
+    type Value = FieldElement;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "an integer or a decimal string")
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<FieldElement, E> {
+        Ok(FieldElement::new(value))
+    }
+
+    fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<FieldElement, E> {
+        Ok(FieldElement::normalize(value))
+    }
+
+    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<FieldElement, E> {
+        value.parse().map_err(E::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Gate;
+    use crate::compiler::compile_str;
+    use crate::witness::{InputFile, WitnessCalculator};
+
+    #[test]
+    fn minus_one_is_p_minus_one() {
+        assert_eq!((-FieldElement::one()).as_u64(), MODULUS - 1);
+        assert_eq!(FieldElement::from(-1).as_u64(), MODULUS - 1);
+    }
+
+    #[test]
+    fn minus_one_normalizes_to_p_minus_one_everywhere() {
+        let p_minus_one = FieldElement::new(MODULUS - 1);
+        assert_eq!(FieldElement::normalize(-1), p_minus_one);
+        assert_eq!(FieldElement::from(-1), p_minus_one);
+        assert_eq!(
+            FieldElement::normalize(i64::MIN),
+            -FieldElement::new(1 << 63)
+        );
+
+        // a -1 literal, in the gates and as an R1CS coefficient
+        let circuit = compile_str("public x\nreturn x * -1").unwrap();
+        assert!(circuit
+            .gates
+            .iter()
+            .any(|gate| matches!(gate, Gate::Const { value, .. } if *value == p_minus_one)));
+        let r1cs = circuit.to_r1cs();
+        assert!(r1cs
+            .constraints
+            .iter()
+            .flat_map(|constraint| constraint
+                .a
+                .iter()
+                .chain(&constraint.b)
+                .chain(&constraint.c))
+            .any(|(_, coeff)| *coeff == p_minus_one));
+
+        // -1 in JSON and TOML input files, and in the witness it gives
+        let json: InputFile = serde_json::from_str(r#"{"public": {"x": -1}}"#).unwrap();
+        let toml: InputFile = toml::from_str("[public]\nx = -1").unwrap();
+        for inputs in [json, toml] {
+            assert_eq!(inputs.public.as_ref().unwrap()["x"], p_minus_one);
+            let identity = compile_str("public x\nreturn x").unwrap();
+            let output = WitnessCalculator::new()
+                .calculate_witness(&identity, inputs)
+                .unwrap();
+            assert_eq!(output, p_minus_one);
+        }
+    }
+
+    #[test]
+    fn assert_subtracts_with_p_minus_one() {
+        let circuit = compile_str("public x\nprivate y\nassert x == y\nreturn x").unwrap();
+        let y = circuit.private_inputs[0].1.id;
+        let r1cs = circuit.to_r1cs();
+        let coefficients: Vec<u64> = r1cs
+            .constraints
+            .iter()
+            .flat_map(|constraint| &constraint.a)
+            .filter(|(wire, _)| *wire == y)
+            .map(|(_, coeff)| coeff.as_u64())
+            .collect();
+        assert_eq!(coefficients, [MODULUS - 1]);
+    }
+
+    #[test]
+    fn element_times_its_inverse_is_one() {
+        for value in [1, 2, 7, 1 << 32, MODULUS - 2, MODULUS - 1] {
+            let element = FieldElement::new(value);
+            let inverse = element.inverse().unwrap();
+            assert_eq!(element * inverse, FieldElement::one(), "{}", value);
+        }
+        // p - 1 is -1, its own inverse
+        assert_eq!(
+            FieldElement::new(MODULUS - 1).inverse(),
+            Some(FieldElement::new(MODULUS - 1))
+        );
+    }
+
+    #[test]
+    fn zero_has_no_inverse() {
+        assert_eq!(FieldElement::zero().inverse(), None);
+        assert_eq!(FieldElement::new(MODULUS).inverse(), None);
+    }
+
+    #[test]
+    fn large_values_round_trip_as_decimal_strings() {
+        for value in [
+            FieldElement::new(MODULUS - 1),
+            FieldElement::new(1 << 53) + FieldElement::one(),
+        ] {
+            let json = serde_json::to_string(&value).unwrap();
+            assert_eq!(json, format!("\"{}\"", value.as_u64()));
+            assert_eq!(serde_json::from_str::<FieldElement>(&json).unwrap(), value);
+        }
+        // plain integers still read, as in hand-written input files
+        assert_eq!(
+            serde_json::from_str::<FieldElement>("-1").unwrap(),
+            FieldElement::new(MODULUS - 1)
+        );
+    }
+
+    #[test]
+    fn pow_matches_iterated_multiplication() {
+        for base in [
+            FieldElement::new(2),
+            FieldElement::new(7),
+            -FieldElement::new(3),
+        ] {
+            let mut product = FieldElement::one();
+            for exp in 0..=70u64 {
+                assert_eq!(base.pow(exp), product, "{} ** {}", base, exp);
+                product = product * base;
+            }
+        }
+        // 2 ** 64 reduces to 2 ** 32 - 1 modulo p
+        assert_eq!(
+            FieldElement::new(2).pow(64),
+            FieldElement::new(u32::MAX as u64)
+        );
+    }
+}
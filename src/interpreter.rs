@@ -0,0 +1,369 @@
+use crate::ast::{add_assign_sum, Expr, InputType, Program, Span, Spanned, Stmt};
+use crate::field::FieldElement;
+use crate::witness::InputFile;
+use std::collections::{HashMap, HashSet};
+
+// Evaluates a program straight from the AST, without SSA, circuit or witness.
+// It serves as a reference for what the compiled circuit should compute.
+pub fn interpret(program: &Program, inputs: &InputFile) -> Result<FieldElement, RuntimeError> {
+    let mut interpreter = Interpreter {
+        inputs,
+        functions: HashMap::new(),
+        scopes: vec![HashMap::new()],
+        constants: HashSet::new(),
+    };
+
+    let mut return_value = None;
+    for stmt in &program.statements {
+        if let Some(value) = interpreter.exec_stmt(stmt)? {
+            return_value = Some(value);
+        }
+    }
+    return_value.ok_or(RuntimeError::NoReturn)
+}
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    MissingInput(String),
+    OutOfRange(String, FieldElement, InputType),
+    UndefinedVariable(String, Span),
+    UndefinedFunction(String, Span),
+    AssertionFailed(FieldElement, FieldElement, Span),
+    InverseOfZero(Span),
+    NotBoolean(FieldElement, Span), // operand of and/or/not
+    NoReturn,
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RuntimeError::MissingInput(name) => write!(f, "Missing input: {}", name),
+            RuntimeError::OutOfRange(name, value, ty) => {
+                write!(f, "Input {} = {} does not fit in {}", name, value, ty)
+            }
+            RuntimeError::UndefinedVariable(name, span) => {
+                write!(f, "Undefined variable {} at {}", name, span)
+            }
+            RuntimeError::UndefinedFunction(name, span) => {
+                write!(f, "Undefined function {} at {}", name, span)
+            }
+            RuntimeError::AssertionFailed(left, right, span) => {
+                write!(f, "Assertion failed at {}: {} != {}", span, left, right)
+            }
+            RuntimeError::InverseOfZero(span) => {
+                write!(f, "Cannot invert zero at {}", span)
+            }
+            RuntimeError::NotBoolean(value, span) => {
+                write!(f, "Boolean operand at {} is {}, not 0 or 1", span, value)
+            }
+            RuntimeError::NoReturn => write!(f, "Program must have a return statement"),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+struct Interpreter<'a> {
+    inputs: &'a InputFile,
+    functions: HashMap<String, (Vec<String>, Vec<Spanned<Stmt>>)>, // name -> (params, body)
+    // same scoping as SsaBuilder: scopes[0] is the program, every call pushes a scope
+    scopes: Vec<HashMap<String, FieldElement>>,
+    constants: HashSet<String>, // program names currently bound by a const
+}
+
+impl Interpreter<'_> {
+    // returns the value of a return statement
+    fn exec_stmt(&mut self, stmt: &Spanned<Stmt>) -> Result<Option<FieldElement>, RuntimeError> {
+        match &stmt.node {
+            Stmt::PublicInput { name, ty } => {
+                let pinned = self
+                    .inputs
+                    .pin
+                    .as_ref()
+                    .filter(|pins| pins.contains_key(name));
+                let values = if pinned.is_some() {
+                    &self.inputs.pin
+                } else {
+                    &self.inputs.public
+                };
+                let value = Self::input(values, name, *ty)?;
+                self.bind(name, value);
+            }
+            Stmt::PrivateInput { name, ty } => {
+                let value = Self::input(&self.inputs.private, name, *ty)?;
+                self.bind(name, value);
+            }
+            Stmt::ConstDecl { name, value } => {
+                self.bind(name, *value);
+                self.constants.insert(name.clone());
+            }
+            Stmt::ConstArray { .. } | Stmt::StaticAssert { .. } => {}
+            Stmt::Let { name, expr } => {
+                let value = self.eval(expr)?;
+                self.bind(name, value);
+            }
+            Stmt::AddAssign { name, expr } => {
+                let value = self.eval(&add_assign_sum(name, expr, stmt.span.clone()))?;
+                self.bind(name, value);
+            }
+            Stmt::Assert { left, right } => {
+                let left_val = self.eval(left)?;
+                let right_val = self.eval(right)?;
+                if left_val != right_val {
+                    return Err(RuntimeError::AssertionFailed(
+                        left_val,
+                        right_val,
+                        stmt.span.clone(),
+                    ));
+                }
+            }
+            Stmt::AssertEq(operands) => {
+                let mut values = Vec::new();
+                for operand in operands {
+                    values.push(self.eval(operand)?);
+                }
+                if let Some(pair) = values.windows(2).find(|pair| pair[0] != pair[1]) {
+                    return Err(RuntimeError::AssertionFailed(
+                        pair[0],
+                        pair[1],
+                        stmt.span.clone(),
+                    ));
+                }
+            }
+            Stmt::Return(expr) => return self.eval(expr).map(Some),
+            Stmt::FnDecl { name, params, body } => {
+                self.functions
+                    .insert(name.clone(), (params.clone(), body.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn eval(&mut self, expr: &Spanned<Expr>) -> Result<FieldElement, RuntimeError> {
+        match &expr.node {
+            Expr::Literal(n) => Ok(*n),
+            Expr::Var(name) => self.lookup(name, expr.span.clone()),
+            Expr::Add(left, right) => Ok(self.eval(left)? + self.eval(right)?),
+            Expr::Sub(left, right) => Ok(self.eval(left)? - self.eval(right)?),
+            Expr::Mul(left, right) => Ok(self.eval(left)? * self.eval(right)?),
+            Expr::Neg(operand) => Ok(-self.eval(operand)?),
+            Expr::Pow(base, exponent) => Ok(self.eval(base)?.pow(*exponent as u64)),
+            Expr::Inv(operand) => self
+                .eval(operand)?
+                .inverse()
+                .ok_or(RuntimeError::InverseOfZero(expr.span.clone())),
+            Expr::IsZero(operand) => Ok(if self.eval(operand)?.is_zero() {
+                FieldElement::one()
+            } else {
+                FieldElement::zero()
+            }),
+            Expr::Ne(left, right) => Ok(if self.eval(left)? != self.eval(right)? {
+                FieldElement::one()
+            } else {
+                FieldElement::zero()
+            }),
+            Expr::And(left, right) => Ok(self.eval_boolean(left)? * self.eval_boolean(right)?),
+            Expr::Or(left, right) => {
+                let (left, right) = (self.eval_boolean(left)?, self.eval_boolean(right)?);
+                Ok(left + right - left * right)
+            }
+            Expr::Not(operand) => Ok(FieldElement::one() - self.eval_boolean(operand)?),
+            Expr::Let(name, value, body) => {
+                let value = self.eval(value)?;
+                let shadowed = self.shadow(name, Some(value));
+                let result = self.eval(body);
+                self.shadow(name, shadowed);
+                result
+            }
+            Expr::Call(name, args) => self.call(name, args, expr.span.clone()),
+        }
+    }
+
+    // an operand of and/or/not, which the circuit constrains to 0 or 1
+    fn eval_boolean(&mut self, expr: &Spanned<Expr>) -> Result<FieldElement, RuntimeError> {
+        let value = self.eval(expr)?;
+        if value.is_zero() || value == FieldElement::one() {
+            Ok(value)
+        } else {
+            Err(RuntimeError::NotBoolean(value, expr.span.clone()))
+        }
+    }
+
+    fn call(
+        &mut self,
+        name: &str,
+        args: &[Spanned<Expr>],
+        span: Span,
+    ) -> Result<FieldElement, RuntimeError> {
+        let (params, body) = self
+            .functions
+            .get(name)
+            .cloned()
+            .ok_or_else(|| RuntimeError::UndefinedFunction(name.to_string(), span))?;
+
+        let mut scope = HashMap::new();
+        for (param, arg) in params.into_iter().zip(args) {
+            scope.insert(param, self.eval(arg)?);
+        }
+
+        self.scopes.push(scope);
+        let mut return_value = None;
+        for stmt in &body {
+            match self.exec_stmt(stmt) {
+                Ok(Some(value)) => return_value = Some(value),
+                Ok(None) => {}
+                Err(err) => {
+                    self.scopes.pop();
+                    return Err(err);
+                }
+            }
+        }
+        self.scopes.pop();
+
+        return_value.ok_or(RuntimeError::NoReturn)
+    }
+}
+
+impl Interpreter<'_> {
+    fn input(
+        values: &Option<HashMap<String, FieldElement>>,
+        name: &str,
+        ty: Option<InputType>,
+    ) -> Result<FieldElement, RuntimeError> {
+        let value = values
+            .as_ref()
+            .and_then(|values| values.get(name))
+            .copied()
+            .ok_or_else(|| RuntimeError::MissingInput(name.to_string()))?;
+
+        match ty {
+            Some(ty) if value.as_u64() >> ty.bits() != 0 => {
+                Err(RuntimeError::OutOfRange(name.to_string(), value, ty))
+            }
+            _ => Ok(value),
+        }
+    }
+
+    fn bind(&mut self, name: &str, value: FieldElement) {
+        if self.scopes.len() == 1 {
+            self.constants.remove(name);
+        }
+        self.scopes
+            .last_mut()
+            .expect("scope stack is never empty")
+            .insert(name.to_string(), value);
+    }
+
+    // binds name in the innermost scope to value, or unbinds it for None,
+    // returning what it was bound to there before
+    fn shadow(&mut self, name: &str, value: Option<FieldElement>) -> Option<FieldElement> {
+        let scope = self.scopes.last_mut().expect("scope stack is never empty");
+        match value {
+            Some(value) => scope.insert(name.to_string(), value),
+            None => scope.remove(name),
+        }
+    }
+
+    // the innermost scope first, then the program scope; a fn body only
+    // sees the program's consts, like in SsaBuilder::lookup
+    fn lookup(&self, name: &str, span: Span) -> Result<FieldElement, RuntimeError> {
+        let innermost = self.scopes.last().and_then(|scope| scope.get(name));
+        let program = self.scopes[0]
+            .get(name)
+            .filter(|_| self.scopes.len() == 1 || self.constants.contains(name));
+        innermost
+            .or(program)
+            .copied()
+            .ok_or_else(|| RuntimeError::UndefinedVariable(name.to_string(), span))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{compile, parse_str};
+    use crate::optimizer::OptLevel;
+    use crate::witness::WitnessCalculator;
+
+    // programs over public x and private y, each run on every input row
+    const PROGRAMS: &[&str] = &[
+        "public x\nprivate y\nreturn x * y + 3",
+        "public x\nprivate y\nlet a = x + 0\nlet b = a * 1 - y\nreturn b * b",
+        "public x\nprivate y\nreturn x ** 5 - y ** 17",
+        "public x\nprivate y\nfn sq(a) {\n return a * a\n}\nreturn sq(x) + sq(y + 1)",
+        "public x\nprivate y\nlet d = x != y\nreturn d * 7 + is_zero(x - 2)",
+        "public x\nprivate y\nconst T = [5, 6, 7]\nlet acc = x\nacc += T[2]\nreturn acc * inv(y)",
+        "public x\nprivate y\nassert x * 2 == y + 1\nreturn let z = x + y in z * z",
+        "public x\nprivate y\nreturn (x != 2) and not (y != 3) or is_zero(x)",
+        "public x\nprivate y\nreturn x or not y",
+    ];
+    const ROWS: &[(i32, i32)] = &[(2, 3), (0, 5), (7, -1), (-4, 9)];
+
+    fn inputs(x: i32, y: i32) -> InputFile {
+        InputFile::builder().public("x", x).private("y", y).build()
+    }
+
+    // the interpreter, and the witness of the circuit at every optimization
+    // level, agree on the result or all fail
+    #[test]
+    fn interpreter_and_witness_agree() {
+        for source in PROGRAMS {
+            let program = parse_str(source).unwrap();
+            for &(x, y) in ROWS {
+                let expected = interpret(&program, &inputs(x, y)).ok();
+                for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+                    let circuit = compile(source, level).unwrap();
+                    let witness = WitnessCalculator::new()
+                        .calculate_witness(&circuit, inputs(x, y))
+                        .ok();
+                    assert_eq!(
+                        witness, expected,
+                        "{:?} at {:?} with x = {}, y = {}",
+                        source, level, x, y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn optimized_and_unoptimized_witness_agree() {
+        for source in PROGRAMS {
+            let unoptimized = compile(source, OptLevel::O0).unwrap();
+            let optimized = compile(source, OptLevel::O2).unwrap();
+            assert!(optimized.gates.len() <= unoptimized.gates.len());
+            for &(x, y) in ROWS {
+                let mut calculator = WitnessCalculator::new();
+                assert_eq!(
+                    calculator.calculate_witness(&optimized, inputs(x, y)).ok(),
+                    calculator
+                        .calculate_witness(&unoptimized, inputs(x, y))
+                        .ok(),
+                    "{:?} with x = {}, y = {}",
+                    source,
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn failing_assert_and_undefined_variable_are_errors() {
+        let program = parse_str("public x\nprivate y\nassert x == y\nreturn x").unwrap();
+        assert!(matches!(
+            interpret(&program, &inputs(1, 2)),
+            Err(RuntimeError::AssertionFailed(..))
+        ));
+        assert_eq!(
+            interpret(&program, &inputs(4, 4)).unwrap(),
+            FieldElement::from(4)
+        );
+
+        let program = parse_str("public x\nprivate y\nreturn z").unwrap();
+        assert!(matches!(
+            interpret(&program, &inputs(1, 2)),
+            Err(RuntimeError::UndefinedVariable(name, _)) if name == "z"
+        ));
+    }
+}
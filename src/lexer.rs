@@ -1,8 +1,10 @@
+use crate::field::FieldElement;
 use crate::token::{Token, TokenType};
 
 pub struct Lexer {
     source: Vec<char>,
     current: usize,
+    finished: bool,
 }
 
 impl Lexer {
@@ -10,23 +12,35 @@ impl Lexer {
         Self {
             source: source.chars().collect(),
             current: 0,
+            finished: false,
         }
     }
 
-    pub fn tokenize(&mut self) -> Vec<Token> {
-        let mut tokens: Vec<Token> = Vec::new();
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, LexError> {
+        self.by_ref().collect()
+    }
+}
 
-        while !self.is_at_end() {
-            if let Some(token) = self.scan_token() {
-                tokens.push(token)
-            }
+// yields one token per call, ending with Eof
+impl Iterator for Lexer {
+    type Item = Result<Token, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
         }
 
-        tokens.push(Token {
-            token_type: TokenType::Eof,
-            span: (self.current, self.current),
-        });
-        tokens
+        match self.scan_token() {
+            Ok(Some(token)) => Some(Ok(token)),
+            Ok(None) => {
+                self.finished = true;
+                Some(Ok(Token {
+                    token_type: TokenType::Eof,
+                    span: (self.current, self.current),
+                }))
+            }
+            Err(err) => Some(Err(err)),
+        }
     }
 }
 
@@ -60,19 +74,34 @@ impl Lexer {
         }
     }
 
-    fn scan_token(&mut self) -> Option<Token> {
+    fn scan_token(&mut self) -> Result<Option<Token>, LexError> {
         self.skip_whitespace();
 
         if self.is_at_end() {
-            return None;
+            return Ok(None);
         }
 
         let start = self.current;
         let ch = self.advance();
 
         let token_type = match ch {
-            '+' => TokenType::Plus,
-            '*' => TokenType::Star,
+            '+' => {
+                if self.peek() == '=' {
+                    self.advance();
+                    TokenType::PlusEquals
+                } else {
+                    TokenType::Plus
+                }
+            }
+            '-' => TokenType::Minus,
+            '*' => {
+                if self.peek() == '*' {
+                    self.advance();
+                    TokenType::StarStar
+                } else {
+                    TokenType::Star
+                }
+            }
             '=' => {
                 if self.peek() == '=' {
                     self.advance();
@@ -81,11 +110,38 @@ impl Lexer {
                     TokenType::Equals
                 }
             }
+            '!' if self.peek() == '=' => {
+                self.advance();
+                TokenType::BangEquals
+            }
+            '<' => {
+                if self.peek() == '=' {
+                    self.advance();
+                    TokenType::LessEquals
+                } else {
+                    TokenType::Less
+                }
+            }
+            '>' => {
+                if self.peek() == '=' {
+                    self.advance();
+                    TokenType::GreaterEquals
+                } else {
+                    TokenType::Greater
+                }
+            }
             '(' => TokenType::LeftParen,
             ')' => TokenType::RightParen,
+            '{' => TokenType::LeftBrace,
+            '}' => TokenType::RightBrace,
+            '[' => TokenType::LeftBracket,
+            ']' => TokenType::RightBracket,
+            ',' => TokenType::Comma,
+            ':' => TokenType::Colon,
+            '"' => TokenType::Str(self.read_string(start)?),
             '0'..='9' => {
                 self.current -= 1;
-                TokenType::Number(self.read_number())
+                TokenType::Number(self.read_number()?)
             }
             'a'..='z' | 'A'..='Z' | '_' => {
                 self.current -= 1;
@@ -96,22 +152,40 @@ impl Lexer {
                     "let" => TokenType::Let,
                     "return" => TokenType::Return,
                     "assert" => TokenType::Assert,
+                    "assert_eq" => TokenType::AssertEq,
+                    "static_assert" => TokenType::StaticAssert,
                     "public" => TokenType::Public,
                     "private" => TokenType::Private,
                     "const" => TokenType::Const,
+                    "fn" => TokenType::Fn,
+                    "include" => TokenType::Include,
+                    "in" => TokenType::In, // reserved by let ... in, no longer a valid name
+                    // the boolean operators are reserved too: and, or and not
+                    // can't name variables, inputs or functions
+                    "and" => TokenType::And,
+                    "or" => TokenType::Or,
+                    "not" => TokenType::Not,
                     _ => TokenType::Identifier(ident),
                 }
             }
-            _ => panic!("Unexpected character: {}", ch),
+            _ => {
+                return Err(LexError {
+                    message: format!("Unexpected character: {}", ch),
+                    span: (start, self.current),
+                })
+            }
         };
 
-        Some(Token {
+        Ok(Some(Token {
             token_type,
             span: (start, self.current),
-        })
+        }))
     }
 
-    fn read_number(&mut self) -> i32 {
+    // any number of digits, reduced modulo p. A literal other than 0 can't
+    // start with 0: 007 could be read as octal, and 0x / 0b prefixes are left
+    // free for other bases.
+    fn read_number(&mut self) -> Result<FieldElement, LexError> {
         let start = self.current;
 
         while !self.is_at_end() && self.peek().is_ascii_digit() {
@@ -119,7 +193,39 @@ impl Lexer {
         }
 
         let num_str: String = self.source[start..self.current].iter().collect();
-        num_str.parse().unwrap()
+        if num_str.len() > 1 && num_str.starts_with('0') {
+            let trimmed = num_str.trim_start_matches('0');
+            let suggestion = if trimmed.is_empty() { "0" } else { trimmed };
+            return Err(LexError {
+                message: format!(
+                    "Number literal {} has leading zeros, write {}",
+                    num_str, suggestion
+                ),
+                span: (start, self.current),
+            });
+        }
+        num_str.parse().map_err(|_| LexError {
+            message: format!("Invalid number literal: {}", num_str),
+            span: (start, self.current),
+        })
+    }
+
+    // the characters up to the closing quote, which must be on the same line;
+    // there are no escapes, strings are only include paths
+    fn read_string(&mut self, start: usize) -> Result<String, LexError> {
+        let content_start = self.current;
+        while !self.is_at_end() && self.peek() != '"' && self.peek() != '\n' {
+            self.advance();
+        }
+        if self.peek() != '"' {
+            return Err(LexError {
+                message: "Unterminated string".to_string(),
+                span: (start, self.current),
+            });
+        }
+        let content = self.source[content_start..self.current].iter().collect();
+        self.advance();
+        Ok(content)
     }
 
     fn read_identifier(&mut self) -> String {
@@ -137,3 +243,70 @@ impl Lexer {
         self.source[start..self.current].iter().collect()
     }
 }
+
+#[derive(Debug)]
+pub struct LexError {
+    pub message: String,
+    pub span: (usize, usize),
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at {}..{}", self.message, self.span.0, self.span.1)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_types(source: &str) -> Vec<TokenType> {
+        Lexer::new(source)
+            .tokenize()
+            .unwrap()
+            .into_iter()
+            .map(|token| token.token_type)
+            .collect()
+    }
+
+    #[test]
+    fn leading_zeros_are_rejected_with_a_suggestion() {
+        let err = Lexer::new("return 007").tokenize().unwrap_err();
+        assert_eq!(err.message, "Number literal 007 has leading zeros, write 7");
+        assert_eq!(err.span, (7, 10));
+
+        let err = Lexer::new("return 00").tokenize().unwrap_err();
+        assert_eq!(err.message, "Number literal 00 has leading zeros, write 0");
+    }
+
+    #[test]
+    fn zero_is_a_number() {
+        assert_eq!(
+            token_types("0"),
+            [TokenType::Number(FieldElement::zero()), TokenType::Eof]
+        );
+    }
+
+    #[test]
+    fn digits_after_a_letter_are_part_of_an_identifier() {
+        assert_eq!(
+            token_types("x07"),
+            [TokenType::Identifier("x07".to_string()), TokenType::Eof]
+        );
+    }
+
+    #[test]
+    fn iterator_yields_the_same_tokens_as_tokenize() {
+        let source = "public x\nprivate y\nlet z = x * (y + 3) ** 2\nassert z == 12\nreturn z - x";
+        let streamed: Vec<Token> = Lexer::new(source).collect::<Result<_, _>>().unwrap();
+        let collected = Lexer::new(source).tokenize().unwrap();
+        assert_eq!(streamed.len(), collected.len());
+        for (a, b) in streamed.iter().zip(&collected) {
+            assert_eq!(a.token_type, b.token_type);
+            assert_eq!(a.span, b.span);
+        }
+        assert_eq!(streamed.last().unwrap().token_type, TokenType::Eof);
+    }
+}
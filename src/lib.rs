@@ -0,0 +1,24 @@
+#[cfg(feature = "arkworks")]
+pub mod arkworks;
+pub mod assert_analysis;
+pub mod ast;
+pub mod backend;
+#[cfg(feature = "bellman")]
+pub mod bellman;
+pub mod circuit;
+pub mod compiler;
+pub mod const_eval;
+pub mod equivalence;
+pub mod error;
+#[cfg(any(feature = "bellman", feature = "arkworks"))]
+pub mod export;
+pub mod field;
+pub mod interpreter;
+pub mod lexer;
+pub mod listing;
+pub mod optimizer;
+pub mod parser;
+pub mod ssa;
+pub mod timings;
+pub mod token;
+pub mod witness;
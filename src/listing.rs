@@ -0,0 +1,45 @@
+// One line of an SSA or gate listing: "index: assigned = rest", or
+// "index: rest" for a line that assigns nothing, like an assert
+pub struct ListingRow {
+    pub index: usize,
+    pub assigned: Option<String>,
+    pub rest: String,
+}
+
+// Lines with the indices right-aligned and the assigned values padded to the
+// widest one, so every " = " of a long listing is in the same column:
+//  9: t7.0  = x.0 * t6.0
+// 10: out.1 = t7.0 + 1
+// 11: assert out.1 == y.0
+pub fn aligned_lines(rows: &[ListingRow]) -> Vec<String> {
+    let index_width = rows
+        .iter()
+        .map(|row| row.index.to_string().len())
+        .max()
+        .unwrap_or(0);
+    let assigned_width = rows
+        .iter()
+        .filter_map(|row| row.assigned.as_ref())
+        .map(|assigned| assigned.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    rows.iter()
+        .map(|row| match &row.assigned {
+            Some(assigned) => format!(
+                "{:>index_width$}: {:<assigned_width$} = {}",
+                row.index,
+                assigned,
+                row.rest,
+                index_width = index_width,
+                assigned_width = assigned_width
+            ),
+            None => format!(
+                "{:>index_width$}: {}",
+                row.index,
+                row.rest,
+                index_width = index_width
+            ),
+        })
+        .collect()
+}
@@ -1,107 +1,560 @@
-mod ast;
-mod circuit;
-mod lexer;
-mod optimizer;
-mod parser;
-mod ssa;
-mod token;
-mod witness;
-
-use circuit::CircuitBuilder;
-use lexer::Lexer;
-use optimizer::{ConstantFolder, DeadCodeEliminator};
-use parser::Parser;
-use ssa::SsaBuilder;
+use circuit_compiler::assert_analysis::{analyze_asserts, AssertVerdict};
+use circuit_compiler::ast::{Program, Span};
+use circuit_compiler::backend::{ConstraintBackend, PlonkishBackend, R1csBackend, Target};
+use circuit_compiler::circuit::{Circuit, CircuitBuilder, GateLimitError};
+use circuit_compiler::const_eval::check_static_asserts;
+use circuit_compiler::error::CompilerError;
+use circuit_compiler::field::FieldElement;
+use circuit_compiler::interpreter::interpret;
+use circuit_compiler::lexer::Lexer;
+use circuit_compiler::optimizer::{self, pin_inputs, prune_unused_inputs, OptLevel};
+use circuit_compiler::parser::Parser;
+use circuit_compiler::ssa::{SsaBuilder, SsaError};
+use circuit_compiler::timings::Timings;
+use circuit_compiler::witness::{InputFile, WitnessCalculator};
+use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::process;
-use witness::{InputFile, WitnessCalculator};
+
+// Prints log records as plain lines, warnings and errors to stderr and the
+// rest to stdout, so the stage dumps the library and this binary log at
+// debug level read the same as before they went through `log`
+struct StdoutLogger;
+
+impl log::Log for StdoutLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        if record.level() <= log::Level::Warn {
+            eprintln!("{}", record.args());
+        } else {
+            println!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StdoutLogger = StdoutLogger;
 
 fn main() {
+    log::set_logger(&LOGGER).expect("no other logger is installed");
+    log::set_max_level(log::LevelFilter::Debug);
+
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 || args.len() > 3 {
-        eprintln!("Usage:");
-        eprintln!("  cargo run <file.zk>              # Compile only");
-        eprintln!("  cargo run <file.zk> <inputs.toml> # Compile and execute");
+    if args.get(1).map(String::as_str) == Some("check") {
+        if args.len() != 3 {
+            print_usage();
+            process::exit(1);
+        }
+        run_check(&args[2]);
+        return;
+    }
+
+    if args.get(1).map(String::as_str) == Some("witness") {
+        if args.len() < 4 {
+            print_usage();
+            process::exit(1);
+        }
+        if args.len() == 4 {
+            run_witness(&args[2], &args[3]);
+        } else {
+            run_witness_batch(&args[2], &args[3..]);
+        }
+        return;
+    }
+
+    let mut options = CompileOptions {
+        opt_level: OptLevel::O1,
+        canonical_order: false,
+        stats_json: false,
+        sparsity_json: false,
+        prune_inputs: false,
+        dump_wires: false,
+        timings: false,
+        verbose: false,
+        target: Target::R1cs,
+        check_asserts: false,
+        max_gates: None,
+        explain: false,
+        threads: 1,
+    };
+    let mut positional = Vec::new();
+    for arg in &args[1..] {
+        if arg == "--no-optimize" {
+            options.opt_level = OptLevel::O0;
+        } else if let Some(level) = OptLevel::from_flag(arg) {
+            options.opt_level = level;
+        } else if arg == "--canonical-order" {
+            options.canonical_order = true;
+        } else if arg == "--stats-json" {
+            options.stats_json = true;
+        } else if arg == "--sparsity-json" {
+            options.sparsity_json = true;
+        } else if arg == "--prune-inputs" {
+            options.prune_inputs = true;
+        } else if arg == "--dump-wires" {
+            options.dump_wires = true;
+        } else if arg == "--timings" {
+            options.timings = true;
+        } else if arg == "--verbose" {
+            options.verbose = true;
+        } else if arg == "--check-asserts" {
+            options.check_asserts = true;
+        } else if arg == "--explain" {
+            options.explain = true;
+        } else if let Some(threads) = arg.strip_prefix("--threads=") {
+            options.threads = match threads.parse() {
+                Ok(threads) if threads > 0 => threads,
+                _ => {
+                    log::error!("Invalid --threads: {} (expected a positive count)", threads);
+                    process::exit(1);
+                }
+            };
+        } else if let Some(limit) = arg.strip_prefix("--max-gates=") {
+            options.max_gates = match limit.parse() {
+                Ok(max_gates) => Some(max_gates),
+                Err(_) => {
+                    log::error!("Invalid --max-gates: {} (expected a gate count)", limit);
+                    process::exit(1);
+                }
+            };
+        } else if let Some(name) = arg.strip_prefix("--target=") {
+            options.target = match Target::from_name(name) {
+                Some(target) => target,
+                None => {
+                    log::error!("Unknown target: {} (expected r1cs or plonkish)", name);
+                    process::exit(1);
+                }
+            };
+        } else if arg.starts_with('-') {
+            log::error!("Unknown option: {}", arg);
+            print_usage();
+            process::exit(1);
+        } else {
+            positional.push(arg);
+        }
+    }
+
+    if positional.is_empty() || positional.len() > 2 {
+        print_usage();
+        process::exit(1);
+    }
+
+    if options.target != Target::R1cs
+        && (options.canonical_order || options.sparsity_json || options.explain)
+    {
+        log::error!("--canonical-order, --sparsity-json and --explain only apply to --target=r1cs");
         process::exit(1);
     }
 
-    let filename = &args[1];
-    let inputs_filename = args.get(2);
+    compile(positional[0], positional.get(1).copied(), &options);
+}
+
+struct CompileOptions {
+    opt_level: OptLevel,
+    // write R1CS and witness as [ONE, out, public, private, rest] for Groth16 tooling
+    canonical_order: bool,
+    // write gate, constraint and wire counts before and after optimization as JSON
+    stats_json: bool,
+    // write the nonzero positions of the R1CS matrices as JSON, for plotting
+    sparsity_json: bool,
+    // drop declared inputs that nothing reads, they take no wire in the circuit
+    prune_inputs: bool,
+    // print every wire value after witness calculation, also when it fails
+    dump_wires: bool,
+    // print how long each compiler stage took
+    timings: bool,
+    // list every gate, not just the first and last GATE_LISTING_EDGE
+    verbose: bool,
+    // constraint model the circuit is lowered to
+    target: Target,
+    // report asserts that can never hold or always hold, before optimizing
+    check_asserts: bool,
+    // abort building a circuit once it has more gates than this
+    max_gates: Option<usize>,
+    // describe every R1CS constraint through the gate it comes from
+    explain: bool,
+    // threads the witness calculation splits each layer of gates across
+    threads: usize,
+}
+
+// how calculate_witness runs, taken from CompileOptions when compiling
+#[derive(Debug, Clone, Copy)]
+struct WitnessOptions {
+    // print all wire values, which also shows how far a failing calculation got
+    dump_wires: bool,
+    threads: usize,
+}
+
+impl Default for WitnessOptions {
+    fn default() -> Self {
+        WitnessOptions {
+            dump_wires: false,
+            threads: 1,
+        }
+    }
+}
+
+// gates printed at either end of a long circuit listing without --verbose
+const GATE_LISTING_EDGE: usize = 20;
+
+fn print_usage() {
+    eprintln!("Usage:");
+    eprintln!("  cargo run <file.zk>                                 # Compile only");
+    eprintln!("  cargo run <file.zk> <inputs.toml>                   # Compile and execute");
+    eprintln!("  cargo run -- check <file.zk>                        # Only report errors");
+    eprintln!("  cargo run -- witness <circuit.json> <inputs.toml>   # Execute a saved circuit");
+    eprintln!(
+        "  cargo run -- witness <circuit.json> <inputs.toml>...  # Execute it for each inputs file"
+    );
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  -O0, --no-optimize   Skip all optimization passes");
+    eprintln!("  -O1                  Peephole, constant folding and DCE (default)");
+    eprintln!("  -O2                  -O1 plus algebraic simplification and CSE, to a fixed point");
+    eprintln!(
+        "  --canonical-order    Number R1CS variables and witness as [one, out, public, private, rest]"
+    );
+    eprintln!("  --stats-json         Write circuit size figures to circuit/<name>.stats.json");
+    eprintln!(
+        "  --sparsity-json      Write R1CS nonzero positions to circuit/<name>.sparsity.json"
+    );
+    eprintln!("  --prune-inputs       Remove declared inputs that nothing reads from the circuit");
+    eprintln!("  --dump-wires         Print the value of every wire after witness calculation");
+    eprintln!("  --timings            Print the time spent in each compiler stage");
+    eprintln!("  --verbose            List every gate of long circuits instead of both ends");
+    eprintln!("  --target=<model>     Constraint model to emit: r1cs (default) or plonkish");
+    eprintln!("  --check-asserts      Report asserts that can never hold or always hold");
+    eprintln!("  --max-gates=<n>      Abort when the optimized circuit has more than n gates");
+    eprintln!("  --explain            Print every R1CS constraint with the gate it comes from");
+    eprintln!("  --threads=<n>        Calculate the witness layer by layer on n threads");
+}
+
+// saved circuits may have been edited by hand, so the wire graph is checked for cycles
+fn load_circuit(circuit_filename: &str) -> Circuit {
+    let circuit = match Circuit::load_from_file(circuit_filename) {
+        Ok(circuit) => circuit,
+        Err(err) => {
+            log::error!("Error loading circuit '{}': {}", circuit_filename, err);
+            process::exit(1);
+        }
+    };
 
+    if let Err(err) = circuit.validate() {
+        log::error!("Invalid circuit '{}': {}", circuit_filename, err);
+        process::exit(1);
+    }
+    if let Err(err) = circuit.check_acyclic() {
+        log::error!("Invalid circuit '{}': {}", circuit_filename, err);
+        process::exit(1);
+    }
+    circuit
+}
+
+// Lexes, parses, checks static asserts and converts to SSA, then stops: no
+// circuit is built and nothing is written. Prints "ok", or the first error and
+// exits 1.
+fn run_check(filename: &str) {
     let source = match fs::read_to_string(filename) {
         Ok(content) => content,
         Err(err) => {
-            eprintln!("Error reading file '{}': {}", filename, err);
+            log::error!("Error reading file '{}': {}", filename, err);
             process::exit(1);
         }
     };
 
-    println!("=== SOURCE ===");
-    println!("{}", source);
+    let result = Lexer::new(&source)
+        .tokenize()
+        .map_err(CompilerError::from)
+        .and_then(|tokens| {
+            let program = Parser::for_file(tokens, std::path::Path::new(filename)).parse()?;
+            check_static_asserts(&program, &HashMap::new())?;
+            let ssa_program = SsaBuilder::new().convert(program)?;
+            optimizer::check_constant_asserts(&ssa_program)?;
+            Ok(())
+        });
+    match result {
+        Ok(()) => log::info!("ok"),
+        Err(err) => {
+            log::error!("{}", err);
+            if matches!(err, CompilerError::Ssa(SsaError::NoReturn)) {
+                log::error!("{}", no_return_hint(&source));
+            }
+            process::exit(1);
+        }
+    }
+}
+
+fn exit_gate_limit(err: GateLimitError) -> ! {
+    log::error!("\n=== GATE LIMIT EXCEEDED ===");
+    log::error!("{}", err);
+    process::exit(1);
+}
+
+// what to do about SsaError::NoReturn, for an empty file or one that only
+// declares inputs and checks them
+fn no_return_hint(source: &str) -> &'static str {
+    if source.trim().is_empty() {
+        "The file is empty; the smallest program is a return statement, like `return 1`"
+    } else {
+        "End the program with `return <expr>`: inputs and asserts alone have no output to compute"
+    }
+}
+
+// loads a circuit saved by a previous compilation and computes its witness
+fn run_witness(circuit_filename: &str, inputs_filename: &str) {
+    let circuit = load_circuit(circuit_filename);
+
+    fs::create_dir_all("circuit").unwrap_or(());
+    let base_name = std::path::Path::new(circuit_filename)
+        .file_stem()
+        .unwrap()
+        .to_str()
+        .unwrap();
+
+    calculate_witness(
+        &circuit,
+        inputs_filename,
+        base_name,
+        None,
+        None,
+        WitnessOptions::default(),
+        &mut Timings::new(),
+    );
+}
+
+// executes a saved circuit once per inputs file and reports which rows fail
+fn run_witness_batch(circuit_filename: &str, inputs_filenames: &[String]) {
+    let circuit = load_circuit(circuit_filename);
+
+    let mut rows = Vec::new();
+    for inputs_filename in inputs_filenames {
+        match InputFile::load_from_file(inputs_filename) {
+            Ok(inputs) => rows.push(inputs),
+            Err(err) => {
+                log::error!("Error reading inputs file '{}': {}", inputs_filename, err);
+                process::exit(1);
+            }
+        }
+    }
+
+    log::info!("=== CALCULATING WITNESS BATCH ===");
+    let mut calculator = WitnessCalculator::new();
+    let results = calculator.calculate_batch(&circuit, &rows);
+
+    let mut failures = 0;
+    for (inputs_filename, result) in inputs_filenames.iter().zip(results) {
+        match result {
+            Ok(value) => log::info!("{}: {}", inputs_filename, value),
+            Err(err) => {
+                failures += 1;
+                log::info!("{}: error: {}", inputs_filename, err);
+            }
+        }
+    }
+    log::info!("{} of {} rows failed", failures, rows.len());
+
+    if failures > 0 {
+        process::exit(1);
+    }
+}
+
+fn compile(filename: &str, inputs_filename: Option<&String>, options: &CompileOptions) {
+    let opt_level = options.opt_level;
+    let source = match fs::read_to_string(filename) {
+        Ok(content) => content,
+        Err(err) => {
+            log::error!("Error reading file '{}': {}", filename, err);
+            process::exit(1);
+        }
+    };
 
+    log::debug!("=== SOURCE ===");
+    log::debug!("{}", source);
+
+    let mut timings = Timings::new();
     let mut lexer = Lexer::new(&source);
-    let tokens = lexer.tokenize();
+    let tokens = match timings.time("lex", || lexer.tokenize()) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            log::error!("\n=== LEX ERROR ===");
+            log::error!("{}", err);
+            process::exit(1);
+        }
+    };
 
-    println!("\n=== TOKENS ===");
+    log::debug!("\n=== TOKENS ===");
     for (i, token) in tokens.iter().enumerate() {
-        println!("{}: {:?}", i, token);
+        log::debug!("{}: {:?}", i, token);
     }
 
-    let mut parser = Parser::new(tokens);
-    let program = match parser.parse() {
+    let mut parser = Parser::for_file(tokens, std::path::Path::new(filename));
+    let program = match timings.time("parse", || parser.parse()) {
         Ok(program) => {
-            println!("\n=== AST ===");
-            println!("{:#?}", program);
+            log::debug!("\n=== AST ===");
+            log::debug!("{:#?}", program);
             program
         }
         Err(err) => {
-            eprintln!("\n=== PARSE ERROR ===");
-            eprintln!("{}", err.message);
+            log::error!("\n=== PARSE ERROR ===");
+            log::error!("{}", err);
             process::exit(1);
         }
     };
 
+    let pins = inputs_filename
+        .map(|inputs_file| load_pins(inputs_file))
+        .unwrap_or_default();
+    if let Err(err) = check_static_asserts(&program, &pins) {
+        log::error!("\n=== STATIC ASSERT ERROR ===");
+        log::error!("{}", err);
+        process::exit(1);
+    }
+
     let ssa_builder = SsaBuilder::new();
-    let ssa_program = ssa_builder.convert(program);
+    let ssa_program = match timings.time("ssa", || ssa_builder.convert(program.clone())) {
+        Ok(ssa_program) => ssa_program,
+        Err(err) => {
+            log::error!("\n=== SSA ERROR ===");
+            log::error!("{}", err);
+            if matches!(err, SsaError::NoReturn) {
+                log::error!("{}", no_return_hint(&source));
+            }
+            process::exit(1);
+        }
+    };
+
+    log::debug!("\n=== SSA IR ===");
+    log::debug!("{}", ssa_program.to_aligned_string());
+
+    let ssa_program = if pins.is_empty() {
+        ssa_program
+    } else {
+        log::debug!("\n=== PINNED PUBLIC INPUTS ===");
+        let mut names: Vec<&String> = pins.keys().collect();
+        names.sort();
+        for name in names {
+            log::debug!("{} = {}", name, pins[name]);
+        }
+        match pin_inputs(ssa_program, &pins) {
+            Ok(ssa_program) => ssa_program,
+            Err(err) => {
+                log::error!("\n=== PIN ERROR ===");
+                log::error!("{}", err);
+                process::exit(1);
+            }
+        }
+    };
+
+    if options.check_asserts {
+        log::info!("\n=== ASSERT ANALYSIS ===");
+        for report in analyze_asserts(&ssa_program) {
+            match report.verdict {
+                AssertVerdict::AlwaysFails => log::warn!("{}", report),
+                AssertVerdict::AlwaysHolds => log::info!("{}", report),
+                AssertVerdict::Unknown => log::debug!("{}", report),
+            }
+        }
+    }
+
+    let circuit_before = match CircuitBuilder::try_from_ssa_with_metadata(ssa_program.clone(), None)
+    {
+        Ok((circuit, _)) => circuit,
+        Err(err) => exit_gate_limit(err),
+    };
 
-    println!("\n=== SSA IR ===");
-    for (i, instr) in ssa_program.instructions.iter().enumerate() {
-        println!("{}: {}", i, instr);
+    log::debug!("\n=== CIRCUIT (BEFORE OPTIMIZATION) ===");
+    log::debug!("Public inputs: {:?}", circuit_before.public_inputs);
+    log::debug!("Private inputs: {:?}", circuit_before.private_inputs);
+    let gate_edge = (!options.verbose).then_some(GATE_LISTING_EDGE);
+    for line in circuit_before.gate_lines(gate_edge) {
+        log::debug!("{}", line);
+    }
+    log::debug!("output: {}", circuit_before.output_wire);
+    log::debug!("Total gates: {}", circuit_before.gates.len());
+
+    let mut optimized_ssa = optimizer::optimize_timed(ssa_program.clone(), opt_level, &mut timings);
+    if options.prune_inputs {
+        let (pruned_ssa, pruned) = prune_unused_inputs(optimized_ssa);
+        if !pruned.is_empty() {
+            log::warn!(
+                "\nWarning: removed inputs that nothing reads: {}",
+                pruned.join(", ")
+            );
+        }
+        optimized_ssa = pruned_ssa;
     }
-    println!("return {}", ssa_program.return_value);
 
-    let circuit_before = CircuitBuilder::from_ssa(ssa_program.clone());
+    log::debug!("\n=== OPTIMIZED SSA ({:?}) ===", opt_level);
+    log::debug!("{}", optimized_ssa.to_aligned_string());
 
-    println!("\n=== CIRCUIT (BEFORE OPTIMIZATION) ===");
-    println!("Public inputs: {:?}", circuit_before.public_inputs);
-    println!("Private inputs: {:?}", circuit_before.private_inputs);
-    for (i, gate) in circuit_before.gates.iter().enumerate() {
-        println!("{}: {}", i, gate);
+    if let Err(err) = optimizer::check_constant_asserts(&optimized_ssa) {
+        log::error!("\n=== ASSERT ERROR ===");
+        log::error!("{}", err);
+        process::exit(1);
     }
-    println!("output: {}", circuit_before.output_wire);
-    println!("Total gates: {}", circuit_before.gates.len());
 
-    let folded_ssa = ConstantFolder::optimize(ssa_program.clone());
-    let optimized_ssa = DeadCodeEliminator::eliminate(folded_ssa);
+    // gate spans are taken before compact_wires, which keeps the gates but
+    // renumbers the wires the provenance refers to
+    let built = timings.time("circuit", || {
+        if opt_level == OptLevel::O0 {
+            let (circuit, provenance) =
+                CircuitBuilder::try_from_ssa_with_metadata(optimized_ssa, options.max_gates)?;
+            let gate_spans = circuit.gate_spans(&provenance);
+            Ok((circuit, gate_spans))
+        } else {
+            let (circuit, provenance) =
+                CircuitBuilder::try_from_ssa_interning_constants_with_metadata(
+                    optimized_ssa,
+                    options.max_gates,
+                )?;
+            // a fused linear combination is one R1CS constraint but doesn't fit
+            // a Plonkish row, so the additions are left as they are there
+            let circuit = match options.target {
+                Target::R1cs => circuit.fuse_additions(),
+                Target::Plonkish => circuit,
+            };
+            let circuit = circuit.fold_constants();
+            let gate_spans = circuit.gate_spans(&provenance);
+            Ok((circuit.compact_wires().0, gate_spans))
+        }
+    });
+    let (circuit_after, gate_spans) = match built {
+        Ok(built) => built,
+        Err(err) => exit_gate_limit(err),
+    };
 
-    println!("\n=== OPTIMIZED SSA ===");
-    for (i, instr) in optimized_ssa.instructions.iter().enumerate() {
-        println!("{}: {}", i, instr);
+    log::debug!("\n=== CIRCUIT (AFTER OPTIMIZATION) ===");
+    log::debug!("Public inputs: {:?}", circuit_after.public_inputs);
+    log::debug!("Private inputs: {:?}", circuit_after.private_inputs);
+    for line in circuit_after.gate_lines(gate_edge) {
+        log::debug!("{}", line);
     }
-    println!("return {}", optimized_ssa.return_value);
+    log::debug!("output: {}", circuit_after.output_wire);
+    log::debug!("Total gates: {}", circuit_after.gates.len());
+    log::debug!(
+        "Estimated constraints: {}",
+        circuit_after.constraint_count_estimate()
+    );
 
-    let circuit_after = CircuitBuilder::from_ssa(optimized_ssa);
+    if let Err(err) = circuit_after.topological_check() {
+        log::error!("\n=== INVALID CIRCUIT ===");
+        log::error!("{}", err);
+        process::exit(1);
+    }
 
-    println!("\n=== CIRCUIT (AFTER OPTIMIZATION) ===");
-    println!("Public inputs: {:?}", circuit_after.public_inputs);
-    println!("Private inputs: {:?}", circuit_after.private_inputs);
-    for (i, gate) in circuit_after.gates.iter().enumerate() {
-        println!("{}: {}", i, gate);
+    let unconstrained = circuit_after.unconstrained_inputs();
+    if !unconstrained.is_empty() {
+        log::warn!(
+            "\nWarning: inputs not constrained by the output or any assert: {}",
+            unconstrained.join(", ")
+        );
     }
-    println!("output: {}", circuit_after.output_wire);
-    println!("Total gates: {}", circuit_after.gates.len());
 
     fs::create_dir_all("circuit").unwrap_or(());
     let base_name = std::path::Path::new(filename)
@@ -112,55 +565,273 @@ fn main() {
     let circuit_filename = format!("circuit/{}.json", base_name);
 
     match circuit_after.save_to_file(&circuit_filename) {
-        Ok(()) => println!("\nSaved circuit to {}", circuit_filename),
+        Ok(()) => log::info!("\nSaved circuit to {}", circuit_filename),
         Err(err) => {
-            eprintln!("Error saving circuit: {}", err);
+            log::error!("Error saving circuit: {}", err);
             process::exit(1);
         }
     }
 
-    let r1cs = circuit_after.to_r1cs();
+    if options.stats_json {
+        let stats_filename = format!("circuit/{}.stats.json", base_name);
+        let report = serde_json::json!({
+            "before": circuit_before.stats(),
+            "after": circuit_after.stats(),
+        });
+        match serde_json::to_string_pretty(&report)
+            .map_err(|err| err.to_string())
+            .and_then(|json| fs::write(&stats_filename, json).map_err(|err| err.to_string()))
+        {
+            Ok(()) => log::info!("Saved stats to {}", stats_filename),
+            Err(err) => log::error!("Error saving stats: {}", err),
+        }
+    }
+
+    let order = options
+        .canonical_order
+        .then(|| circuit_after.canonical_order());
+    match options.target {
+        Target::R1cs => emit_r1cs(
+            &circuit_after,
+            filename,
+            base_name,
+            &gate_spans,
+            order.as_deref(),
+            options,
+            &mut timings,
+        ),
+        Target::Plonkish => emit_plonkish(&circuit_after, base_name, &mut timings),
+    }
+
+    if let Some(inputs_file) = inputs_filename {
+        calculate_witness(
+            &circuit_after,
+            inputs_file,
+            base_name,
+            Some(&program),
+            order.as_deref(),
+            WitnessOptions {
+                dump_wires: options.dump_wires,
+                threads: options.threads,
+            },
+            &mut timings,
+        );
+    }
+
+    if options.timings {
+        log::info!("\n=== TIMINGS ===");
+        log::info!("{}", timings);
+    }
+}
+
+// lowers to R1CS and saves it, along with the source map, the circom files
+// and, with --sparsity-json, the matrix sparsity; `order` is the
+// --canonical-order numbering of the variables
+fn emit_r1cs(
+    circuit: &Circuit,
+    filename: &str,
+    base_name: &str,
+    gate_spans: &[Option<Span>],
+    order: Option<&[usize]>,
+    options: &CompileOptions,
+    timings: &mut Timings,
+) {
+    let (r1cs, removed) = timings.time("r1cs", || {
+        let Ok(r1cs) = R1csBackend.lower(circuit);
+        let mut r1cs = match order {
+            Some(order) => r1cs.reordered(order),
+            None => r1cs,
+        };
+        let removed = if options.opt_level != OptLevel::O0 {
+            r1cs.dedup_constraints()
+        } else {
+            Vec::new()
+        };
+        (r1cs, removed)
+    });
+    if !removed.is_empty() {
+        log::info!("\nRemoved {} duplicate constraints", removed.len());
+    }
     let r1cs_filename = format!("circuit/{}.r1cs", base_name);
     match r1cs.save_to_file(&r1cs_filename) {
-        Ok(()) => println!("Saved R1CS to {}", r1cs_filename),
-        Err(err) => eprintln!("Error saving R1CS: {}", err),
+        Ok(()) => log::info!("Saved R1CS to {}", r1cs_filename),
+        Err(err) => log::error!("Error saving R1CS: {}", err),
     }
 
-    if let Some(inputs_file) = inputs_filename {
-        println!("\n=== CALCULATING WITNESS ===");
+    // the constraints dedup_constraints dropped are left out and the rest
+    // numbered as in the saved R1CS
+    let mut source_map = circuit.source_map(gate_spans);
+    source_map.retain(|entry| !removed.contains(&entry.constraint));
+    for (index, entry) in source_map.iter_mut().enumerate() {
+        entry.constraint = index;
+    }
+    let map_filename = format!("circuit/{}.r1cs.map", base_name);
+    let map = serde_json::json!({
+        "source": filename,
+        "constraints": source_map,
+    });
+    match serde_json::to_string_pretty(&map)
+        .map_err(|err| err.to_string())
+        .and_then(|json| fs::write(&map_filename, json).map_err(|err| err.to_string()))
+    {
+        Ok(()) => log::info!("Saved source map to {}", map_filename),
+        Err(err) => log::error!("Error saving source map: {}", err),
+    }
 
-        let inputs_content = match fs::read_to_string(inputs_file) {
-            Ok(content) => content,
-            Err(err) => {
-                eprintln!("Error reading inputs file: {}", err);
-                process::exit(1);
+    if options.explain {
+        log::info!("\n=== R1CS EXPLAINED ===");
+        // explains the saved R1CS: its wire numbering and the constraints
+        // left after deduplication
+        let gates: Vec<usize> = circuit
+            .constraint_gates()
+            .into_iter()
+            .enumerate()
+            .filter(|(constraint, _)| !removed.contains(constraint))
+            .map(|(_, gate)| gate)
+            .collect();
+        let explained = circuit.explain_r1cs(&r1cs, &gates);
+        for (line, entry) in explained.iter().zip(&source_map) {
+            match &entry.span {
+                Some(span) => log::info!("{:>4}: {} (source {})", entry.constraint, line, span),
+                None => log::info!("{:>4}: {}", entry.constraint, line),
             }
-        };
+        }
+    }
 
-        let inputs: InputFile = match toml::from_str(&inputs_content) {
-            Ok(inputs) => inputs,
-            Err(err) => {
-                eprintln!("Error parsing inputs file: {}", err);
-                process::exit(1);
+    if options.sparsity_json {
+        let sparsity_filename = format!("circuit/{}.sparsity.json", base_name);
+        match serde_json::to_string(&r1cs.sparsity())
+            .map_err(|err| err.to_string())
+            .and_then(|json| fs::write(&sparsity_filename, json).map_err(|err| err.to_string()))
+        {
+            Ok(()) => log::info!("Saved sparsity to {}", sparsity_filename),
+            Err(err) => log::error!("Error saving sparsity: {}", err),
+        }
+    }
+
+    let circom_json_filename = format!("circuit/{}_constraints.json", base_name);
+    let circom_sym_filename = format!("circuit/{}.sym", base_name);
+    match r1cs.save_circom_files(&circom_json_filename, &circom_sym_filename) {
+        Ok(()) => log::info!(
+            "Saved circom constraints to {} and {}",
+            circom_json_filename,
+            circom_sym_filename
+        ),
+        Err(err) => log::error!("Error saving circom constraints: {}", err),
+    }
+}
+
+// lowers to a Plonkish table and saves it as circuit/<name>.plonk.json
+fn emit_plonkish(circuit: &Circuit, base_name: &str, timings: &mut Timings) {
+    let table = match timings.time("plonkish", || PlonkishBackend.lower(circuit)) {
+        Ok(table) => table,
+        Err(err) => {
+            log::error!("\n=== PLONKISH ERROR ===");
+            log::error!("{}", err);
+            process::exit(1);
+        }
+    };
+    log::info!("\nPlonkish rows: {}", table.rows.len());
+    let table_filename = format!("circuit/{}.plonk.json", base_name);
+    match table.save_to_file(&table_filename) {
+        Ok(()) => log::info!("Saved Plonkish table to {}", table_filename),
+        Err(err) => log::error!("Error saving Plonkish table: {}", err),
+    }
+}
+
+// the [pin] section of an inputs file, as constants for the folder
+fn load_pins(inputs_file: &str) -> HashMap<String, FieldElement> {
+    let inputs = match InputFile::load_from_file(inputs_file) {
+        Ok(inputs) => inputs,
+        Err(err) => {
+            log::error!("Error reading inputs file '{}': {}", inputs_file, err);
+            process::exit(1);
+        }
+    };
+
+    inputs.pin.unwrap_or_default()
+}
+
+// `source` is the program the circuit was compiled from, when available its
+// interpreted result is compared against the witness; `order` is the wire
+// order the witness is saved in; the calculation itself is timed as the
+// "witness" stage
+fn calculate_witness(
+    circuit: &Circuit,
+    inputs_file: &str,
+    base_name: &str,
+    source: Option<&Program>,
+    order: Option<&[usize]>,
+    witness_options: WitnessOptions,
+    timings: &mut Timings,
+) {
+    log::info!("\n=== CALCULATING WITNESS ===");
+
+    let inputs = match InputFile::load_from_file(inputs_file) {
+        Ok(inputs) => inputs,
+        Err(err) => {
+            log::error!("Error reading inputs file '{}': {}", inputs_file, err);
+            process::exit(1);
+        }
+    };
+
+    let mut calculator = WitnessCalculator::with_threads(witness_options.threads);
+    let outcome = timings.time("witness", || {
+        calculator.calculate_witness(circuit, inputs.clone())
+    });
+    if witness_options.dump_wires {
+        log::debug!("\n=== WIRE VALUES ===");
+        for (id, label, value) in calculator.dump_wire_values(circuit) {
+            match label {
+                Some(label) => log::debug!("w{} ({}) = {}", id, label, value),
+                None => log::debug!("w{} = {}", id, value),
             }
-        };
+        }
+        log::debug!("");
+    }
+    match outcome {
+        Ok(result) => {
+            log::info!("Witness calculation complete");
+            log::info!("Result: {}", result);
 
-        let mut calculator = WitnessCalculator::new();
-        match calculator.calculate_witness(&circuit_after, inputs) {
-            Ok(result) => {
-                println!("Witness calculation complete");
-                println!("Result: {}", result);
+            if let Some(program) = source {
+                match interpret(program, &inputs) {
+                    Ok(expected) if expected == result => {
+                        log::info!("Interpreter check: result matches")
+                    }
+                    Ok(expected) => log::error!(
+                        "Interpreter check: interpreter returned {}, circuit returned {}",
+                        expected,
+                        result
+                    ),
+                    Err(err) => log::error!("Interpreter check: {}", err),
+                }
+            }
 
-                let witness_filename = format!("circuit/{}.witness", base_name);
-                match calculator.save_r1cs_witness(&circuit_after, &witness_filename, result) {
-                    Ok(()) => println!("Saved witness to {}", witness_filename),
-                    Err(err) => eprintln!("Error saving witness: {}", err),
+            let r1cs = circuit.to_r1cs();
+            let evaluations = r1cs.evaluate(&calculator.witness_vector());
+            let failed: Vec<_> = evaluations.iter().filter(|eval| !eval.satisfied).collect();
+            if failed.is_empty() {
+                log::info!(
+                    "R1CS check: all {} constraints satisfied",
+                    evaluations.len()
+                );
+            } else {
+                log::error!("R1CS check: {} constraints not satisfied", failed.len());
+                for eval in failed {
+                    log::error!("  {}", eval);
                 }
             }
-            Err(err) => {
-                eprintln!("Witness calculation error: {}", err);
-                process::exit(1);
+
+            let witness_filename = format!("circuit/{}.witness", base_name);
+            match calculator.save_r1cs_witness(circuit, &witness_filename, result, order) {
+                Ok(()) => log::info!("Saved witness to {}", witness_filename),
+                Err(err) => log::error!("Error saving witness: {}", err),
             }
         }
+        Err(err) => {
+            log::error!("Witness calculation error: {}", err);
+            process::exit(1);
+        }
     }
 }
@@ -1,8 +1,17 @@
+use crate::ast::Span;
+use crate::field::FieldElement;
 use crate::ssa::{SsaInstruction, SsaProgram, SsaValue};
-use std::collections::HashMap;
+use crate::timings::Timings;
+use std::collections::{HashMap, HashSet};
 
 pub struct ConstantFolder {
-    constants: HashMap<SsaValue, i32>,
+    constants: HashMap<SsaValue, FieldElement>,
+}
+
+impl Default for ConstantFolder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ConstantFolder {
@@ -26,19 +35,33 @@ impl ConstantFolder {
             return_value: ssa_program.return_value,
             public_inputs: ssa_program.public_inputs,
             private_inputs: ssa_program.private_inputs,
+            spans: ssa_program.spans,
         }
     }
 }
 
 impl ConstantFolder {
-    fn get_constant_value(&self, ssa_value: &SsaValue) -> Option<i32> {
+    fn get_constant_value(&self, ssa_value: &SsaValue) -> Option<FieldElement> {
         self.constants.get(ssa_value).copied()
     }
 
-    fn record_constant(&mut self, ssa_value: SsaValue, value: i32) {
+    fn record_constant(&mut self, ssa_value: SsaValue, value: FieldElement) {
         self.constants.insert(ssa_value, value);
     }
 
+    // None unless both operands are constants
+    fn fold_binary(
+        &self,
+        left: &SsaValue,
+        right: &SsaValue,
+        op: fn(FieldElement, FieldElement) -> FieldElement,
+    ) -> Option<FieldElement> {
+        Some(op(
+            self.get_constant_value(left)?,
+            self.get_constant_value(right)?,
+        ))
+    }
+
     fn try_fold_instruction(&mut self, instr: &SsaInstruction) -> SsaInstruction {
         match instr {
             SsaInstruction::Const(dest, value) => {
@@ -46,11 +69,25 @@ impl ConstantFolder {
                 instr.clone()
             }
             SsaInstruction::Add(dest, left, right) => {
-                if let (Some(left_val), Some(right_val)) = (
-                    self.get_constant_value(left),
-                    self.get_constant_value(right),
-                ) {
-                    let result = left_val + right_val;
+                if let Some(result) = self.fold_binary(left, right, |a, b| a + b) {
+                    self.record_constant(dest.clone(), result);
+
+                    SsaInstruction::Const(dest.clone(), result)
+                } else {
+                    instr.clone()
+                }
+            }
+            SsaInstruction::Sub(dest, left, right) => {
+                if let Some(result) = self.fold_binary(left, right, |a, b| a - b) {
+                    self.record_constant(dest.clone(), result);
+
+                    SsaInstruction::Const(dest.clone(), result)
+                } else {
+                    instr.clone()
+                }
+            }
+            SsaInstruction::Neg(dest, operand) => {
+                if let Some(result) = self.get_constant_value(operand).map(|value| -value) {
                     self.record_constant(dest.clone(), result);
 
                     SsaInstruction::Const(dest.clone(), result)
@@ -59,11 +96,41 @@ impl ConstantFolder {
                 }
             }
             SsaInstruction::Mul(dest, left, right) => {
-                if let (Some(left_val), Some(right_val)) = (
-                    self.get_constant_value(left),
-                    self.get_constant_value(right),
-                ) {
-                    let result = left_val * right_val;
+                if let Some(result) = self.fold_binary(left, right, |a, b| a * b) {
+                    self.record_constant(dest.clone(), result);
+
+                    SsaInstruction::Const(dest.clone(), result)
+                } else {
+                    instr.clone()
+                }
+            }
+            SsaInstruction::Square(dest, operand) => {
+                if let Some(result) = self.fold_binary(operand, operand, |a, b| a * b) {
+                    self.record_constant(dest.clone(), result);
+
+                    SsaInstruction::Const(dest.clone(), result)
+                } else {
+                    instr.clone()
+                }
+            }
+            SsaInstruction::IsZero(dest, operand) => {
+                if let Some(operand_val) = self.get_constant_value(operand) {
+                    let result = if operand_val.is_zero() {
+                        FieldElement::one()
+                    } else {
+                        FieldElement::zero()
+                    };
+                    self.record_constant(dest.clone(), result);
+
+                    SsaInstruction::Const(dest.clone(), result)
+                } else {
+                    instr.clone()
+                }
+            }
+            // constants are field elements now, so a constant inverse folds like
+            // any other operation; inv(0) is left for the witness to reject
+            SsaInstruction::Inv(dest, operand) => {
+                if let Some(result) = self.get_constant_value(operand).and_then(|v| v.inverse()) {
                     self.record_constant(dest.clone(), result);
 
                     SsaInstruction::Const(dest.clone(), result)
@@ -72,16 +139,554 @@ impl ConstantFolder {
                 }
             }
             SsaInstruction::Assert(_left, _right) => instr.clone(),
+            SsaInstruction::RangeCheck(_, _) => instr.clone(),
+        }
+    }
+}
+
+// Rewrites instructions that cancel to zero regardless of their runtime
+// operands: `x - x` and `x + (-x)`. Also turns `x * x` into a Square.
+pub struct PeepholeOptimizer {
+    negations: HashMap<SsaValue, SsaValue>, // value -> the value it negates
+}
+
+impl Default for PeepholeOptimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PeepholeOptimizer {
+    pub fn new() -> Self {
+        Self {
+            negations: HashMap::new(),
+        }
+    }
+
+    pub fn optimize(ssa_program: SsaProgram) -> SsaProgram {
+        let mut peephole = PeepholeOptimizer::new();
+        let mut optimized_instructions = Vec::new();
+
+        for instr in &ssa_program.instructions {
+            optimized_instructions.push(peephole.rewrite_instruction(instr));
+        }
+
+        SsaProgram {
+            instructions: optimized_instructions,
+            return_value: ssa_program.return_value,
+            public_inputs: ssa_program.public_inputs,
+            private_inputs: ssa_program.private_inputs,
+            spans: ssa_program.spans,
+        }
+    }
+}
+
+impl PeepholeOptimizer {
+    fn is_negation_of(&self, value: &SsaValue, other: &SsaValue) -> bool {
+        self.negations.get(value) == Some(other)
+    }
+
+    fn rewrite_instruction(&mut self, instr: &SsaInstruction) -> SsaInstruction {
+        match instr {
+            SsaInstruction::Neg(dest, operand) => {
+                self.negations.insert(dest.clone(), operand.clone());
+                instr.clone()
+            }
+            // x - x = 0
+            SsaInstruction::Sub(dest, left, right) if left == right => {
+                SsaInstruction::Const(dest.clone(), FieldElement::zero())
+            }
+            // x + (-x) = 0 and (-x) + x = 0
+            SsaInstruction::Add(dest, left, right)
+                if self.is_negation_of(right, left) || self.is_negation_of(left, right) =>
+            {
+                SsaInstruction::Const(dest.clone(), FieldElement::zero())
+            }
+            SsaInstruction::Mul(dest, left, right) if left == right => {
+                SsaInstruction::Square(dest.clone(), left.clone())
+            }
+            _ => instr.clone(),
+        }
+    }
+}
+
+// Reuses the result of an earlier identical computation instead of recomputing it.
+pub struct CommonSubexpressionEliminator;
+
+impl CommonSubexpressionEliminator {
+    pub fn eliminate(ssa_program: SsaProgram) -> SsaProgram {
+        let mut seen: HashMap<(&'static str, Vec<SsaValue>, Option<FieldElement>), SsaValue> =
+            HashMap::new();
+        let mut renames: HashMap<SsaValue, SsaValue> = HashMap::new();
+        let mut optimized_instructions = Vec::new();
+
+        for instr in &ssa_program.instructions {
+            let instr = rename_operands(instr, &renames);
+            let (dest, key) = match &instr {
+                SsaInstruction::Const(dest, value) => (dest, ("const", vec![], Some(*value))),
+                // commutative, so operand order doesn't matter
+                SsaInstruction::Add(dest, left, right) => {
+                    (dest, ("add", sorted(left, right), None))
+                }
+                SsaInstruction::Mul(dest, left, right) => {
+                    (dest, ("mul", sorted(left, right), None))
+                }
+                // keyed like the Mul it replaces, so x * x and square(x) are shared
+                SsaInstruction::Square(dest, operand) => {
+                    (dest, ("mul", sorted(operand, operand), None))
+                }
+                SsaInstruction::Sub(dest, left, right) => {
+                    (dest, ("sub", vec![left.clone(), right.clone()], None))
+                }
+                SsaInstruction::Neg(dest, operand) => (dest, ("neg", vec![operand.clone()], None)),
+                SsaInstruction::Inv(dest, operand) => (dest, ("inv", vec![operand.clone()], None)),
+                SsaInstruction::IsZero(dest, operand) => {
+                    (dest, ("is_zero", vec![operand.clone()], None))
+                }
+                SsaInstruction::Assert(_, _) | SsaInstruction::RangeCheck(_, _) => {
+                    optimized_instructions.push(instr);
+                    continue;
+                }
+            };
+
+            if let Some(existing) = seen.get(&key) {
+                renames.insert(dest.clone(), existing.clone());
+            } else {
+                seen.insert(key, dest.clone());
+                optimized_instructions.push(instr);
+            }
+        }
+
+        SsaProgram {
+            instructions: optimized_instructions,
+            return_value: renamed(&ssa_program.return_value, &renames),
+            public_inputs: ssa_program.public_inputs,
+            private_inputs: ssa_program.private_inputs,
+            spans: ssa_program.spans,
         }
     }
 }
 
+// Drops asserts that can't fail: `assert x == x`, asserts between two equal
+// constants, and repeats of an assert already made. An assert between two
+// different constants is kept for check_constant_asserts to report.
+pub struct RedundantAssertEliminator;
+
+// an assert operand, constants compared by value so that two Const
+// instructions holding 5 count as the same operand
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum AssertOperand {
+    Constant(u64),
+    Value(SsaValue),
+}
+
+impl RedundantAssertEliminator {
+    pub fn eliminate(ssa_program: SsaProgram) -> SsaProgram {
+        let mut constants: HashMap<SsaValue, FieldElement> = HashMap::new();
+        let mut seen: HashSet<Vec<AssertOperand>> = HashSet::new();
+        let mut optimized_instructions = Vec::new();
+
+        for instr in ssa_program.instructions {
+            match &instr {
+                SsaInstruction::Const(dest, value) => {
+                    constants.insert(dest.clone(), *value);
+                }
+                SsaInstruction::Assert(left, right) => {
+                    let operand = |value: &SsaValue| match constants.get(value) {
+                        Some(constant) => AssertOperand::Constant(constant.as_u64()),
+                        None => AssertOperand::Value(value.clone()),
+                    };
+                    let (left, right) = (operand(left), operand(right));
+                    let both_constant = matches!(
+                        (&left, &right),
+                        (AssertOperand::Constant(_), AssertOperand::Constant(_))
+                    );
+                    if left == right {
+                        continue;
+                    }
+                    // a false one is kept as it is, repeats included, so
+                    // check_constant_asserts still sees it
+                    if !both_constant {
+                        let mut key = vec![left, right];
+                        key.sort();
+                        if !seen.insert(key) {
+                            continue;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            optimized_instructions.push(instr);
+        }
+
+        SsaProgram {
+            instructions: optimized_instructions,
+            return_value: ssa_program.return_value,
+            public_inputs: ssa_program.public_inputs,
+            private_inputs: ssa_program.private_inputs,
+            spans: ssa_program.spans,
+        }
+    }
+}
+
+// Fails on the first assert between two different constants, which no
+// witness could satisfy. Runs at every level: at -O0 it only sees literal
+// operands, with folding also computed ones like `assert 2 + 3 == 6`.
+pub fn check_constant_asserts(ssa_program: &SsaProgram) -> Result<(), ConstantAssertError> {
+    let mut constants: HashMap<&SsaValue, FieldElement> = HashMap::new();
+    for instr in &ssa_program.instructions {
+        match instr {
+            SsaInstruction::Const(dest, value) => {
+                constants.insert(dest, *value);
+            }
+            SsaInstruction::Assert(left, right) => {
+                if let (Some(left_val), Some(right_val)) =
+                    (constants.get(left), constants.get(right))
+                {
+                    if left_val != right_val {
+                        return Err(ConstantAssertError {
+                            left: *left_val,
+                            right: *right_val,
+                            span: ssa_program.spans.get(left).cloned(),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct ConstantAssertError {
+    pub left: FieldElement,
+    pub right: FieldElement,
+    // source of the left operand, if it still has one
+    pub span: Option<Span>,
+}
+
+impl std::fmt::Display for ConstantAssertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "assert {} == {} compares two different constants, no witness can satisfy it",
+            self.left, self.right
+        )?;
+        if let Some(span) = &self.span {
+            write!(f, " at {}", span)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConstantAssertError {}
+
+fn sorted(left: &SsaValue, right: &SsaValue) -> Vec<SsaValue> {
+    let mut operands = vec![left.clone(), right.clone()];
+    operands.sort();
+    operands
+}
+
+// Applies identities that don't need both operands to be constant:
+// x + 0 = x, x - 0 = x, x * 1 = x, x * 0 = 0.
+pub struct AlgebraicSimplifier {
+    constants: HashMap<SsaValue, FieldElement>,
+    renames: HashMap<SsaValue, SsaValue>,
+}
+
+impl Default for AlgebraicSimplifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AlgebraicSimplifier {
+    pub fn new() -> Self {
+        Self {
+            constants: HashMap::new(),
+            renames: HashMap::new(),
+        }
+    }
+
+    pub fn optimize(ssa_program: SsaProgram) -> SsaProgram {
+        let mut simplifier = AlgebraicSimplifier::new();
+        let mut optimized_instructions = Vec::new();
+
+        for instr in &ssa_program.instructions {
+            let instr = rename_operands(instr, &simplifier.renames);
+            if let Some(simplified) = simplifier.simplify_instruction(instr) {
+                optimized_instructions.push(simplified);
+            }
+        }
+
+        SsaProgram {
+            instructions: optimized_instructions,
+            return_value: renamed(&ssa_program.return_value, &simplifier.renames),
+            public_inputs: ssa_program.public_inputs,
+            private_inputs: ssa_program.private_inputs,
+            spans: ssa_program.spans,
+        }
+    }
+}
+
+impl AlgebraicSimplifier {
+    fn is_constant(&self, value: &SsaValue, expected: i32) -> bool {
+        self.constants.get(value) == Some(&FieldElement::from(expected))
+    }
+
+    // None drops the instruction, its destination is renamed to the surviving operand
+    fn simplify_instruction(&mut self, instr: SsaInstruction) -> Option<SsaInstruction> {
+        match &instr {
+            SsaInstruction::Const(dest, value) => {
+                self.constants.insert(dest.clone(), *value);
+            }
+            SsaInstruction::Add(dest, left, right) => {
+                if self.is_constant(right, 0) {
+                    self.renames.insert(dest.clone(), left.clone());
+                    return None;
+                }
+                if self.is_constant(left, 0) {
+                    self.renames.insert(dest.clone(), right.clone());
+                    return None;
+                }
+            }
+            SsaInstruction::Sub(dest, left, right) if self.is_constant(right, 0) => {
+                self.renames.insert(dest.clone(), left.clone());
+                return None;
+            }
+            SsaInstruction::Mul(dest, left, right) => {
+                if self.is_constant(left, 0) || self.is_constant(right, 0) {
+                    self.constants.insert(dest.clone(), FieldElement::zero());
+                    return Some(SsaInstruction::Const(dest.clone(), FieldElement::zero()));
+                }
+                if self.is_constant(right, 1) {
+                    self.renames.insert(dest.clone(), left.clone());
+                    return None;
+                }
+                if self.is_constant(left, 1) {
+                    self.renames.insert(dest.clone(), right.clone());
+                    return None;
+                }
+            }
+            _ => {}
+        }
+        Some(instr)
+    }
+}
+
+fn renamed(value: &SsaValue, renames: &HashMap<SsaValue, SsaValue>) -> SsaValue {
+    renames.get(value).cloned().unwrap_or_else(|| value.clone())
+}
+
+fn rename_operands(
+    instr: &SsaInstruction,
+    renames: &HashMap<SsaValue, SsaValue>,
+) -> SsaInstruction {
+    let r = |value: &SsaValue| renamed(value, renames);
+    match instr {
+        SsaInstruction::Const(_, _) => instr.clone(),
+        SsaInstruction::Add(dest, left, right) => {
+            SsaInstruction::Add(dest.clone(), r(left), r(right))
+        }
+        SsaInstruction::Sub(dest, left, right) => {
+            SsaInstruction::Sub(dest.clone(), r(left), r(right))
+        }
+        SsaInstruction::Mul(dest, left, right) => {
+            SsaInstruction::Mul(dest.clone(), r(left), r(right))
+        }
+        SsaInstruction::Square(dest, operand) => SsaInstruction::Square(dest.clone(), r(operand)),
+        SsaInstruction::Neg(dest, operand) => SsaInstruction::Neg(dest.clone(), r(operand)),
+        SsaInstruction::Inv(dest, operand) => SsaInstruction::Inv(dest.clone(), r(operand)),
+        SsaInstruction::IsZero(dest, operand) => SsaInstruction::IsZero(dest.clone(), r(operand)),
+        SsaInstruction::Assert(left, right) => SsaInstruction::Assert(r(left), r(right)),
+        SsaInstruction::RangeCheck(value, bits) => SsaInstruction::RangeCheck(r(value), *bits),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptLevel {
+    O0, // no passes
+    O1, // peephole, constant folding, redundant assert removal, dead code elimination
+    O2, // O1 plus algebraic simplification and CSE, repeated until nothing changes
+}
+
+impl OptLevel {
+    pub fn from_flag(flag: &str) -> Option<OptLevel> {
+        match flag {
+            "-O0" => Some(OptLevel::O0),
+            "-O1" => Some(OptLevel::O1),
+            "-O2" => Some(OptLevel::O2),
+            _ => None,
+        }
+    }
+}
+
+pub fn optimize(ssa_program: SsaProgram, level: OptLevel) -> SsaProgram {
+    optimize_timed(ssa_program, level, &mut Timings::new())
+}
+
+// optimize, recording the time spent in each pass
+pub fn optimize_timed(
+    ssa_program: SsaProgram,
+    level: OptLevel,
+    timings: &mut Timings,
+) -> SsaProgram {
+    match level {
+        OptLevel::O0 => ssa_program,
+        OptLevel::O1 => run_o1_passes(ssa_program, timings),
+        OptLevel::O2 => {
+            let mut program = ssa_program;
+            loop {
+                let before = program.instructions.len();
+                program = run_o1_passes(program, timings);
+                program = timings.time("algebraic simplification", || {
+                    AlgebraicSimplifier::optimize(program)
+                });
+                program = timings.time("cse", || CommonSubexpressionEliminator::eliminate(program));
+                program = timings.time("dce", || DeadCodeEliminator::eliminate(program));
+                if program.instructions.len() == before {
+                    return program;
+                }
+            }
+        }
+    }
+}
+
+fn run_o1_passes(ssa_program: SsaProgram, timings: &mut Timings) -> SsaProgram {
+    // peephole first so the zeros it produces get folded further
+    let peephole_ssa = timings.time("peephole", || PeepholeOptimizer::optimize(ssa_program));
+    let folded_ssa = timings.time("constant folding", || {
+        ConstantFolder::optimize(peephole_ssa)
+    });
+    // after folding, so asserts between computed constants are caught too
+    let checked_ssa = timings.time("redundant asserts", || {
+        RedundantAssertEliminator::eliminate(folded_ssa)
+    });
+    timings.time("dce", || DeadCodeEliminator::eliminate(checked_ssa))
+}
+
+// Replaces pinned public inputs with constants so the folder can fold through
+// them; a pinned input is no longer an input of the circuit.
+pub fn pin_inputs(
+    ssa_program: SsaProgram,
+    pins: &std::collections::HashMap<String, FieldElement>,
+) -> Result<SsaProgram, PinError> {
+    // sorted, so the name reported is the same on every run
+    let mut names: Vec<&String> = pins.keys().collect();
+    names.sort();
+    for name in names {
+        if !ssa_program
+            .public_inputs
+            .iter()
+            .any(|input| input.name == *name)
+        {
+            let private = ssa_program
+                .private_inputs
+                .iter()
+                .any(|input| input.name == *name);
+            return Err(PinError {
+                name: name.clone(),
+                private,
+            });
+        }
+    }
+
+    let (pinned, public_inputs): (Vec<SsaValue>, Vec<SsaValue>) = ssa_program
+        .public_inputs
+        .into_iter()
+        .partition(|input| pins.contains_key(&input.name));
+
+    let mut instructions: Vec<SsaInstruction> = pinned
+        .into_iter()
+        .map(|input| {
+            let value = pins[&input.name];
+            SsaInstruction::Const(input, value)
+        })
+        .collect();
+    instructions.extend(ssa_program.instructions);
+
+    Ok(SsaProgram {
+        instructions,
+        return_value: ssa_program.return_value,
+        public_inputs,
+        private_inputs: ssa_program.private_inputs,
+        spans: ssa_program.spans,
+    })
+}
+
+// a [pin] entry that doesn't name a public input of the program
+#[derive(Debug)]
+pub struct PinError {
+    pub name: String,
+    // it names a private input, which can't be pinned
+    pub private: bool,
+}
+
+impl std::fmt::Display for PinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.private {
+            write!(
+                f,
+                "Cannot pin {}: it is a private input, only public inputs can be pinned",
+                self.name
+            )
+        } else {
+            write!(
+                f,
+                "Cannot pin {}: the program has no public input of that name",
+                self.name
+            )
+        }
+    }
+}
+
+impl std::error::Error for PinError {}
+
+// Drops declared inputs that no instruction reads and that aren't returned,
+// so they take no wire in the circuit; DeadCodeEliminator keeps every input.
+// Returns the program and the names of the dropped inputs. Inputs feeding an
+// assert or a range check are read, so they are kept.
+pub fn prune_unused_inputs(ssa_program: SsaProgram) -> (SsaProgram, Vec<String>) {
+    let mut read: HashSet<SsaValue> = ssa_program
+        .instructions
+        .iter()
+        .flat_map(DeadCodeEliminator::get_inputs)
+        .collect();
+    read.insert(ssa_program.return_value.clone());
+
+    let mut pruned = Vec::new();
+    let mut keep = |inputs: Vec<SsaValue>| -> Vec<SsaValue> {
+        inputs
+            .into_iter()
+            .filter(|input| {
+                let used = read.contains(input);
+                if !used {
+                    pruned.push(input.name.clone());
+                }
+                used
+            })
+            .collect()
+    };
+    let public_inputs = keep(ssa_program.public_inputs);
+    let private_inputs = keep(ssa_program.private_inputs);
+
+    let program = SsaProgram {
+        instructions: ssa_program.instructions,
+        return_value: ssa_program.return_value,
+        public_inputs,
+        private_inputs,
+        spans: ssa_program.spans,
+    };
+    (program, pruned)
+}
+
 pub struct DeadCodeEliminator;
 
 impl DeadCodeEliminator {
     pub fn eliminate(ssa_program: SsaProgram) -> SsaProgram {
-        let mut used_values = std::collections::HashSet::new();
-        let mut input_dependent = std::collections::HashSet::new();
+        let mut used_values = HashSet::new();
+        let mut input_dependent = HashSet::new();
 
         // all inputs are used and input-dependent
         for input in &ssa_program.public_inputs {
@@ -93,8 +698,15 @@ impl DeadCodeEliminator {
             input_dependent.insert(input.clone());
         }
 
-        // return value is always used
+        // the return value and the operands of asserts and range checks are
+        // the roots: an input only read by `assert x == 5` must stay, along
+        // with everything the check is computed from
         used_values.insert(ssa_program.return_value.clone());
+        for instr in &ssa_program.instructions {
+            if Self::get_destination(instr).is_none() {
+                used_values.extend(Self::get_inputs(instr));
+            }
+        }
 
         // all values that transitively depend on inputs
         let mut changed = true;
@@ -106,24 +718,22 @@ impl DeadCodeEliminator {
                 if let Some(dest) = Self::get_destination(instr) {
                     // if any input to this instruction depends on circuit inputs,
                     // then this instruction's output also depends on circuit inputs
-                    if inputs.iter().any(|input| input_dependent.contains(input)) {
-                        if input_dependent.insert(dest) {
-                            changed = true;
-                        }
-                    }
-                } else {
-                    for input in inputs {
-                        if input_dependent.insert(input) {
-                            changed = true;
-                        }
+                    if inputs.iter().any(|input| input_dependent.contains(input))
+                        && input_dependent.insert(dest)
+                    {
+                        changed = true;
                     }
                 }
             }
         }
 
-        // all input-dependent values are used
+        // all input-dependent values are used, except those only computed
+        // for a `let _` binding
+        let discard_only = Self::discard_only(&ssa_program, &used_values);
         for value in &input_dependent {
-            used_values.insert(value.clone());
+            if !discard_only.contains(value) {
+                used_values.insert(value.clone());
+            }
         }
 
         // backwards reachability from used values
@@ -139,12 +749,6 @@ impl DeadCodeEliminator {
                             }
                         }
                     }
-                } else {
-                    for input in Self::get_inputs(instr) {
-                        if used_values.insert(input) {
-                            changed = true;
-                        }
-                    }
                 }
             }
         }
@@ -166,15 +770,58 @@ impl DeadCodeEliminator {
             return_value: ssa_program.return_value,
             public_inputs: ssa_program.public_inputs,
             private_inputs: ssa_program.private_inputs,
+            spans: ssa_program.spans,
+        }
+    }
+
+    // Values whose only purpose is a `let _` binding: the discarded value
+    // itself when nothing reads it, and whatever only such values read. Roots
+    // are never in it, so the operands of asserts inside the expression
+    // survive; neither is an inverse, which constrains its operand to be
+    // nonzero.
+    fn discard_only(ssa_program: &SsaProgram, roots: &HashSet<SsaValue>) -> HashSet<SsaValue> {
+        let mut readers: HashMap<SsaValue, Vec<SsaValue>> = HashMap::new();
+        for instr in &ssa_program.instructions {
+            if let Some(dest) = Self::get_destination(instr) {
+                for input in Self::get_inputs(instr) {
+                    readers.entry(input).or_default().push(dest.clone());
+                }
+            }
+        }
+
+        // readers come after the value they read, so walking backwards
+        // decides every reader first
+        let mut discard_only = HashSet::new();
+        for instr in ssa_program.instructions.iter().rev() {
+            let Some(dest) = Self::get_destination(instr) else {
+                continue;
+            };
+            if roots.contains(&dest) || matches!(instr, SsaInstruction::Inv(_, _)) {
+                continue;
+            }
+            let only_discarded = match readers.get(&dest) {
+                Some(readers) => readers.iter().all(|reader| discard_only.contains(reader)),
+                None => dest.is_discarded(),
+            };
+            if only_discarded {
+                discard_only.insert(dest);
+            }
         }
+        discard_only
     }
 
     fn get_destination(instr: &SsaInstruction) -> Option<SsaValue> {
         match instr {
             SsaInstruction::Const(dest, _) => Some(dest.clone()),
             SsaInstruction::Add(dest, _, _) => Some(dest.clone()),
+            SsaInstruction::Sub(dest, _, _) => Some(dest.clone()),
+            SsaInstruction::Neg(dest, _) => Some(dest.clone()),
+            SsaInstruction::Inv(dest, _) => Some(dest.clone()),
+            SsaInstruction::IsZero(dest, _) => Some(dest.clone()),
             SsaInstruction::Mul(dest, _, _) => Some(dest.clone()),
+            SsaInstruction::Square(dest, _) => Some(dest.clone()),
             SsaInstruction::Assert(_, _) => None,
+            SsaInstruction::RangeCheck(_, _) => None,
         }
     }
 
@@ -182,8 +829,304 @@ impl DeadCodeEliminator {
         match instr {
             SsaInstruction::Const(_, _) => vec![],
             SsaInstruction::Add(_, left, right) => vec![left.clone(), right.clone()],
+            SsaInstruction::Sub(_, left, right) => vec![left.clone(), right.clone()],
+            SsaInstruction::Neg(_, operand) => vec![operand.clone()],
+            SsaInstruction::Inv(_, operand) => vec![operand.clone()],
+            SsaInstruction::IsZero(_, operand) => vec![operand.clone()],
             SsaInstruction::Mul(_, left, right) => vec![left.clone(), right.clone()],
+            SsaInstruction::Square(_, operand) => vec![operand.clone()],
             SsaInstruction::Assert(left, right) => vec![left.clone(), right.clone()],
+            SsaInstruction::RangeCheck(value, _) => vec![value.clone()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Gate;
+    use crate::compiler::parse_str;
+    use crate::ssa::SsaBuilder;
+    use crate::witness::{InputFile, WitnessCalculator, WitnessError};
+
+    fn convert(source: &str) -> SsaProgram {
+        SsaBuilder::new()
+            .convert(parse_str(source).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn multiplication_by_two_stays_a_multiplication() {
+        let optimized = optimize(convert("public x\nreturn x * 2"), OptLevel::O1);
+        assert!(optimized
+            .instructions
+            .iter()
+            .any(|instr| matches!(instr, SsaInstruction::Mul(..))));
+        assert!(!optimized
+            .instructions
+            .iter()
+            .any(|instr| matches!(instr, SsaInstruction::Add(..))));
+    }
+
+    // the peephole pass alone, so nothing else gets to fold the result first
+    fn peephole_return(source: &str) -> SsaInstruction {
+        let program = PeepholeOptimizer::optimize(convert(source));
+        program
+            .instructions
+            .into_iter()
+            .find(|instr| {
+                DeadCodeEliminator::get_destination(instr).as_ref() == Some(&program.return_value)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn peephole_cancels_x_minus_x() {
+        assert!(matches!(
+            peephole_return("public x\nreturn x - x"),
+            SsaInstruction::Const(_, value) if value.is_zero()
+        ));
+    }
+
+    #[test]
+    fn peephole_cancels_x_plus_minus_x() {
+        for source in ["public x\nreturn x + -x", "public x\nreturn -x + x"] {
+            assert!(matches!(
+                peephole_return(source),
+                SsaInstruction::Const(_, value) if value.is_zero()
+            ));
+        }
+    }
+
+    #[test]
+    fn subtraction_and_negation_lower_without_a_multiplication() {
+        for source in ["public x\nprivate y\nreturn x - y", "public x\nreturn -x"] {
+            let circuit = crate::compiler::compile(source, OptLevel::O0).unwrap();
+            assert_eq!(circuit.gates.len(), 1, "{}", source);
+            assert!(!matches!(
+                circuit.gates[0],
+                Gate::Mul { .. } | Gate::Const { .. }
+            ));
+            let constraint = &circuit.to_r1cs().constraints[0];
+            assert!(constraint
+                .a
+                .iter()
+                .any(|(_, coeff)| *coeff == -FieldElement::one()));
+        }
+    }
+
+    #[test]
+    fn o0_keeps_the_unoptimized_gate_count() {
+        let source = "public x\nlet a = 5 + 10\nlet unused = x * x\nreturn x * a";
+        let unoptimized = crate::circuit::CircuitBuilder::from_ssa(convert(source));
+        let o0 = crate::compiler::compile(source, OptLevel::O0).unwrap();
+        assert_eq!(o0.gates.len(), unoptimized.gates.len());
+        assert!(
+            crate::compiler::compile(source, OptLevel::O1)
+                .unwrap()
+                .gates
+                .len()
+                < o0.gates.len()
+        );
+    }
+
+    // past i32 the product is still exact, and past p it wraps modulo p,
+    // never to what a machine integer would have wrapped to
+    #[test]
+    fn overflowing_constant_product_folds_to_the_field_value() {
+        for (source, expected) in [
+            ("return 2000000000 * 2", FieldElement::new(4_000_000_000)),
+            ("return -1 * 2", -FieldElement::new(2)),
+            (
+                "return 4294967296 * 4294967296",
+                FieldElement::new(u32::MAX as u64),
+            ),
+        ] {
+            let optimized = optimize(convert(source), OptLevel::O1);
+            assert!(
+                matches!(&optimized.instructions[..], [SsaInstruction::Const(_, value)] if *value == expected),
+                "{}: {:?}",
+                source,
+                optimized.instructions
+            );
         }
     }
+
+    #[test]
+    fn unused_private_input_is_pruned() {
+        let source = "public x\nprivate unused\nprivate checked\nassert checked == 1\nreturn x * x";
+        let optimized = optimize(convert(source), OptLevel::O1);
+        let before = crate::circuit::CircuitBuilder::from_ssa(optimized.clone()).to_r1cs();
+
+        let (pruned, dropped) = prune_unused_inputs(optimized);
+        assert_eq!(dropped, ["unused"]);
+        // the input feeding the assert stays
+        let names: Vec<&str> = pruned
+            .private_inputs
+            .iter()
+            .map(|input| input.name.as_str())
+            .collect();
+        assert_eq!(names, ["checked"]);
+        let after = crate::circuit::CircuitBuilder::from_ssa(pruned).to_r1cs();
+        assert_eq!(after.num_variables, before.num_variables - 1);
+    }
+
+    #[test]
+    fn assert_only_input_survives_dce() {
+        let source = "public y\nprivate x\nlet d = x * 2\nassert d == 10\nreturn y";
+        let optimized = optimize(convert(source), OptLevel::O2);
+        assert_eq!(optimized.private_inputs.len(), 1);
+        // the product feeding the assert is kept along with it
+        assert!(optimized
+            .instructions
+            .iter()
+            .any(|instr| matches!(instr, SsaInstruction::Mul(..))));
+        assert!(optimized
+            .instructions
+            .iter()
+            .any(|instr| matches!(instr, SsaInstruction::Assert(..))));
+
+        let circuit = crate::compiler::compile(source, OptLevel::O2).unwrap();
+        assert!(circuit.unconstrained_inputs().is_empty());
+        let inputs = InputFile::builder().public("y", 1).private("x", 4).build();
+        assert!(WitnessCalculator::new()
+            .calculate_witness(&circuit, inputs)
+            .is_err());
+    }
+
+    #[test]
+    fn discarded_let_survives_only_with_an_assert() {
+        let muls = |source: &str| {
+            optimize(convert(source), OptLevel::O1)
+                .instructions
+                .iter()
+                .filter(|instr| matches!(instr, SsaInstruction::Mul(..)))
+                .count()
+        };
+        assert_eq!(muls("public x\nprivate y\nlet _ = x * y\nreturn x"), 0);
+        // the assert is inside the discarded computation, so the product stays
+        assert_eq!(
+            muls("public x\nprivate y\nfn check(a, b) {\nlet p = a * b\nassert p == 6\nreturn p\n}\nlet _ = check(x, y)\nreturn x"),
+            1
+        );
+    }
+
+    #[test]
+    fn pinned_input_becomes_a_constant() {
+        let pins = std::collections::HashMap::from([("n".to_string(), FieldElement::new(3))]);
+        let pinned = pin_inputs(convert("public n\npublic x\nreturn x * n"), &pins).unwrap();
+        let names: Vec<&str> = pinned
+            .public_inputs
+            .iter()
+            .map(|input| input.name.as_str())
+            .collect();
+        assert_eq!(names, ["x"]);
+        assert!(matches!(
+            &pinned.instructions[0],
+            SsaInstruction::Const(input, value) if input.name == "n" && *value == FieldElement::new(3)
+        ));
+    }
+
+    #[test]
+    fn pinning_an_unknown_name_is_an_error() {
+        let program = convert("public x\nprivate y\nreturn x * y");
+        let pins = std::collections::HashMap::from([("z".to_string(), FieldElement::one())]);
+        let err = pin_inputs(program.clone(), &pins).unwrap_err();
+        assert_eq!(err.name, "z");
+        assert!(!err.private);
+
+        let pins = std::collections::HashMap::from([("y".to_string(), FieldElement::one())]);
+        let err = pin_inputs(program, &pins).unwrap_err();
+        assert_eq!(err.name, "y");
+        assert!(err.private);
+        assert!(err.to_string().contains("private input"));
+    }
+
+    #[test]
+    fn inverse_of_a_constant_folds() {
+        let optimized = optimize(convert("return inv(7)"), OptLevel::O1);
+        let seven_inv = FieldElement::new(7).inverse().unwrap();
+        assert!(optimized
+            .instructions
+            .iter()
+            .any(|instr| matches!(instr, SsaInstruction::Const(_, value) if *value == seven_inv)));
+        assert!(!optimized
+            .instructions
+            .iter()
+            .any(|instr| matches!(instr, SsaInstruction::Inv(..))));
+    }
+
+    #[test]
+    fn inverse_of_zero_is_not_folded() {
+        let optimized = optimize(convert("public x\nreturn x + inv(0)"), OptLevel::O1);
+        assert!(optimized
+            .instructions
+            .iter()
+            .any(|instr| matches!(instr, SsaInstruction::Inv(..))));
+
+        let circuit = crate::compiler::compile_str("public x\nreturn x + inv(0)").unwrap();
+        let inputs = InputFile::builder().public("x", 1).build();
+        let err = WitnessCalculator::new()
+            .calculate_witness(&circuit, inputs)
+            .unwrap_err();
+        assert!(matches!(err, WitnessError::InverseOfZero(_)));
+    }
+
+    #[test]
+    fn seventy_digit_constant_reaches_the_const_gate() {
+        let digits = "1".repeat(70);
+        let circuit = crate::compiler::compile_str(&format!("return {}", digits)).unwrap();
+        let expected = digits.bytes().fold(FieldElement::zero(), |acc, digit| {
+            acc * FieldElement::new(10) + FieldElement::new((digit - b'0') as u64)
+        });
+        assert!(circuit
+            .gates
+            .iter()
+            .any(|gate| matches!(gate, Gate::Const { value, .. } if *value == expected)));
+    }
+
+    fn asserts(program: &SsaProgram) -> usize {
+        program
+            .instructions
+            .iter()
+            .filter(|instr| matches!(instr, SsaInstruction::Assert(..)))
+            .count()
+    }
+
+    #[test]
+    fn trivially_true_asserts_are_removed() {
+        let program = convert("public x\nassert 5 == 5\nassert x == x\nreturn x");
+        assert_eq!(asserts(&program), 2);
+        assert_eq!(asserts(&RedundantAssertEliminator::eliminate(program)), 0);
+    }
+
+    #[test]
+    fn duplicate_asserts_are_removed() {
+        let program = convert(
+            "public x\nprivate y\nassert x == y\nassert y == x\nassert x == y\nassert x == 3\nreturn x",
+        );
+        let eliminated = RedundantAssertEliminator::eliminate(program);
+        // x == y once, whichever side each operand is on, and x == 3
+        assert_eq!(asserts(&eliminated), 2);
+        assert_eq!(asserts(&optimize(eliminated, OptLevel::O1)), 2);
+    }
+
+    #[test]
+    fn assert_between_different_constants_is_a_compile_time_error() {
+        let err =
+            check_constant_asserts(&convert("public x\nassert 5 == 6\nreturn x")).unwrap_err();
+        assert_eq!(
+            (err.left, err.right),
+            (FieldElement::new(5), FieldElement::new(6))
+        );
+
+        // only constant once folded, so the static check can't see it
+        let err =
+            crate::compiler::compile_str("public x\nassert x - x + 5 == 6\nreturn x").unwrap_err();
+        assert!(
+            matches!(err, crate::error::CompilerError::ConstantAssert(_)),
+            "{}",
+            err
+        );
+    }
 }
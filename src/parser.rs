@@ -1,82 +1,315 @@
 /*
-program = statement*
-statement = "public" IDENT
-          | "private" IDENT
-          | "const" IDENT "=" NUMBER
-          | "let" IDENT "=" expr
+program = (statement | include)*     // inputs before any let or return, return last
+include = "include" STRING     // the file's statements, path relative to this file
+statement = "public" IDENT (":" TYPE)?
+          | "private" IDENT (":" TYPE)?
+          | "const" IDENT "=" "-"? NUMBER
+          | "const" IDENT "=" "[" "-"? NUMBER ("," "-"? NUMBER)* "]"
+          | "let" IDENT "=" expr          // may rebind an existing name, later reads see the new value
+          | "let" "_" "=" expr            // computed for its asserts only, "_" can't be read
+          | IDENT "+=" expr               // let IDENT = IDENT + expr
           | "return" expr
-          | "assert" expr "==" expr
-expr = term ("+" term | "*" term)*
-term = IDENT | NUMBER | "(" expr ")"
+          | "assert" expr "==" expr ("==" expr)*
+          | "assert" sum "!=" sum
+          | "assert_eq" "(" expr "," expr ("," expr)* ")"
+          | "static_assert" sum CMP sum   // checked at compile time, no constraint
+          | "fn" IDENT "(" params? ")" "{" body "}"
+TYPE = "u8" | "u16" | "u32"    // range checks the input to [0, 2^bits)
+CMP = "<" | "<=" | ">" | ">=" | "==" | "!="
+params = IDENT ("," IDENT)*
+body = ("let" ... | IDENT "+=" ... | "assert" ... | "assert_eq" ...)* "return" expr   // fn bodies only compute
+expr = conjunction ("or" conjunction)*       // a or b is a + b - a * b
+conjunction = negation ("and" negation)*     // a and b is a * b
+negation = "not" negation | comparison       // not a is 1 - a; operands must be 0 or 1
+comparison = sum ("!=" sum)*  // a != b is 1 if they differ, else 0
+sum = product (("+" | "-") product)*
+product = power ("*" power)*
+power = term ("**" power)?    // right associative, exponent must be a constant expression
+term = IDENT | NUMBER | "(" expr ")" | "-" power  // -2 ** 2 is -(2 ** 2); "-" NUMBER is a negative literal
+     | IDENT "(" (expr ("," expr)*)? ")"          // call of a builtin or an earlier fn
+     | IDENT "[" expr "]"                         // const array element, constant index
+     | "let" IDENT "=" expr "in" expr             // IDENT is bound only in the second expr
 */
 
-use crate::ast::{Expr, Program, Stmt};
+use crate::ast::{add_assign_sum, CompareOp, Expr, InputType, Program, Span, Spanned, Stmt};
+use crate::const_eval::eval_const_in;
+use crate::field::FieldElement;
+use crate::lexer::Lexer;
 use crate::token::{Token, TokenType};
 
+use std::collections::{HashMap, HashSet};
 use std::mem::discriminant;
+use std::path::{Path, PathBuf};
+
+// builtin functions, all of them take a single argument
+const BUILTINS: &[&str] = &["inv", "is_zero"];
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    functions: HashMap<String, usize>, // declared functions and their arity
+    arrays: HashMap<String, Vec<FieldElement>>, // declared const arrays
+    inputs: HashSet<String>,           // declared public and private inputs
+    // name -> its value if known while parsing, as in check_static_asserts;
+    // None shadows an earlier constant. Indices and exponents may read them.
+    consts: HashMap<String, Option<i32>>,
+    // directory include paths are relative to, the current one when None
+    base_dir: Option<PathBuf>,
+    // canonical paths of the files being parsed, the including ones first
+    include_stack: Vec<PathBuf>,
+    // path of the included file being parsed, for its spans; None for the
+    // file being compiled
+    file: Option<String>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            current: 0,
+            functions: HashMap::new(),
+            arrays: HashMap::new(),
+            inputs: HashSet::new(),
+            consts: HashMap::new(),
+            base_dir: None,
+            include_stack: Vec::new(),
+            file: None,
+        }
+    }
+
+    // a parser for the tokens of the file at `path`, resolving its includes
+    // relative to the file's directory
+    pub fn for_file(tokens: Vec<Token>, path: &Path) -> Self {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        Self {
+            base_dir: path.parent().map(Path::to_path_buf),
+            include_stack: vec![canonical],
+            ..Self::new(tokens)
+        }
     }
 
-    // program = statement*
+    // program = (statement | include)*
     pub fn parse(&mut self) -> Result<Program, ParseError> {
-        let mut statements: Vec<Stmt> = Vec::new();
+        let mut statements: Vec<Spanned<Stmt>> = Vec::new();
         while !self.is_at_end() {
-            let stmt = self.parse_statement()?;
-            statements.push(stmt);
+            if *self.peek() == TokenType::Include {
+                statements.extend(self.parse_include()?);
+            } else {
+                let stmt = self.parse_statement()?;
+                statements.push(stmt);
+            }
         }
+        check_statement_order(&statements)?;
         Ok(Program { statements })
     }
 }
 
+// Inputs have to be declared before the first let or return, and nothing may
+// follow the return, which would otherwise be parsed and then never matter.
+fn check_statement_order(statements: &[Spanned<Stmt>]) -> Result<(), ParseError> {
+    let mut first_let_or_return: Option<Span> = None;
+    let mut return_span: Option<Span> = None;
+
+    for stmt in statements {
+        if let Some(ret) = return_span {
+            return Err(ParseError {
+                message: format!(
+                    "Statement at {} follows the return at {}, return must come last",
+                    stmt.span, ret
+                ),
+            });
+        }
+        match &stmt.node {
+            Stmt::PublicInput { name, .. } | Stmt::PrivateInput { name, .. } => {
+                if let Some(earlier) = first_let_or_return {
+                    return Err(ParseError {
+                        message: format!(
+                            "Input {} at {} is declared after the statement at {}, inputs must come before any let or return",
+                            name, stmt.span, earlier
+                        ),
+                    });
+                }
+            }
+            Stmt::Let { .. } | Stmt::AddAssign { .. } => {
+                first_let_or_return.get_or_insert(stmt.span.clone());
+            }
+            Stmt::Return(_) => {
+                first_let_or_return.get_or_insert(stmt.span.clone());
+                return_span = Some(stmt.span.clone());
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+impl Parser {
+    // include = "include" STRING
+    // The file's statements are parsed in place, sharing the declared inputs,
+    // functions and const arrays, so either side can use what the other
+    // declared before it. Their spans are offsets into the included file and
+    // name it by its path relative to the including file's directory.
+    fn parse_include(&mut self) -> Result<Vec<Spanned<Stmt>>, ParseError> {
+        self.consume(TokenType::Include)?;
+        let relative = match &self.advance()?.token_type {
+            TokenType::Str(path) => path.clone(),
+            other => {
+                return Err(ParseError {
+                    message: format!("Expected a quoted path after include, found {:?}", other),
+                })
+            }
+        };
+        let path = match &self.base_dir {
+            Some(dir) => dir.join(&relative),
+            None => PathBuf::from(&relative),
+        };
+        let file = path.display().to_string();
+        let base_dir = path.parent().map(Path::to_path_buf);
+        let path = path.canonicalize().map_err(|err| ParseError {
+            message: format!("Cannot include {}: {}", relative, err),
+        })?;
+
+        if let Some(first) = self.include_stack.iter().position(|file| *file == path) {
+            let cycle: Vec<String> = self.include_stack[first..]
+                .iter()
+                .chain(std::iter::once(&path))
+                .map(|file| file.display().to_string())
+                .collect();
+            return Err(ParseError {
+                message: format!("Include cycle: {}", cycle.join(" -> ")),
+            });
+        }
+
+        let in_file = |message: String| ParseError {
+            message: format!("In {}: {}", file, message),
+        };
+        let source = std::fs::read_to_string(&path).map_err(|err| in_file(err.to_string()))?;
+        let tokens = Lexer::new(&source)
+            .tokenize()
+            .map_err(|err| in_file(err.to_string()))?;
+
+        let mut include_stack = self.include_stack.clone();
+        include_stack.push(path.clone());
+        let mut included = Parser {
+            functions: std::mem::take(&mut self.functions),
+            arrays: std::mem::take(&mut self.arrays),
+            inputs: std::mem::take(&mut self.inputs),
+            consts: std::mem::take(&mut self.consts),
+            base_dir,
+            include_stack,
+            file: Some(file.clone()),
+            ..Parser::new(tokens)
+        };
+        let program = included.parse().map_err(|err| in_file(err.message))?;
+        self.functions = included.functions;
+        self.arrays = included.arrays;
+        self.inputs = included.inputs;
+        self.consts = included.consts;
+
+        Ok(program.statements)
+    }
+}
+
 impl Parser {
-    // statement = "public" IDENT
-    //       | "private" IDENT
-    //       | "const" IDENT "=" NUMBER
+    // statement = "public" IDENT (":" TYPE)?
+    //       | "private" IDENT (":" TYPE)?
+    //       | "const" IDENT "=" "-"? NUMBER
+    //       | "const" IDENT "=" "[" "-"? NUMBER ("," "-"? NUMBER)* "]"
     //       | "let" IDENT "=" expr
     //       | "return" expr
-    fn parse_statement(&mut self) -> Result<Stmt, ParseError> {
-        match self.peek() {
+    //       | "assert" expr "==" expr ("==" expr)*
+    //       | "assert" sum "!=" sum
+    //       | "assert_eq" "(" expr "," expr ("," expr)* ")"
+    //       | "static_assert" sum CMP sum
+    //       | "fn" IDENT "(" params? ")" "{" body "}"
+    fn parse_statement(&mut self) -> Result<Spanned<Stmt>, ParseError> {
+        let start = self.current_start();
+        let stmt = match self.peek() {
             TokenType::Public => self.parse_public_stmt(),
             TokenType::Private => self.parse_private_stmt(),
             TokenType::Const => self.parse_const_stmt(),
             TokenType::Let => self.parse_let_stmt(),
             TokenType::Return => self.parse_return_stmt(),
             TokenType::Assert => self.parse_assert_stmt(),
+            TokenType::AssertEq => self.parse_assert_eq_stmt(),
+            TokenType::StaticAssert => self.parse_static_assert_stmt(),
+            TokenType::Fn => self.parse_fn_decl(),
+            TokenType::Identifier(_) => self.parse_add_assign_stmt(),
             _ => Err(ParseError {
                 message: format!("Expected statement, found {:?}", self.peek()),
             }),
-        }
+        }?;
+        Ok(self.spanned(stmt, start))
     }
 
-    // "public" IDENT
+    // "public" IDENT (":" TYPE)?
     fn parse_public_stmt(&mut self) -> Result<Stmt, ParseError> {
         self.consume(TokenType::Public)?;
-        let name = self.expect_identifier()?;
-        Ok(Stmt::PublicInput { name })
+        let name = self.expect_input_name()?;
+        let ty = self.parse_input_type()?;
+        self.consts.insert(name.clone(), None);
+        Ok(Stmt::PublicInput { name, ty })
     }
 
-    // "private" IDENT
+    // "private" IDENT (":" TYPE)?
     fn parse_private_stmt(&mut self) -> Result<Stmt, ParseError> {
         self.consume(TokenType::Private)?;
+        let name = self.expect_input_name()?;
+        let ty = self.parse_input_type()?;
+        self.consts.insert(name.clone(), None);
+        Ok(Stmt::PrivateInput { name, ty })
+    }
+
+    // inputs are looked up by name in the inputs file, so a name can be an
+    // input only once; `let` may still rebind it afterwards
+    fn expect_input_name(&mut self) -> Result<String, ParseError> {
         let name = self.expect_identifier()?;
-        Ok(Stmt::PrivateInput { name })
+        if !self.inputs.insert(name.clone()) {
+            return Err(ParseError {
+                message: format!("Input {} is already declared", name),
+            });
+        }
+        Ok(name)
+    }
+
+    // (":" TYPE)?
+    fn parse_input_type(&mut self) -> Result<Option<InputType>, ParseError> {
+        if *self.peek() != TokenType::Colon {
+            return Ok(None);
+        }
+        self.advance()?;
+        let type_name = self.expect_identifier()?;
+        match InputType::from_name(&type_name) {
+            Some(ty) => Ok(Some(ty)),
+            None => Err(ParseError {
+                message: format!("Unknown type {}, expected u8, u16 or u32", type_name),
+            }),
+        }
     }
 
-    // "const" IDENT "=" NUMBER
+    // "const" IDENT "=" "-"? NUMBER
+    // "const" IDENT "=" "[" "-"? NUMBER ("," "-"? NUMBER)* "]"
     fn parse_const_stmt(&mut self) -> Result<Stmt, ParseError> {
         self.consume(TokenType::Const)?;
         let name = self.expect_identifier()?;
         self.consume(TokenType::Equals)?;
-        let value = self.expect_number()?;
-        Ok(Stmt::ConstDecl { name, value })
+        if *self.peek() != TokenType::LeftBracket {
+            let value = self.expect_signed_number()?;
+            self.consts.insert(name.clone(), value.to_i32());
+            return Ok(Stmt::ConstDecl { name, value });
+        }
+
+        self.advance()?;
+        let mut values = vec![self.expect_signed_number()?];
+        while *self.peek() == TokenType::Comma {
+            self.advance()?;
+            values.push(self.expect_signed_number()?);
+        }
+        self.consume(TokenType::RightBracket)?;
+
+        self.arrays.insert(name.clone(), values.clone());
+        self.consts.insert(name.clone(), None);
+        Ok(Stmt::ConstArray { name, values })
     }
 
     // "let" IDENT "=" expr
@@ -85,9 +318,27 @@ impl Parser {
         let name = self.expect_identifier()?;
         self.consume(TokenType::Equals)?;
         let expr = self.parse_expr()?;
+        self.consts
+            .insert(name.clone(), eval_const_in(&expr.node, &self.consts));
         Ok(Stmt::Let { name, expr })
     }
 
+    // IDENT "+=" expr
+    fn parse_add_assign_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let name = self.expect_identifier()?;
+        if name == "_" {
+            return Err(ParseError {
+                message: "_ can't be read, so it can't be added to".to_string(),
+            });
+        }
+        self.consume(TokenType::PlusEquals)?;
+        let expr = self.parse_expr()?;
+        let sum = add_assign_sum(&name, &expr, expr.span.clone());
+        self.consts
+            .insert(name.clone(), eval_const_in(&sum.node, &self.consts));
+        Ok(Stmt::AddAssign { name, expr })
+    }
+
     // "return" expr
     fn parse_return_stmt(&mut self) -> Result<Stmt, ParseError> {
         self.consume(TokenType::Return)?;
@@ -95,56 +346,484 @@ impl Parser {
         Ok(Stmt::Return(expr))
     }
 
-    // "assert" expr "==" expr
+    // "assert" expr "==" expr ("==" expr)*
+    // "assert" sum "!=" sum
     fn parse_assert_stmt(&mut self) -> Result<Stmt, ParseError> {
         self.consume(TokenType::Assert)?;
         let left = self.parse_expr()?;
+        // assert a != b holds when (a != b) == 1
+        if matches!(left.node, Expr::Ne(..)) && *self.peek() != TokenType::EqualsEquals {
+            let one = Spanned::new(Expr::Literal(FieldElement::one()), left.span.clone());
+            return Ok(Stmt::Assert { left, right: one });
+        }
         self.consume(TokenType::EqualsEquals)?;
         let right = self.parse_expr()?;
-        Ok(Stmt::Assert { left, right })
+
+        if *self.peek() != TokenType::EqualsEquals {
+            return Ok(Stmt::Assert { left, right });
+        }
+        let mut operands = vec![left, right];
+        while *self.peek() == TokenType::EqualsEquals {
+            self.advance()?;
+            operands.push(self.parse_expr()?);
+        }
+        Ok(Stmt::AssertEq(operands))
+    }
+
+    // "assert_eq" "(" expr "," expr ("," expr)* ")"
+    fn parse_assert_eq_stmt(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::AssertEq)?;
+        self.consume(TokenType::LeftParen)?;
+        let mut operands = vec![self.parse_expr()?];
+        while *self.peek() == TokenType::Comma {
+            self.advance()?;
+            operands.push(self.parse_expr()?);
+        }
+        self.consume(TokenType::RightParen)?;
+
+        if operands.len() < 2 {
+            return Err(ParseError {
+                message: "assert_eq takes at least 2 arguments, found 1".to_string(),
+            });
+        }
+        Ok(Stmt::AssertEq(operands))
     }
 
-    // expr = term ("+" term | "*" term)*
-    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
-        let mut left = self.parse_term()?;
+    // "static_assert" sum CMP sum
+    fn parse_static_assert_stmt(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::StaticAssert)?;
+        let left = self.parse_sum()?;
+        let op = match &self.advance()?.token_type {
+            TokenType::Less => CompareOp::Less,
+            TokenType::LessEquals => CompareOp::LessEquals,
+            TokenType::Greater => CompareOp::Greater,
+            TokenType::GreaterEquals => CompareOp::GreaterEquals,
+            TokenType::EqualsEquals => CompareOp::Equals,
+            TokenType::BangEquals => CompareOp::NotEquals,
+            other => {
+                return Err(ParseError {
+                    message: format!("Expected a comparison in static_assert, found {:?}", other),
+                })
+            }
+        };
+        let right = self.parse_sum()?;
+        Ok(Stmt::StaticAssert { left, op, right })
+    }
+
+    // "fn" IDENT "(" params? ")" "{" body "}"
+    fn parse_fn_decl(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(TokenType::Fn)?;
+        let name = self.expect_identifier()?;
+        if BUILTINS.contains(&name.as_str()) {
+            return Err(ParseError {
+                message: format!("Function name {} is reserved for a builtin", name),
+            });
+        }
+        if self.functions.contains_key(&name) {
+            return Err(ParseError {
+                message: format!("Function {} is already defined", name),
+            });
+        }
 
-        while matches!(self.peek(), TokenType::Plus | TokenType::Star) {
-            let op = self.advance()?;
-            let right = self.parse_term()?;
+        self.consume(TokenType::LeftParen)?;
+        let mut params = Vec::new();
+        if *self.peek() != TokenType::RightParen {
+            params.push(self.expect_identifier()?);
+            while *self.peek() == TokenType::Comma {
+                self.advance()?;
+                params.push(self.expect_identifier()?);
+            }
+        }
+        self.consume(TokenType::RightParen)?;
+
+        // a body reads the program's names as they are at each call, which
+        // parsing can't know, so only its own constant lets count in it
+        let program_consts = std::mem::take(&mut self.consts);
+        self.consts
+            .extend(params.iter().map(|param| (param.clone(), None)));
+        self.consume(TokenType::LeftBrace)?;
+        let body = self.parse_fn_body(&name);
+        self.consts = program_consts;
+        let body = body?;
+        self.consume(TokenType::RightBrace)?;
+
+        // registered only after the body is parsed, so a function can't call itself
+        self.functions.insert(name.clone(), params.len());
+        Ok(Stmt::FnDecl { name, params, body })
+    }
 
-            left = match op.token_type {
-                TokenType::Plus => Expr::Add(Box::new(left), Box::new(right)),
-                TokenType::Star => Expr::Mul(Box::new(left), Box::new(right)),
-                _ => unreachable!(),
+    // body = ("let" ... | "assert" ...)* "return" expr
+    fn parse_fn_body(&mut self, name: &str) -> Result<Vec<Spanned<Stmt>>, ParseError> {
+        let mut body = Vec::new();
+        loop {
+            let start = self.current_start();
+            match self.peek() {
+                TokenType::Let => {
+                    let stmt = self.parse_let_stmt()?;
+                    body.push(self.spanned(stmt, start));
+                }
+                TokenType::Identifier(_) => {
+                    let stmt = self.parse_add_assign_stmt()?;
+                    body.push(self.spanned(stmt, start));
+                }
+                TokenType::Assert => {
+                    let stmt = self.parse_assert_stmt()?;
+                    body.push(self.spanned(stmt, start));
+                }
+                TokenType::AssertEq => {
+                    let stmt = self.parse_assert_eq_stmt()?;
+                    body.push(self.spanned(stmt, start));
+                }
+                TokenType::Return => {
+                    let stmt = self.parse_return_stmt()?;
+                    body.push(self.spanned(stmt, start));
+                    return Ok(body);
+                }
+                TokenType::RightBrace | TokenType::Eof => {
+                    return Err(ParseError {
+                        message: format!("Function {} must end with a return statement", name),
+                    })
+                }
+                other => {
+                    return Err(ParseError {
+                        message: format!(
+                        "Expected let, +=, assert, assert_eq or return in function {}, found {:?}",
+                        name, other
+                    ),
+                    })
+                }
+            }
+        }
+    }
+
+    // expr = conjunction ("or" conjunction)*
+    fn parse_expr(&mut self) -> Result<Spanned<Expr>, ParseError> {
+        self.parse_binary(0)
+    }
+
+    // sum, every operator binding tighter than "!="
+    fn parse_sum(&mut self) -> Result<Spanned<Expr>, ParseError> {
+        self.parse_binary(op_info(&TokenType::BangEquals).map_or(0, |info| info.prec + 1))
+    }
+
+    // Precedence climbing over BINARY_OPERATORS: the operand to the right of
+    // an operator takes every operator binding tighter than it, and the
+    // operator itself again if it is right associative.
+    fn parse_binary(&mut self, min_prec: u8) -> Result<Spanned<Expr>, ParseError> {
+        let mut left = self.parse_negation(min_prec)?;
+
+        while let Some(info) = op_info(self.peek()).filter(|info| info.prec >= min_prec) {
+            // operator tokens carry no data, cloning one is a copy
+            let op = self.advance()?.token_type.clone();
+            let right_min_prec = match info.assoc {
+                Assoc::Left => info.prec + 1,
+                Assoc::Right => info.prec,
             };
+            let right = self.parse_binary(right_min_prec)?;
+            left = binary_expr(op, left, right, &self.consts)?;
         }
 
         Ok(left)
     }
 
-    // term = IDENT | NUMBER | "(" expr ")"
-    fn parse_term(&mut self) -> Result<Expr, ParseError> {
-        let token = self.advance()?;
+    // negation = "not" negation | comparison
+    // "not" binds looser than "!=" and tighter than "and", as in Python, so
+    // not a != b is not (a != b); where only tighter operators are allowed,
+    // as in a + not b, it is an error
+    fn parse_negation(&mut self, min_prec: u8) -> Result<Spanned<Expr>, ParseError> {
+        let comparison_prec = op_info(&TokenType::BangEquals).map_or(0, |info| info.prec);
+        if *self.peek() != TokenType::Not || min_prec > comparison_prec {
+            return self.parse_term();
+        }
+        let start = self.advance()?.span.0;
+        let operand = self.parse_binary(comparison_prec)?;
+        Ok(self.spanned(Expr::Not(Box::new(operand)), start))
+    }
 
-        match token.token_type {
-            TokenType::Identifier(name) => Ok(Expr::Var(name)),
-            TokenType::Number(n) => Ok(Expr::Literal(n)),
+    // term = IDENT | NUMBER | "(" expr ")" | "-" power
+    //      | IDENT "(" (expr ("," expr)*)? ")" | IDENT "[" expr "]"
+    //      | "let" IDENT "=" expr "in" expr
+    fn parse_term(&mut self) -> Result<Spanned<Expr>, ParseError> {
+        let span = self.advance()?.span;
+        let start = span.0;
+
+        // matched in place, a name is only cloned into the node that keeps it
+        match &self.tokens[self.current - 1].token_type {
+            TokenType::Identifier(name) if *self.peek() == TokenType::LeftParen => {
+                let call = self.parse_call(name.clone())?;
+                Ok(self.spanned(call, start))
+            }
+            TokenType::Identifier(name) if *self.peek() == TokenType::LeftBracket => {
+                let name = name.clone();
+                let element = self.parse_index(&name)?;
+                Ok(self.spanned(Expr::Literal(element), start))
+            }
+            TokenType::Identifier(name) if name == "_" => Err(ParseError {
+                message: format!(
+                    "_ can be bound by let but not read, at {}..{}",
+                    span.0, span.1
+                ),
+            }),
+            TokenType::Identifier(name) => Ok(self.spanned(Expr::Var(name.clone()), start)),
+            TokenType::Number(n) => Ok(self.spanned(Expr::Literal(*n), start)),
             TokenType::LeftParen => {
+                // the span covers the parentheses too
                 let expr = self.parse_expr()?;
                 self.consume(TokenType::RightParen)?;
-                Ok(expr)
+                Ok(self.spanned(expr.node, start))
             }
-            _ => Err(ParseError {
+            TokenType::Let => {
+                let name = self.expect_identifier()?;
+                self.consume(TokenType::Equals)?;
+                let value = self.parse_expr()?;
+                self.consume(TokenType::In)?;
+                let bound = eval_const_in(&value.node, &self.consts);
+                let shadowed = self.consts.insert(name.clone(), bound);
+                let body = self.parse_expr();
+                match shadowed {
+                    Some(shadowed) => self.consts.insert(name.clone(), shadowed),
+                    None => self.consts.remove(&name),
+                };
+                let body = body?;
+                Ok(self.spanned(Expr::Let(name, Box::new(value), Box::new(body)), start))
+            }
+            // -5 is the literal -5 (p - 5 in the field), -x negates at runtime;
+            // "**" binds tighter, so -2 ** 2 is -(2 ** 2)
+            TokenType::Minus => {
+                let operand =
+                    self.parse_binary(op_info(&TokenType::StarStar).map_or(0, |info| info.prec))?;
+                let node = match operand.node {
+                    Expr::Literal(n) => Expr::Literal(-n),
+                    _ => Expr::Neg(Box::new(operand)),
+                };
+                Ok(self.spanned(node, start))
+            }
+            other => Err(ParseError {
                 message: format!(
-                    "Expected identifier, number, or '(', found {:?}",
-                    token.token_type
+                    "Expected identifier, number, '(', '-' or let, found {:?}",
+                    other
                 ),
             }),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Assoc {
+    Left,
+    Right,
+}
+
+// how a binary operator groups: higher prec binds tighter
+#[derive(Debug, Clone, Copy)]
+struct OpInfo {
+    prec: u8,
+    assoc: Assoc,
+}
+
+// Binary operators, loosest first. A new operator takes an entry here and a
+// case in binary_expr building its AST node.
+const BINARY_OPERATORS: &[(TokenType, OpInfo)] = &[
+    (
+        TokenType::Or,
+        OpInfo {
+            prec: 1,
+            assoc: Assoc::Left,
+        },
+    ),
+    (
+        TokenType::And,
+        OpInfo {
+            prec: 2,
+            assoc: Assoc::Left,
+        },
+    ),
+    (
+        TokenType::BangEquals,
+        OpInfo {
+            prec: 3,
+            assoc: Assoc::Left,
+        },
+    ),
+    (
+        TokenType::Plus,
+        OpInfo {
+            prec: 4,
+            assoc: Assoc::Left,
+        },
+    ),
+    (
+        TokenType::Minus,
+        OpInfo {
+            prec: 4,
+            assoc: Assoc::Left,
+        },
+    ),
+    (
+        TokenType::Star,
+        OpInfo {
+            prec: 5,
+            assoc: Assoc::Left,
+        },
+    ),
+    // 2 ** 3 ** 2 is 2 ** 9
+    (
+        TokenType::StarStar,
+        OpInfo {
+            prec: 6,
+            assoc: Assoc::Right,
+        },
+    ),
+];
+
+fn op_info(token: &TokenType) -> Option<OpInfo> {
+    BINARY_OPERATORS
+        .iter()
+        .find(|(op, _)| op == token)
+        .map(|(_, info)| *info)
+}
+
+// `consts` are the names an exponent may read, see Parser::consts
+fn binary_expr(
+    op: TokenType,
+    left: Spanned<Expr>,
+    right: Spanned<Expr>,
+    consts: &HashMap<String, Option<i32>>,
+) -> Result<Spanned<Expr>, ParseError> {
+    let span = Span {
+        start: left.span.start,
+        end: right.span.end,
+        file: left.span.file.clone(),
+    };
+    let (left, right) = (Box::new(left), Box::new(right));
+    let expr = match op {
+        TokenType::Or => Expr::Or(left, right),
+        TokenType::And => Expr::And(left, right),
+        TokenType::BangEquals => Expr::Ne(left, right),
+        TokenType::Plus => Expr::Add(left, right),
+        TokenType::Minus => Expr::Sub(left, right),
+        TokenType::Star => Expr::Mul(left, right),
+        // the exponent must be a constant expression, a non-negative i32 (see eval_const)
+        TokenType::StarStar => {
+            let exponent = match eval_const_in(&right, consts) {
+                Some(n) if n >= 0 => n as u32,
+                Some(n) => {
+                    return Err(ParseError {
+                        message: format!("Exponent must be non-negative, found {}", n),
+                    })
+                }
+                None => {
+                    return Err(ParseError {
+                        message: format!(
+                            "Exponent must be a compile-time constant, found {}",
+                            right
+                        ),
+                    })
+                }
+            };
+            Expr::Pow(left, exponent)
+        }
+        other => unreachable!("{:?} is not in BINARY_OPERATORS", other),
+    };
+    Ok(Spanned::new(expr, span))
+}
+
 impl Parser {
+    // "[" expr "]" after the array name; the index must be a constant in bounds,
+    // evaluated as an i32 (see eval_const)
+    fn parse_index(&mut self, name: &str) -> Result<FieldElement, ParseError> {
+        self.consume(TokenType::LeftBracket)?;
+        let index_expr = self.parse_expr()?;
+        self.consume(TokenType::RightBracket)?;
+
+        let values = self.arrays.get(name).ok_or_else(|| ParseError {
+            message: format!("{} is not a const array", name),
+        })?;
+        let index = eval_const_in(&index_expr, &self.consts).ok_or_else(|| ParseError {
+            message: format!(
+                "Index into {} must be a compile-time constant, found {}",
+                name, index_expr
+            ),
+        })?;
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| values.get(index).copied())
+            .ok_or_else(|| ParseError {
+                message: format!(
+                    "Index {} is out of bounds for {}, which has {} elements",
+                    index,
+                    name,
+                    values.len()
+                ),
+            })
+    }
+
+    fn parse_call(&mut self, name: String) -> Result<Expr, ParseError> {
+        let arity = match self.functions.get(&name) {
+            Some(arity) => *arity,
+            None if BUILTINS.contains(&name.as_str()) => 1,
+            None => {
+                return Err(ParseError {
+                    message: format!(
+                        "Call to undefined function {} (functions must be defined before use, recursion is not supported)",
+                        name
+                    ),
+                })
+            }
+        };
+
+        self.consume(TokenType::LeftParen)?;
+        let mut args = Vec::new();
+        if *self.peek() != TokenType::RightParen {
+            args.push(self.parse_expr()?);
+            while *self.peek() == TokenType::Comma {
+                self.advance()?;
+                args.push(self.parse_expr()?);
+            }
+        }
+        self.consume(TokenType::RightParen)?;
+
+        if args.len() != arity {
+            return Err(ParseError {
+                message: format!(
+                    "Function {} takes {} arguments, found {}",
+                    name,
+                    arity,
+                    args.len()
+                ),
+            });
+        }
+
+        match name.as_str() {
+            "inv" => Ok(Expr::Inv(Box::new(args.remove(0)))),
+            "is_zero" => Ok(Expr::IsZero(Box::new(args.remove(0)))),
+            _ => Ok(Expr::Call(name, args)),
+        }
+    }
+}
+
+impl Parser {
+    // start of the next token
+    fn current_start(&self) -> usize {
+        self.tokens
+            .get(self.current)
+            .map_or(0, |token| token.span.0)
+    }
+
+    // wraps a node spanning from `start` to the end of the last consumed token
+    fn spanned<T>(&self, node: T, start: usize) -> Spanned<T> {
+        let end = self
+            .current
+            .checked_sub(1)
+            .map_or(start, |previous| self.tokens[previous].span.1);
+        let span = Span {
+            start,
+            end,
+            file: self.file.clone(),
+        };
+        Spanned::new(node, span)
+    }
+
     fn is_at_end(&self) -> bool {
         self.current >= self.tokens.len() || *self.peek() == TokenType::Eof
     }
@@ -156,10 +835,11 @@ impl Parser {
             .unwrap_or(&TokenType::Eof)
     }
 
-    fn advance(&mut self) -> Result<Token, ParseError> {
+    // the token is borrowed, callers clone only what they keep
+    fn advance(&mut self) -> Result<&Token, ParseError> {
         if let Some(token) = self.tokens.get(self.current) {
             self.current += 1;
-            Ok(token.clone())
+            Ok(token)
         } else {
             Err(ParseError {
                 message: "Unexpected end of input".to_string(),
@@ -167,9 +847,9 @@ impl Parser {
         }
     }
 
-    fn consume(&mut self, expected: TokenType) -> Result<Token, ParseError> {
+    fn consume(&mut self, expected: TokenType) -> Result<(), ParseError> {
         if discriminant(self.peek()) == discriminant(&expected) {
-            self.advance()
+            self.advance().map(|_| ())
         } else {
             Err(ParseError {
                 message: format!("Expected {:?}, found {:?}", expected, self.peek()),
@@ -178,17 +858,27 @@ impl Parser {
     }
 
     fn expect_identifier(&mut self) -> Result<String, ParseError> {
-        match self.advance()?.token_type {
-            TokenType::Identifier(name) => Ok(name),
+        match &self.advance()?.token_type {
+            TokenType::Identifier(name) => Ok(name.clone()),
             other => Err(ParseError {
                 message: format!("Expected identifier, found {:?}", other),
             }),
         }
     }
 
-    fn expect_number(&mut self) -> Result<i32, ParseError> {
-        match self.advance()?.token_type {
-            TokenType::Number(n) => Ok(n),
+    // "-"? NUMBER
+    fn expect_signed_number(&mut self) -> Result<FieldElement, ParseError> {
+        if *self.peek() == TokenType::Minus {
+            self.advance()?;
+            Ok(-self.expect_number()?)
+        } else {
+            self.expect_number()
+        }
+    }
+
+    fn expect_number(&mut self) -> Result<FieldElement, ParseError> {
+        match &self.advance()?.token_type {
+            TokenType::Number(n) => Ok(*n),
             other => Err(ParseError {
                 message: format!("Expected number, found {:?}", other),
             }),
@@ -200,3 +890,198 @@ impl Parser {
 pub struct ParseError {
     pub message: String,
 }
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::NodeRef;
+    use crate::const_eval::eval_const;
+
+    fn parse(source: &str) -> Result<Program, ParseError> {
+        Parser::new(Lexer::new(source).tokenize().unwrap()).parse()
+    }
+
+    // the literal an index resolved to, or the exponent of a power
+    fn returned(source: &str) -> Expr {
+        let program = parse(source).unwrap();
+        match &program.statements.last().unwrap().node {
+            Stmt::Return(expr) => expr.node.clone(),
+            other => panic!("expected a return, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn var_keeps_the_span_of_its_identifier() {
+        let source = "public x\nprivate long_name\nreturn x + long_name";
+        let program = parse(source).unwrap();
+        let Stmt::Return(expr) = &program.statements.last().unwrap().node else {
+            panic!("expected a return");
+        };
+        let Expr::Add(left, right) = &expr.node else {
+            panic!("expected an addition, found {:?}", expr.node);
+        };
+        for (operand, name) in [(left, "x"), (right, "long_name")] {
+            assert!(matches!(&operand.node, Expr::Var(var) if var == name));
+            assert_eq!(&source[operand.span.start..operand.span.end], name);
+        }
+    }
+
+    #[test]
+    fn offset_inside_a_sum_finds_the_add() {
+        let source = "public a\npublic b\nreturn a + b";
+        let program = parse(source).unwrap();
+        let plus = source.find('+').unwrap();
+        assert!(matches!(
+            program.node_at(plus),
+            Some(NodeRef::Expr(expr)) if matches!(expr.node, Expr::Add(..))
+        ));
+        // on an operand the operand itself is the smallest node
+        assert!(matches!(
+            program.node_at(plus + 2),
+            Some(NodeRef::Expr(expr)) if matches!(&expr.node, Expr::Var(name) if name == "b")
+        ));
+    }
+
+    #[test]
+    fn index_and_exponent_read_consts() {
+        let source = "const T = [5, 6, 7]\nconst i = 2\nreturn T[i]";
+        assert!(matches!(returned(source), Expr::Literal(n) if n == FieldElement::new(7)));
+
+        let source = "public x\nconst n = 3\nlet m = n + 1\nreturn x ** m";
+        assert!(matches!(returned(source), Expr::Pow(_, 4)));
+
+        let source = "public x\nconst T = [5, 6, 7]\nreturn let j = 1 in T[j] * x";
+        assert!(matches!(returned(source), Expr::Let(..)));
+    }
+
+    #[test]
+    fn index_and_exponent_reject_runtime_values() {
+        let err = parse("public i\nconst T = [5, 6, 7]\nreturn T[i]").unwrap_err();
+        assert!(err.message.contains("compile-time constant"), "{}", err);
+
+        // a let rebinding the const hides its value
+        let err = parse("public x\nconst n = 2\nlet n = x\nreturn x ** n").unwrap_err();
+        assert!(err.message.contains("compile-time constant"), "{}", err);
+
+        // a let-in binding is gone after its body
+        let err = parse("public x\nlet y = let n = 2 in x ** n\nreturn y ** n").unwrap_err();
+        assert!(err.message.contains("compile-time constant"), "{}", err);
+
+        // function bodies only see their own constant lets
+        let err = parse("const n = 2\nfn f(a) {\nreturn a ** n\n}\nreturn f(3)").unwrap_err();
+        assert!(err.message.contains("compile-time constant"), "{}", err);
+        assert!(parse("fn f(a) {\nlet n = 2\nreturn a ** n\n}\nreturn f(3)").is_ok());
+    }
+
+    #[test]
+    fn mixed_precedence_follows_the_operator_table() {
+        let sum = returned(
+            "const e = 3\npublic a\npublic b\npublic c\npublic d\nreturn a + b * c - d ** e",
+        );
+        assert_eq!(
+            sum.to_sexpr(),
+            "(sub (add (var a) (mul (var b) (var c))) (pow (var d) 3))"
+        );
+        // ** groups to the right
+        assert_eq!(returned("return 2 ** 3 ** 2").to_sexpr(), "(pow (lit 2) 9)");
+    }
+
+    #[test]
+    fn unary_minus_binds_looser_than_power() {
+        let negated_power = returned("return -2 ** 2");
+        assert_eq!(negated_power.to_sexpr(), "(neg (pow (lit 2) 2))");
+        assert_eq!(eval_const(&negated_power), Some(-4));
+        // other operators take the negative literal as their operand
+        assert_eq!(
+            returned("return -2 * 3").to_sexpr(),
+            "(mul (lit 18446744069414584319) (lit 3))"
+        );
+    }
+
+    #[test]
+    fn second_return_is_rejected() {
+        let err = parse("public x\nreturn x\nreturn x * x").unwrap_err();
+        assert!(err.message.contains("follows the return"), "{}", err);
+    }
+
+    #[test]
+    fn input_after_return_is_rejected() {
+        let err = parse("public x\nreturn x\nprivate y").unwrap_err();
+        assert_eq!(
+            err.message,
+            "Statement at 18..27 follows the return at 9..17, return must come last"
+        );
+        // before the return, an input still has to precede every let
+        let err = parse("public x\nlet y = x\nprivate z\nreturn y").unwrap_err();
+        assert_eq!(
+            err.message,
+            "Input z at 19..28 is declared after the statement at 9..18, inputs must come before any let or return"
+        );
+    }
+
+    #[test]
+    fn statement_after_return_is_rejected() {
+        let err = parse("public x\nreturn x\nlet y = x * x").unwrap_err();
+        assert_eq!(
+            err.message,
+            "Statement at 18..31 follows the return at 9..17, return must come last"
+        );
+    }
+
+    #[test]
+    fn input_declared_twice_is_rejected() {
+        let err = parse("public x\nprivate x\nreturn x").unwrap_err();
+        assert_eq!(err.message, "Input x is already declared");
+        // a let may still rebind an input
+        assert!(parse("public x\nlet x = x + 1\nreturn x").is_ok());
+    }
+
+    fn parse_file(path: &str) -> Result<Program, ParseError> {
+        let source = std::fs::read_to_string(path).unwrap();
+        Parser::for_file(Lexer::new(&source).tokenize().unwrap(), Path::new(path)).parse()
+    }
+
+    #[test]
+    fn included_spans_name_their_file() {
+        let program = parse_file("examples/include.zk").unwrap();
+        let files: Vec<Option<&str>> = program
+            .statements
+            .iter()
+            .map(|stmt| stmt.span.file.as_deref())
+            .collect();
+        let lib = Some("examples/lib/squares.zk");
+        assert_eq!(files, [None, None, lib, lib, None]);
+
+        // offsets are into the main file: 0 is `public a`, not the included const
+        assert_eq!(program.statements[2].span.start, 0);
+        assert!(matches!(
+            program.node_at(0),
+            Some(NodeRef::Stmt(stmt)) if matches!(stmt.node, Stmt::PublicInput { .. })
+        ));
+    }
+
+    #[test]
+    fn error_in_an_included_file_names_it() {
+        let dir = std::env::temp_dir().join(format!("include-span-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.zk"), "fn f(a) {\nreturn a + y\n}\n").unwrap();
+        std::fs::write(dir.join("main.zk"), "include \"lib.zk\"\nreturn f(1)\n").unwrap();
+
+        let program = parse_file(dir.join("main.zk").to_str().unwrap()).unwrap();
+        let err = crate::ssa::SsaBuilder::new().convert(program).unwrap_err();
+        let lib = dir.join("lib.zk").display().to_string();
+        assert_eq!(
+            err.to_string(),
+            format!("Undefined variable y at {}:21..22", lib)
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
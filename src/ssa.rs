@@ -1,6 +1,13 @@
-use crate::ast::{Expr, Program, Stmt};
+use crate::ast::{add_assign_sum, Expr, InputType, Program, Span, Spanned, Stmt};
+use crate::field::FieldElement;
+use crate::listing::{aligned_lines, ListingRow};
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet};
+
+// exponents up to this value are unrolled into a linear chain of multiplications,
+// larger ones use square-and-multiply
+const POW_UNROLL_LIMIT: u32 = 4;
 
 #[derive(Debug, Clone)]
 pub struct SsaProgram {
@@ -8,17 +15,26 @@ pub struct SsaProgram {
     pub return_value: SsaValue,
     pub public_inputs: Vec<SsaValue>,
     pub private_inputs: Vec<SsaValue>,
+    // source range of the expression or declaration defining each value;
+    // values made up by the optimizer have none
+    pub spans: BTreeMap<SsaValue, Span>,
 }
 
 #[derive(Debug, Clone)]
 pub enum SsaInstruction {
-    Const(SsaValue, i32),              // destiantion, value
+    Const(SsaValue, FieldElement),     // destiantion, value
     Add(SsaValue, SsaValue, SsaValue), // destination, left, right
+    Sub(SsaValue, SsaValue, SsaValue), // destination, left, right
+    Neg(SsaValue, SsaValue),           // destination, operand
+    Inv(SsaValue, SsaValue),           // destination, operand
+    IsZero(SsaValue, SsaValue),        // destination, operand
     Mul(SsaValue, SsaValue, SsaValue), // destination, left, right
+    Square(SsaValue, SsaValue),        // destination, operand (operand * operand)
     Assert(SsaValue, SsaValue),        // left, right (left == right)
+    RangeCheck(SsaValue, u32),         // value, bits (value < 2^bits)
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct SsaValue {
     pub name: String,
     pub version: usize,
@@ -30,6 +46,23 @@ pub struct SsaBuilder {
     temp_counter: usize,
     public_inputs: Vec<SsaValue>,
     private_inputs: Vec<SsaValue>,
+    functions: HashMap<String, (Vec<String>, Vec<Spanned<Stmt>>)>, // name -> (params, body)
+    // variable name -> current SSA value; scopes[0] is the program, every
+    // inlined call pushes its own scope on top
+    scopes: Vec<HashMap<String, SsaValue>>,
+    // values bound by a const, the only program names a fn body can read
+    constants: HashSet<SsaValue>,
+    inline_stack: Vec<String>, // functions currently being inlined
+    spans: BTreeMap<SsaValue, Span>,
+    // values known to be 0 or 1: results of is_zero, != and the boolean
+    // operators, and operands already constrained by assert_boolean
+    booleans: HashSet<SsaValue>,
+}
+
+impl Default for SsaBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SsaBuilder {
@@ -40,94 +73,148 @@ impl SsaBuilder {
             temp_counter: 0,
             public_inputs: Vec::new(),
             private_inputs: Vec::new(),
+            functions: HashMap::new(),
+            scopes: vec![HashMap::new()],
+            constants: HashSet::new(),
+            inline_stack: Vec::new(),
+            spans: BTreeMap::new(),
+            booleans: HashSet::new(),
         }
     }
 
-    pub fn convert(mut self, program: Program) -> SsaProgram {
+    pub fn convert(mut self, program: Program) -> Result<SsaProgram, SsaError> {
         let mut return_value = None;
 
+        // the parser only accepts a return as the last statement, see
+        // check_statement_order
         for stmt in program.statements {
-            match stmt {
-                Stmt::PublicInput { name } => {
-                    let version = self.next_variable_version(&name);
-                    let input_ssa = SsaValue { name, version };
-                    self.public_inputs.push(input_ssa);
-                }
-                Stmt::PrivateInput { name } => {
-                    let version = self.next_variable_version(&name);
-                    let input_ssa = SsaValue { name, version };
-                    self.private_inputs.push(input_ssa);
-                }
-                Stmt::ConstDecl { name, value } => {
-                    let temp = self.new_temp();
-                    self.instructions
-                        .push(SsaInstruction::Const(temp.clone(), value));
+            if let Some(value) = self.convert_stmt(stmt)? {
+                return_value = Some(value);
+                break;
+            }
+        }
 
-                    let version = self.next_variable_version(&name);
-                    let var_ssa = SsaValue {
-                        name: name.clone(),
-                        version,
-                    };
+        Ok(SsaProgram {
+            instructions: self.instructions,
+            return_value: return_value.ok_or(SsaError::NoReturn)?,
+            public_inputs: self.public_inputs,
+            private_inputs: self.private_inputs,
+            spans: self.spans,
+        })
+    }
 
-                    if let Some(last_instr) = self.instructions.pop() {
-                        let new_instr = match last_instr {
-                            SsaInstruction::Const(_, val) => SsaInstruction::Const(var_ssa, val),
-                            _ => unreachable!(),
-                        };
-                        self.instructions.push(new_instr);
-                    }
+    // returns the value of a return statement
+    fn convert_stmt(&mut self, stmt: Spanned<Stmt>) -> Result<Option<SsaValue>, SsaError> {
+        let span = stmt.span;
+        match stmt.node {
+            Stmt::PublicInput { name, ty } => {
+                let input_ssa = self.bind_new_version(&name);
+                self.spans.insert(input_ssa.clone(), span);
+                self.public_inputs.push(input_ssa.clone());
+                self.emit_range_check(input_ssa, ty);
+            }
+            Stmt::PrivateInput { name, ty } => {
+                let input_ssa = self.bind_new_version(&name);
+                self.spans.insert(input_ssa.clone(), span);
+                self.private_inputs.push(input_ssa.clone());
+                self.emit_range_check(input_ssa, ty);
+            }
+            Stmt::ConstDecl { name, value } => {
+                let var_ssa = self.bind_new_version(&name);
+                self.spans.insert(var_ssa.clone(), span);
+                self.constants.insert(var_ssa.clone());
+                self.instructions
+                    .push(SsaInstruction::Const(var_ssa, value));
+            }
+            // every use was replaced by its element during parsing
+            Stmt::ConstArray { .. } => {}
+            // checked before SSA conversion, see const_eval::check_static_asserts
+            Stmt::StaticAssert { .. } => {}
+            Stmt::AddAssign { name, expr } => {
+                let expr = add_assign_sum(&name, &expr, span.clone());
+                return self.convert_stmt(Spanned::new(Stmt::Let { name, expr }, span));
+            }
+            Stmt::Let { name, expr } => {
+                let first_new = self.instructions.len();
+                let expr_result = self.convert_expr(expr)?;
+
+                // `let y = x` (or a call returning a parameter) produces no
+                // instruction, so the name just aliases the existing value
+                let produced_by_last = self.instructions.len() > first_new
+                    && self
+                        .instructions
+                        .last()
+                        .and_then(Self::destination)
+                        .is_some_and(|dest| *dest == expr_result);
+                if !produced_by_last {
+                    self.bind(&name, expr_result);
+                    return Ok(None);
                 }
-                Stmt::Let { name, expr } => {
-                    let _expr_result = self.convert_expr(expr);
 
-                    let version = self.next_variable_version(&name);
-                    let var_ssa = SsaValue {
-                        name: name.clone(),
-                        version,
-                    };
+                let var_ssa = self.bind_new_version(&name);
+                if let Some(expr_span) = self.spans.remove(&expr_result) {
+                    self.spans.insert(var_ssa.clone(), expr_span);
+                }
 
-                    // replace the destination of the last instruction
-                    if let Some(last_instr) = self.instructions.pop() {
-                        let new_instr = match last_instr {
-                            SsaInstruction::Const(_, value) => {
-                                SsaInstruction::Const(var_ssa, value)
-                            }
-                            SsaInstruction::Add(_, left, right) => {
-                                SsaInstruction::Add(var_ssa, left, right)
-                            }
-                            SsaInstruction::Mul(_, left, right) => {
-                                SsaInstruction::Mul(var_ssa, left, right)
-                            }
-                            SsaInstruction::Assert(left, right) => {
-                                self.instructions.push(SsaInstruction::Assert(left, right));
-                                continue;
-                            }
-                        };
-                        self.instructions.push(new_instr);
-                    }
+                // replace the destination of the last instruction
+                if let Some(last_instr) = self.instructions.pop() {
+                    let new_instr = match last_instr {
+                        SsaInstruction::Const(_, value) => SsaInstruction::Const(var_ssa, value),
+                        SsaInstruction::Add(_, left, right) => {
+                            SsaInstruction::Add(var_ssa, left, right)
+                        }
+                        SsaInstruction::Sub(_, left, right) => {
+                            SsaInstruction::Sub(var_ssa, left, right)
+                        }
+                        SsaInstruction::Neg(_, operand) => SsaInstruction::Neg(var_ssa, operand),
+                        SsaInstruction::Inv(_, operand) => SsaInstruction::Inv(var_ssa, operand),
+                        SsaInstruction::IsZero(_, operand) => {
+                            SsaInstruction::IsZero(var_ssa, operand)
+                        }
+                        SsaInstruction::Mul(_, left, right) => {
+                            SsaInstruction::Mul(var_ssa, left, right)
+                        }
+                        SsaInstruction::Square(_, operand) => {
+                            SsaInstruction::Square(var_ssa, operand)
+                        }
+                        SsaInstruction::Assert(_, _) | SsaInstruction::RangeCheck(_, _) => {
+                            unreachable!()
+                        }
+                    };
+                    self.instructions.push(new_instr);
                 }
-                Stmt::Assert { left, right } => {
-                    let left_val = self.convert_expr(left);
-                    let right_val = self.convert_expr(right);
+            }
+            Stmt::Assert { left, right } => {
+                let left_val = self.convert_expr(left)?;
+                let right_val = self.convert_expr(right)?;
+                self.instructions
+                    .push(SsaInstruction::Assert(left_val, right_val));
+            }
+            Stmt::AssertEq(operands) => {
+                // every operand is converted once, then adjacent pairs are asserted equal
+                let values = operands
+                    .into_iter()
+                    .map(|operand| self.convert_expr(operand))
+                    .collect::<Result<Vec<SsaValue>, SsaError>>()?;
+                for pair in values.windows(2) {
                     self.instructions
-                        .push(SsaInstruction::Assert(left_val, right_val));
-                }
-                Stmt::Return(expr) => {
-                    return_value = Some(self.convert_expr(expr));
+                        .push(SsaInstruction::Assert(pair[0].clone(), pair[1].clone()));
                 }
             }
+            Stmt::Return(expr) => {
+                return Ok(Some(self.convert_expr(expr)?));
+            }
+            Stmt::FnDecl { name, params, body } => {
+                self.functions.insert(name, (params, body));
+            }
         }
-
-        SsaProgram {
-            instructions: self.instructions,
-            return_value: return_value.expect("Program must have a return statement"),
-            public_inputs: self.public_inputs,
-            private_inputs: self.private_inputs,
-        }
+        Ok(None)
     }
 
-    fn convert_expr(&mut self, expr: Expr) -> SsaValue {
-        match expr {
+    fn convert_expr(&mut self, expr: Spanned<Expr>) -> Result<SsaValue, SsaError> {
+        let span = expr.span;
+        let first_new = self.instructions.len();
+        let value = match expr.node {
             Expr::Literal(n) => {
                 let temp = self.new_temp();
                 self.instructions
@@ -135,34 +222,304 @@ impl SsaBuilder {
                 temp
             }
             // no instruction generated, just reading value
-            Expr::Var(name) => {
-                let current_version = self.var_versions.get(&name).copied().unwrap_or(0);
-                SsaValue {
-                    name,
-                    version: current_version,
-                }
-            }
+            Expr::Var(name) => match self.lookup(&name) {
+                Some(value) => value,
+                None => match self.inline_stack.last() {
+                    Some(function) if self.scopes[0].contains_key(&name) => {
+                        return Err(SsaError::FreeVariable {
+                            function: function.clone(),
+                            name,
+                            span: span.clone(),
+                        })
+                    }
+                    _ => {
+                        return Err(SsaError::UndefinedVariable {
+                            name,
+                            span: span.clone(),
+                        })
+                    }
+                },
+            },
             Expr::Add(left, right) => {
-                let left_val = self.convert_expr(*left);
-                let right_val = self.convert_expr(*right);
+                let left_val = self.convert_expr(*left)?;
+                let right_val = self.convert_expr(*right)?;
                 let result = self.new_temp();
                 self.instructions
                     .push(SsaInstruction::Add(result.clone(), left_val, right_val));
                 result
             }
+            Expr::Sub(left, right) => {
+                let left_val = self.convert_expr(*left)?;
+                let right_val = self.convert_expr(*right)?;
+                let result = self.new_temp();
+                self.instructions
+                    .push(SsaInstruction::Sub(result.clone(), left_val, right_val));
+                result
+            }
+            Expr::Neg(operand) => {
+                let operand_val = self.convert_expr(*operand)?;
+                let result = self.new_temp();
+                self.instructions
+                    .push(SsaInstruction::Neg(result.clone(), operand_val));
+                result
+            }
+            Expr::Inv(operand) => {
+                let operand_val = self.convert_expr(*operand)?;
+                let result = self.new_temp();
+                self.instructions
+                    .push(SsaInstruction::Inv(result.clone(), operand_val));
+                result
+            }
+            Expr::IsZero(operand) => {
+                let operand_val = self.convert_expr(*operand)?;
+                let result = self.new_temp();
+                self.instructions
+                    .push(SsaInstruction::IsZero(result.clone(), operand_val));
+                self.booleans.insert(result.clone());
+                result
+            }
             Expr::Mul(left, right) => {
-                let left_val = self.convert_expr(*left);
-                let right_val = self.convert_expr(*right);
+                let left_val = self.convert_expr(*left)?;
+                let right_val = self.convert_expr(*right)?;
+                let result = self.new_temp();
+                self.instructions
+                    .push(SsaInstruction::Mul(result.clone(), left_val, right_val));
+                result
+            }
+            // a != b is 1 - is_zero(a - b)
+            Expr::Ne(left, right) => {
+                let left_val = self.convert_expr(*left)?;
+                let right_val = self.convert_expr(*right)?;
+                let diff = self.new_temp();
+                self.instructions
+                    .push(SsaInstruction::Sub(diff.clone(), left_val, right_val));
+                let equal = self.new_temp();
+                self.instructions
+                    .push(SsaInstruction::IsZero(equal.clone(), diff));
+                let one = self.new_temp();
+                self.instructions
+                    .push(SsaInstruction::Const(one.clone(), FieldElement::one()));
+                let result = self.new_temp();
+                self.instructions
+                    .push(SsaInstruction::Sub(result.clone(), one, equal));
+                self.booleans.insert(result.clone());
+                result
+            }
+            // a and b is a * b; the operands are constrained to 0 or 1, which
+            // makes the results of the boolean operators 0 or 1 as well
+            Expr::And(left, right) => {
+                let left_val = self.convert_expr(*left)?;
+                let right_val = self.convert_expr(*right)?;
+                self.assert_boolean(&left_val);
+                self.assert_boolean(&right_val);
                 let result = self.new_temp();
                 self.instructions
                     .push(SsaInstruction::Mul(result.clone(), left_val, right_val));
+                self.booleans.insert(result.clone());
                 result
             }
+            // a or b is a + b - a * b
+            Expr::Or(left, right) => {
+                let left_val = self.convert_expr(*left)?;
+                let right_val = self.convert_expr(*right)?;
+                self.assert_boolean(&left_val);
+                self.assert_boolean(&right_val);
+                let sum = self.new_temp();
+                self.instructions.push(SsaInstruction::Add(
+                    sum.clone(),
+                    left_val.clone(),
+                    right_val.clone(),
+                ));
+                let both = self.new_temp();
+                self.instructions
+                    .push(SsaInstruction::Mul(both.clone(), left_val, right_val));
+                let result = self.new_temp();
+                self.instructions
+                    .push(SsaInstruction::Sub(result.clone(), sum, both));
+                self.booleans.insert(result.clone());
+                result
+            }
+            // not a is 1 - a
+            Expr::Not(operand) => {
+                let operand_val = self.convert_expr(*operand)?;
+                self.assert_boolean(&operand_val);
+                let one = self.new_temp();
+                self.instructions
+                    .push(SsaInstruction::Const(one.clone(), FieldElement::one()));
+                let result = self.new_temp();
+                self.instructions
+                    .push(SsaInstruction::Sub(result.clone(), one, operand_val));
+                self.booleans.insert(result.clone());
+                result
+            }
+            Expr::Pow(base, exponent) => {
+                let base_val = self.convert_expr(*base)?;
+                self.convert_pow(base_val, exponent)
+            }
+            // no instruction of its own, name aliases the value while the
+            // body is converted, then whatever it shadowed is back
+            Expr::Let(name, value, body) => {
+                let value = self.convert_expr(*value)?;
+                let shadowed = self.shadow(&name, Some(value));
+                let result = self.convert_expr(*body);
+                self.shadow(&name, shadowed);
+                result?
+            }
+            Expr::Call(name, args) => self.inline_call(name, args, span.clone())?,
+        };
+
+        // subexpressions recorded their own spans already, what is left (the
+        // result, or the steps of a pow) belongs to this expression as a whole
+        for instr in &self.instructions[first_new..] {
+            if let Some(dest) = Self::destination(instr) {
+                self.spans.entry(dest.clone()).or_insert(span.clone());
+            }
+        }
+        Ok(value)
+    }
+
+    // calls are fully inlined: the body is converted in a fresh scope where the
+    // parameters are bound to the argument values
+    fn inline_call(
+        &mut self,
+        name: String,
+        args: Vec<Spanned<Expr>>,
+        span: Span,
+    ) -> Result<SsaValue, SsaError> {
+        if self.inline_stack.contains(&name) {
+            return Err(SsaError::RecursiveCall { name, span });
+        }
+        let Some((params, body)) = self.functions.get(&name).cloned() else {
+            return Err(SsaError::UndefinedFunction { name, span });
+        };
+
+        let mut scope = HashMap::new();
+        for (param, arg) in params.into_iter().zip(args) {
+            let arg_val = self.convert_expr(arg)?;
+            scope.insert(param, arg_val);
+        }
+
+        self.scopes.push(scope);
+        self.inline_stack.push(name.clone());
+
+        let mut return_value = None;
+        for stmt in body {
+            if let Some(value) = self.convert_stmt(stmt)? {
+                return_value = Some(value);
+            }
+        }
+
+        self.inline_stack.pop();
+        self.scopes.pop();
+
+        return_value.ok_or(SsaError::FunctionNoReturn(name))
+    }
+
+    fn convert_pow(&mut self, base: SsaValue, exponent: u32) -> SsaValue {
+        if exponent == 0 {
+            let temp = self.new_temp();
+            self.instructions
+                .push(SsaInstruction::Const(temp.clone(), FieldElement::one()));
+            return temp;
+        }
+
+        if exponent <= POW_UNROLL_LIMIT {
+            // x ** n = x * x * ... * x
+            let mut acc = base.clone();
+            for _ in 1..exponent {
+                acc = self.emit_mul(acc, base.clone());
+            }
+            return acc;
+        }
+
+        // square-and-multiply, walking the exponent bits from the most significant one
+        let mut acc = base.clone();
+        let top_bit = 31 - exponent.leading_zeros();
+        for bit in (0..top_bit).rev() {
+            acc = self.emit_mul(acc.clone(), acc);
+            if exponent & (1 << bit) != 0 {
+                acc = self.emit_mul(acc, base.clone());
+            }
+        }
+        acc
+    }
+
+    fn emit_range_check(&mut self, value: SsaValue, ty: Option<InputType>) {
+        if let Some(ty) = ty {
+            self.instructions
+                .push(SsaInstruction::RangeCheck(value, ty.bits()));
         }
     }
+
+    fn emit_mul(&mut self, left: SsaValue, right: SsaValue) -> SsaValue {
+        let result = self.new_temp();
+        self.instructions
+            .push(SsaInstruction::Mul(result.clone(), left, right));
+        result
+    }
 }
 
 impl SsaBuilder {
+    // Every binding of a name (input, const or let) gets the next version, and
+    // later reads see the latest one: `let x = x + 1` reads x.1 and binds x.2.
+    // Function locals are prefixed with the function name so every inlined
+    // call gets fresh versions that can't clash with the caller's variables.
+    fn bind_new_version(&mut self, name: &str) -> SsaValue {
+        let ssa_name = match self.inline_stack.last() {
+            Some(function) => format!("{}::{}", function, name),
+            None => name.to_string(),
+        };
+        let version = self.next_variable_version(&ssa_name);
+        let value = SsaValue {
+            name: ssa_name,
+            version,
+        };
+        self.bind(name, value.clone());
+        value
+    }
+
+    fn bind(&mut self, name: &str, value: SsaValue) {
+        self.scopes
+            .last_mut()
+            .expect("scope stack is never empty")
+            .insert(name.to_string(), value);
+    }
+
+    // binds name in the innermost scope to value, or unbinds it for None,
+    // returning what it was bound to there before
+    fn shadow(&mut self, name: &str, value: Option<SsaValue>) -> Option<SsaValue> {
+        let scope = self.scopes.last_mut().expect("scope stack is never empty");
+        match value {
+            Some(value) => scope.insert(name.to_string(), value),
+            None => scope.remove(name),
+        }
+    }
+
+    // the innermost scope first, then the program scope. A fn body sees
+    // its parameters, its own lets and the program's consts, not the
+    // program's inputs or lets: those reach it as arguments.
+    fn lookup(&self, name: &str) -> Option<SsaValue> {
+        let innermost = self.scopes.last().and_then(|scope| scope.get(name));
+        let program = self.scopes[0]
+            .get(name)
+            .filter(|value| self.inline_stack.is_empty() || self.constants.contains(value));
+        innermost.or(program).cloned()
+    }
+
+    fn destination(instr: &SsaInstruction) -> Option<&SsaValue> {
+        match instr {
+            SsaInstruction::Const(dest, _)
+            | SsaInstruction::Add(dest, _, _)
+            | SsaInstruction::Sub(dest, _, _)
+            | SsaInstruction::Neg(dest, _)
+            | SsaInstruction::Inv(dest, _)
+            | SsaInstruction::IsZero(dest, _)
+            | SsaInstruction::Mul(dest, _, _)
+            | SsaInstruction::Square(dest, _) => Some(dest),
+            SsaInstruction::Assert(_, _) | SsaInstruction::RangeCheck(_, _) => None,
+        }
+    }
+
     fn next_variable_version(&mut self, name: &str) -> usize {
         *self
             .var_versions
@@ -171,6 +528,19 @@ impl SsaBuilder {
             .or_insert(1)
     }
 
+    // value * value == value, true only for 0 and 1; skipped for values
+    // already known to be one of them
+    fn assert_boolean(&mut self, value: &SsaValue) {
+        if !self.booleans.insert(value.clone()) {
+            return;
+        }
+        let square = self.new_temp();
+        self.instructions
+            .push(SsaInstruction::Square(square.clone(), value.clone()));
+        self.instructions
+            .push(SsaInstruction::Assert(square, value.clone()));
+    }
+
     fn new_temp(&mut self) -> SsaValue {
         let temp_name = format!("t{}", self.temp_counter);
         let new_temp = SsaValue {
@@ -182,19 +552,315 @@ impl SsaBuilder {
     }
 }
 
+#[derive(Debug)]
+pub enum SsaError {
+    NoReturn,
+    FunctionNoReturn(String), // function name
+    UndefinedFunction {
+        name: String,
+        span: Span,
+    }, // call site
+    RecursiveCall {
+        name: String,
+        span: Span,
+    }, // call site
+    UndefinedVariable {
+        name: String,
+        span: Span,
+    }, // the read
+    FreeVariable {
+        function: String,
+        name: String,
+        span: Span,
+    }, // a program name read in a fn body
+}
+
+impl std::fmt::Display for SsaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SsaError::NoReturn => write!(f, "Program must have a return statement"),
+            SsaError::FunctionNoReturn(name) => {
+                write!(f, "Function {} must have a return statement", name)
+            }
+            SsaError::UndefinedFunction { name, span } => {
+                write!(f, "Call to undefined function {} at {}", name, span)
+            }
+            SsaError::RecursiveCall { name, span } => {
+                write!(f, "Recursive call to function {} at {}", name, span)
+            }
+            SsaError::UndefinedVariable { name, span } => {
+                write!(f, "Undefined variable {} at {}", name, span)
+            }
+            SsaError::FreeVariable {
+                function,
+                name,
+                span,
+            } => write!(
+                f,
+                "Function {} reads {} at {}, which is not a parameter, local or const; pass it as an argument",
+                function, name, span
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SsaError {}
+
+impl SsaValue {
+    // bound by `let _ = ...`, in the program or an inlined function body
+    pub fn is_discarded(&self) -> bool {
+        self.name == "_" || self.name.ends_with("::_")
+    }
+}
+
 impl std::fmt::Display for SsaValue {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}.{}", self.name, self.version)
     }
 }
 
+impl SsaProgram {
+    // every instruction as in listing::aligned_lines, then the return value
+    pub fn to_aligned_string(&self) -> String {
+        let rows: Vec<ListingRow> = self
+            .instructions
+            .iter()
+            .enumerate()
+            .map(|(index, instr)| {
+                let (dest, rest) = instr.listing_parts();
+                ListingRow {
+                    index,
+                    assigned: dest.map(|dest| dest.to_string()),
+                    rest,
+                }
+            })
+            .collect();
+        let mut lines = aligned_lines(&rows);
+        lines.push(format!("return {}", self.return_value));
+        lines.join("\n")
+    }
+}
+
+impl SsaInstruction {
+    // the value assigned, if any, and what it is assigned, as in Gate's listing
+    pub(crate) fn listing_parts(&self) -> (Option<&SsaValue>, String) {
+        match self {
+            SsaInstruction::Const(dest, value) => (Some(dest), value.to_string()),
+            SsaInstruction::Add(dest, left, right) => (Some(dest), format!("{} + {}", left, right)),
+            SsaInstruction::Sub(dest, left, right) => (Some(dest), format!("{} - {}", left, right)),
+            SsaInstruction::Neg(dest, operand) => (Some(dest), format!("-{}", operand)),
+            SsaInstruction::Inv(dest, operand) => (Some(dest), format!("inv({})", operand)),
+            SsaInstruction::IsZero(dest, operand) => (Some(dest), format!("is_zero({})", operand)),
+            SsaInstruction::Mul(dest, left, right) => (Some(dest), format!("{} * {}", left, right)),
+            SsaInstruction::Square(dest, operand) => (Some(dest), format!("square({})", operand)),
+            SsaInstruction::Assert(left, right) => (None, format!("assert {} == {}", left, right)),
+            SsaInstruction::RangeCheck(value, bits) => {
+                (None, format!("range_check {} < 2^{}", value, bits))
+            }
+        }
+    }
+}
+
 impl std::fmt::Display for SsaInstruction {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            SsaInstruction::Const(dest, value) => write!(f, "{} = {}", dest, value),
-            SsaInstruction::Add(dest, left, right) => write!(f, "{} = {} + {}", dest, left, right),
-            SsaInstruction::Mul(dest, left, right) => write!(f, "{} = {} * {}", dest, left, right),
-            SsaInstruction::Assert(left, right) => write!(f, "assert {} == {}", left, right),
+        match self.listing_parts() {
+            (Some(dest), rest) => write!(f, "{} = {}", dest, rest),
+            (None, rest) => write!(f, "{}", rest),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parse_str;
+
+    fn convert(source: &str) -> SsaProgram {
+        SsaBuilder::new()
+            .convert(parse_str(source).unwrap())
+            .unwrap()
+    }
+
+    fn multiplications(program: &SsaProgram) -> usize {
+        program
+            .instructions
+            .iter()
+            .filter(|instr| matches!(instr, SsaInstruction::Mul(..) | SsaInstruction::Square(..)))
+            .count()
+    }
+
+    #[test]
+    fn pow_16_squares_instead_of_unrolling() {
+        let squared = convert("public x\nreturn x ** 16");
+        let unrolled = convert(&format!("public x\nreturn x{}", " * x".repeat(15)));
+        assert_eq!(multiplications(&squared), 4);
+        assert_eq!(multiplications(&unrolled), 15);
+    }
+
+    #[test]
+    fn small_exponents_unroll() {
+        assert_eq!(multiplications(&convert("public x\nreturn x ** 3")), 2);
+        assert_eq!(multiplications(&convert("public x\nreturn x ** 0")), 0);
+    }
+
+    fn value(name: &str, version: usize) -> SsaValue {
+        SsaValue {
+            name: name.to_string(),
+            version,
         }
     }
+
+    #[test]
+    fn rebinding_bumps_the_version() {
+        let program = convert("let x = 1\nlet x = x + 1\nreturn x");
+        assert!(
+            matches!(&program.instructions[0], SsaInstruction::Const(dest, _) if *dest == value("x", 1))
+        );
+        assert!(matches!(
+            &program.instructions[2],
+            SsaInstruction::Add(dest, left, _) if *dest == value("x", 2) && *left == value("x", 1)
+        ));
+        assert_eq!(program.return_value, value("x", 2));
+    }
+
+    #[test]
+    fn reads_take_the_current_version() {
+        let program = convert("public x\nlet y = x * x\nlet x = y + 1\nreturn x * y");
+        assert_eq!(program.public_inputs, [value("x", 1)]);
+        assert!(matches!(
+            &program.instructions[0],
+            SsaInstruction::Mul(_, left, right) if *left == value("x", 1) && *right == value("x", 1)
+        ));
+        assert!(matches!(
+            program.instructions.last().unwrap(),
+            SsaInstruction::Mul(_, left, right) if *left == value("x", 2) && *right == value("y", 1)
+        ));
+    }
+
+    #[test]
+    fn function_locals_do_not_shadow_the_caller() {
+        let program =
+            convert("fn f(a) {\nlet x = a + 1\nreturn x\n}\npublic x\nlet x = f(x) + x\nreturn x");
+        assert!(matches!(
+            &program.instructions[1],
+            SsaInstruction::Add(dest, left, _) if *dest == value("f::x", 1) && *left == value("x", 1)
+        ));
+        assert!(matches!(
+            &program.instructions[2],
+            SsaInstruction::Add(dest, left, right)
+                if *dest == value("x", 2) && *left == value("f::x", 1) && *right == value("x", 1)
+        ));
+    }
+
+    #[test]
+    fn let_in_binds_its_name_for_the_body_only() {
+        let circuit =
+            crate::compiler::compile_str("public x\nreturn (let a = x+1 in a*a)").unwrap();
+        let inputs = crate::witness::InputFile::builder().public("x", 3).build();
+        let result = crate::witness::WitnessCalculator::new()
+            .calculate_witness(&circuit, inputs)
+            .unwrap();
+        assert_eq!(result, FieldElement::new(16));
+
+        // an outer a is back after the body
+        let program = convert("public a\nlet b = let a = a + 1 in a * a\nreturn b + a");
+        assert!(matches!(
+            program.instructions.last().unwrap(),
+            SsaInstruction::Add(_, _, right) if *right == value("a", 1)
+        ));
+    }
+
+    // Reading a name nothing binds used to silently read its version 0, a
+    // value no instruction defines; with let ... in unbinding names after
+    // the body that is an error instead
+    #[test]
+    fn reading_an_unbound_name_is_an_error() {
+        let program = parse_str("public x\nlet y = let a = x in a\nreturn y + a").unwrap();
+        let err = SsaBuilder::new().convert(program).unwrap_err();
+        assert!(matches!(&err, SsaError::UndefinedVariable { name, .. } if name == "a"));
+        assert_eq!(err.to_string(), "Undefined variable a at 43..44");
+
+        assert!(parse_str("public in\nreturn in").is_err());
+    }
+
+    #[test]
+    fn square_of_a_sum_inlines_to_the_product_of_the_sum() {
+        let program = convert("public a\nfn square(x) {\nreturn x * x\n}\nreturn square(a + 1)");
+        // (a + 1) is computed once, at the call site, and multiplied by itself
+        let [SsaInstruction::Const(one, _), SsaInstruction::Add(sum, left, right), SsaInstruction::Mul(product, mul_left, mul_right)] =
+            &program.instructions[..]
+        else {
+            panic!(
+                "expected a + 1 then its square, got {:?}",
+                program.instructions
+            );
+        };
+        assert_eq!(*left, value("a", 1));
+        assert_eq!(right, one);
+        assert_eq!(mul_left, sum);
+        assert_eq!(mul_right, sum);
+        assert_eq!(program.return_value, *product);
+    }
+
+    #[test]
+    fn fn_bodies_read_consts_but_not_other_program_names() {
+        let program = convert("const K = 3\nfn f(x) {\nreturn x * K\n}\npublic a\nreturn f(a)");
+        assert!(matches!(
+            program.instructions.last().unwrap(),
+            SsaInstruction::Mul(_, _, right) if *right == value("K", 1)
+        ));
+
+        for source in [
+            "public a\nfn f(x) {\nreturn x * a\n}\nreturn f(2)",
+            "public a\nlet b = a + 1\nfn f(x) {\nreturn x * b\n}\nreturn f(a)",
+        ] {
+            let err = SsaBuilder::new()
+                .convert(parse_str(source).unwrap())
+                .unwrap_err();
+            assert!(
+                matches!(&err, SsaError::FreeVariable { function, .. } if function == "f"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn aligned_listing_lines_up_names_of_different_lengths() {
+        let program = convert(
+            "public x\nprivate a_much_longer_name\nlet y = x * a_much_longer_name\nlet total = y + x\nassert total == x\nreturn total",
+        );
+        let listing = program.to_aligned_string();
+        let columns: Vec<usize> = listing
+            .lines()
+            .filter_map(|line| line.find(" = "))
+            .collect();
+        assert_eq!(columns.len(), 2, "{}", listing);
+        assert!(
+            columns.iter().all(|&column| column == columns[0]),
+            "{}",
+            listing
+        );
+        // the padding goes after the shorter names, not inside them
+        assert!(
+            listing.contains("0: y.1     = x.1 * a_much_longer_name.1"),
+            "{}",
+            listing
+        );
+        assert!(listing.contains("1: total.1 = y.1 + x.1"), "{}", listing);
+        assert!(listing.contains("2: assert total.1 == x.1"), "{}", listing);
+    }
+
+    #[test]
+    fn add_assign_lowers_to_the_same_ssa_as_the_explicit_sum() {
+        let sugared =
+            convert("public x\nprivate y\nlet acc = 0\nacc += x\nacc += y * 2\nreturn acc");
+        let explicit = convert(
+            "public x\nprivate y\nlet acc = 0\nlet acc = acc + x\nlet acc = acc + y * 2\nreturn acc",
+        );
+        // the listing shows every instruction and the return value
+        assert_eq!(sugared.to_aligned_string(), explicit.to_aligned_string());
+        // each += is a new version of acc
+        assert_eq!(sugared.return_value, value("acc", 3));
+    }
 }
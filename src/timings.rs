@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+// wall-clock time spent per compiler stage, in the order stages first ran;
+// a stage that runs more than once (the -O2 passes) adds up into one entry
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    pub entries: Vec<(String, Duration)>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // runs f and adds the time it took to `stage`
+    pub fn time<T>(&mut self, stage: &str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(stage, start.elapsed());
+        result
+    }
+
+    pub fn record(&mut self, stage: &str, elapsed: Duration) {
+        match self.entries.iter_mut().find(|(name, _)| name == stage) {
+            Some((_, total)) => *total += elapsed,
+            None => self.entries.push((stage.to_string(), elapsed)),
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.entries.iter().map(|(_, elapsed)| *elapsed).sum()
+    }
+}
+
+// one row per stage with its share of the total, then the total
+impl std::fmt::Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let total = self.total();
+        let width = self
+            .entries
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("total".len());
+
+        for (name, elapsed) in &self.entries {
+            let share = if total.is_zero() {
+                0.0
+            } else {
+                100.0 * elapsed.as_secs_f64() / total.as_secs_f64()
+            };
+            writeln!(
+                f,
+                "{:<width$}  {:>10.3} ms  {:>5.1}%",
+                name,
+                elapsed.as_secs_f64() * 1000.0,
+                share,
+                width = width
+            )?;
+        }
+        write!(
+            f,
+            "{:<width$}  {:>10.3} ms",
+            "total",
+            total.as_secs_f64() * 1000.0,
+            width = width
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parse_str;
+    use crate::optimizer::{optimize_timed, OptLevel};
+    use crate::ssa::SsaBuilder;
+
+    #[test]
+    fn repeated_stage_adds_up_into_one_entry() {
+        let mut timings = Timings::new();
+        timings.record("parse", Duration::from_millis(2));
+        timings.record("ssa", Duration::from_millis(1));
+        timings.record("parse", Duration::from_millis(3));
+        assert_eq!(
+            timings.entries,
+            [
+                ("parse".to_string(), Duration::from_millis(5)),
+                ("ssa".to_string(), Duration::from_millis(1)),
+            ]
+        );
+        assert_eq!(timings.total(), Duration::from_millis(6));
+        // a row per stage and the total
+        assert_eq!(timings.to_string().lines().count(), 3);
+    }
+
+    #[test]
+    fn o2_records_one_entry_per_pass() {
+        let program = SsaBuilder::new()
+            .convert(
+                parse_str("public x\nlet a = x * 1\nlet b = a + 0\nreturn b * (2 + 3)").unwrap(),
+            )
+            .unwrap();
+        let mut timings = Timings::new();
+        optimize_timed(program, OptLevel::O2, &mut timings);
+        let stages: Vec<&str> = timings
+            .entries
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(
+            stages,
+            [
+                "peephole",
+                "constant folding",
+                "redundant asserts",
+                "dce",
+                "algebraic simplification",
+                "cse"
+            ]
+        );
+    }
+}
@@ -1,23 +1,48 @@
+use crate::field::FieldElement;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     Let,
     Return,
     Assert,
+    AssertEq,
+    StaticAssert,
 
     Public,
     Private,
     Const,
+    Fn,
+    Include,
+    In,
+    And,
+    Or,
+    Not,
 
     Star,
+    StarStar,
     Plus,
+    PlusEquals,
+    Minus,
     Equals,
     EqualsEquals,
+    BangEquals,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
 
     Identifier(String),
-    Number(i32),
+    Number(FieldElement),
+    Str(String),
 
     LeftParen,
     RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    Colon,
     Eof,
 }
 
@@ -1,21 +1,138 @@
-use crate::circuit::{Circuit, Gate, Wire};
+use crate::circuit::{write_json_file, Circuit, Gate, HintOp, InputKind, Wire};
+use crate::field::FieldElement;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct InputFile {
-    pub public: Option<HashMap<String, i32>>,
-    pub private: Option<HashMap<String, i32>>,
+    pub public: Option<HashMap<String, FieldElement>>,
+    pub private: Option<HashMap<String, FieldElement>>,
+    // public inputs fixed at compile time, they become constants in the circuit
+    pub pin: Option<HashMap<String, FieldElement>>,
+}
+
+// an inputs file before its values are checked, see InputFile::load_from_file
+#[derive(Deserialize)]
+struct RawInputFile {
+    public: Option<HashMap<String, serde_json::Value>>,
+    private: Option<HashMap<String, serde_json::Value>>,
+    pin: Option<HashMap<String, serde_json::Value>>,
+}
+
+impl InputFile {
+    // `.json` files are parsed with serde_json, anything else as TOML
+    pub fn load_from_file(filename: &str) -> Result<InputFile, Box<dyn std::error::Error>> {
+        let content = std::fs::read_to_string(filename)?;
+        let is_json = std::path::Path::new(filename)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+
+        let raw: RawInputFile = if is_json {
+            serde_json::from_str(&content)?
+        } else {
+            toml::from_str(&content)?
+        };
+        Ok(InputFile {
+            public: field_values(raw.public)?,
+            private: field_values(raw.private)?,
+            pin: field_values(raw.pin)?,
+        })
+    }
+}
+
+impl InputFile {
+    pub fn builder() -> InputFileBuilder {
+        InputFileBuilder::default()
+    }
+
+    pub fn from_public(public: HashMap<String, FieldElement>) -> InputFile {
+        InputFile {
+            public: Some(public),
+            private: None,
+            pin: None,
+        }
+    }
+
+    pub fn from_private(private: HashMap<String, FieldElement>) -> InputFile {
+        InputFile {
+            public: None,
+            private: Some(private),
+            pin: None,
+        }
+    }
+
+    pub fn from_public_and_private(
+        public: HashMap<String, FieldElement>,
+        private: HashMap<String, FieldElement>,
+    ) -> InputFile {
+        InputFile {
+            public: Some(public),
+            private: Some(private),
+            pin: None,
+        }
+    }
+}
+
+// Inputs set one at a time, for building an InputFile in code:
+// InputFile::builder().public("x", 3).private("y", 5).build(). A section
+// nothing was set in stays None, like one missing from the file.
+#[derive(Debug, Clone, Default)]
+pub struct InputFileBuilder {
+    input_file: InputFile,
+}
+
+impl InputFileBuilder {
+    pub fn public(mut self, name: &str, value: impl Into<FieldElement>) -> Self {
+        let public = self.input_file.public.get_or_insert_with(HashMap::new);
+        public.insert(name.to_string(), value.into());
+        self
+    }
+
+    pub fn private(mut self, name: &str, value: impl Into<FieldElement>) -> Self {
+        let private = self.input_file.private.get_or_insert_with(HashMap::new);
+        private.insert(name.to_string(), value.into());
+        self
+    }
+
+    pub fn pin(mut self, name: &str, value: impl Into<FieldElement>) -> Self {
+        let pin = self.input_file.pin.get_or_insert_with(HashMap::new);
+        pin.insert(name.to_string(), value.into());
+        self
+    }
+
+    pub fn build(self) -> InputFile {
+        self.input_file
+    }
+}
+
+// values are checked one by one so the error can name the offending input
+fn field_values(
+    section: Option<HashMap<String, serde_json::Value>>,
+) -> Result<Option<HashMap<String, FieldElement>>, WitnessError> {
+    let Some(section) = section else {
+        return Ok(None);
+    };
+    section
+        .into_iter()
+        .map(|(name, value)| match FieldElement::deserialize(&value) {
+            Ok(element) => Ok((name, element)),
+            Err(_) => Err(WitnessError::InputNotInteger(name, value.to_string())),
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
 }
 
 #[derive(Debug)]
 pub enum WitnessError {
     MissingPublicInput(String),
     MissingPrivateInput(String),
-    MissingWireValue(String),
+    MissingWireValue(String, Vec<String>), // wire, dependency chain explaining why
     NoPublicInputsProvided,
     NoPrivateInputsProvided,
-    AssertionFailed(i32, i32),
+    AssertionFailed(FieldElement, FieldElement, Vec<String>), // left, right, operand trace
+    InverseOfZero(String),                                    // wire
+    OutOfRange(String, FieldElement, usize),                  // wire, value, bits
+    InputNotInteger(String, String),                          // input, value as written
 }
 
 impl std::fmt::Display for WitnessError {
@@ -23,16 +140,39 @@ impl std::fmt::Display for WitnessError {
         match self {
             WitnessError::MissingPublicInput(name) => write!(f, "Missing public input: {}", name),
             WitnessError::MissingPrivateInput(name) => write!(f, "Missing private input: {}", name),
-            WitnessError::MissingWireValue(wire) => write!(f, "Wire {} has no value", wire),
+            WitnessError::MissingWireValue(wire, chain) => {
+                write!(f, "Wire {} has no value", wire)?;
+                for step in chain {
+                    write!(f, "\n  {}", step)?;
+                }
+                Ok(())
+            }
             WitnessError::NoPublicInputsProvided => {
                 write!(f, "Circuit requires public inputs but none provided")
             }
             WitnessError::NoPrivateInputsProvided => {
                 write!(f, "Circuit requires private inputs but none provided")
             }
-            WitnessError::AssertionFailed(left, right) => {
-                write!(f, "Assertion failed: {} != {}", left, right)
+            WitnessError::AssertionFailed(left, right, trace) => {
+                write!(f, "Assertion failed: {} != {}", left, right)?;
+                for line in trace {
+                    write!(f, "\n  {}", line)?;
+                }
+                Ok(())
+            }
+            WitnessError::InverseOfZero(wire) => {
+                write!(f, "Cannot invert wire {}: its value is zero", wire)
             }
+            WitnessError::OutOfRange(wire, value, bits) => write!(
+                f,
+                "Wire {} holds {}, which does not fit in {} bits",
+                wire, value, bits
+            ),
+            WitnessError::InputNotInteger(name, value) => write!(
+                f,
+                "Input {} = {} is not an integer or a decimal string",
+                name, value
+            ),
         }
     }
 }
@@ -40,17 +180,54 @@ impl std::fmt::Display for WitnessError {
 impl std::error::Error for WitnessError {}
 
 pub struct WitnessCalculator {
-    wire_values: HashMap<Wire, i32>,
+    wire_values: HashMap<Wire, FieldElement>,
+    // above 1, gates are evaluated layer by layer (see Circuit::gate_layers)
+    // with each large enough layer split across this many threads
+    threads: usize,
+    // gate kind -> the evaluator replacing Gate's own for it
+    evaluators: HashMap<String, Box<dyn EvalGate>>,
+}
+
+// levels of gates an assertion failure traces its operands back through
+const OPERAND_TRACE_DEPTH: usize = 4;
+
+// layers with fewer gates than this per thread are not worth spawning for
+const MIN_GATES_PER_THREAD: usize = 1024;
+
+impl Default for WitnessCalculator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl WitnessCalculator {
     pub fn new() -> Self {
+        Self::with_threads(1)
+    }
+
+    // the witness is identical for any thread count, only the time to compute it changes
+    pub fn with_threads(threads: usize) -> Self {
         Self {
             wire_values: HashMap::new(),
+            threads: threads.max(1),
+            evaluators: HashMap::new(),
         }
     }
 
-    fn get_wire_value(&self, wire: &Wire) -> Option<i32> {
+    // Evaluates every gate of `kind` (as in Gate::kind) with `evaluator`
+    // instead of the built-in evaluation, replacing any evaluator registered
+    // for it before
+    pub fn register_evaluator(&mut self, kind: &str, evaluator: impl EvalGate + 'static) {
+        self.evaluators
+            .insert(kind.to_string(), Box::new(evaluator));
+    }
+
+    // forgets every wire value, calculate_witness starts with this
+    pub fn reset(&mut self) {
+        self.wire_values.clear();
+    }
+
+    fn get_wire_value(&self, wire: &Wire) -> Option<FieldElement> {
         self.wire_values.get(wire).copied()
     }
 
@@ -58,46 +235,169 @@ impl WitnessCalculator {
         &mut self,
         circuit: &Circuit,
         inputs: InputFile,
-    ) -> Result<i32, WitnessError> {
+    ) -> Result<FieldElement, WitnessError> {
+        self.reset();
+        self.wire_values.insert(Wire::ONE, FieldElement::one());
         self.set_inputs(circuit, inputs)?;
 
-        for gate in &circuit.gates {
-            self.execute_gate(gate)?;
+        if self.threads > 1 {
+            self.execute_layers(circuit)?;
+        } else {
+            for (index, gate) in circuit.gates.iter().enumerate() {
+                self.execute_gate(circuit, gate)
+                    .map_err(|err| gate_error(err, index, gate))?;
+            }
         }
 
-        self.get_wire_value(&circuit.output_wire)
-            .ok_or_else(|| WitnessError::MissingWireValue(circuit.output_wire.to_string()))
+        self.read_wire(circuit, &circuit.output_wire)
     }
 
-    pub fn save_r1cs_witness(
+    // Evaluates one layer at a time against the values of the layers below it,
+    // then stores the whole layer. When several gates fail, the error reported
+    // is the one of the earliest failing gate in the lowest failing layer.
+    fn execute_layers(&mut self, circuit: &Circuit) -> Result<(), WitnessError> {
+        for layer in circuit.gate_layers() {
+            let threads = self.threads.min(layer.len() / MIN_GATES_PER_THREAD).max(1);
+            let chunk_size = layer.len().div_ceil(threads);
+
+            let calculator = &*self;
+            let results: Vec<Result<Vec<(Wire, FieldElement)>, WitnessError>> =
+                std::thread::scope(|scope| {
+                    let handles: Vec<_> = layer
+                        .chunks(chunk_size)
+                        .map(|chunk| scope.spawn(move || calculator.evaluate_gates(circuit, chunk)))
+                        .collect();
+                    handles
+                        .into_iter()
+                        .map(|handle| handle.join().expect("witness thread panicked"))
+                        .collect()
+                });
+
+            for assignments in results {
+                self.wire_values.extend(assignments?);
+            }
+        }
+        Ok(())
+    }
+
+    fn evaluate_gates(
         &self,
         circuit: &Circuit,
-        filename: &str,
-        result: i32,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        use serde_json::json;
+        gate_indices: &[usize],
+    ) -> Result<Vec<(Wire, FieldElement)>, WitnessError> {
+        let mut assignments = Vec::new();
+        for &index in gate_indices {
+            let gate = &circuit.gates[index];
+            assignments.extend(
+                self.evaluate_gate(circuit, gate)
+                    .map_err(|err| gate_error(err, index, gate))?,
+            );
+        }
+        Ok(assignments)
+    }
+
+    // runs every input row against the same circuit, starting each row from
+    // empty wire values; one result per row, in order
+    pub fn calculate_batch(
+        &mut self,
+        circuit: &Circuit,
+        inputs: &[InputFile],
+    ) -> Vec<Result<FieldElement, WitnessError>> {
+        inputs
+            .iter()
+            .map(|row| self.calculate_witness(circuit, row.clone()))
+            .collect()
+    }
 
+    // wire values indexed by wire id, unset wires are zero
+    pub fn witness_vector(&self) -> Vec<FieldElement> {
         let max_wire_id = self
             .wire_values
             .keys()
             .map(|wire| wire.id)
             .max()
             .unwrap_or(0);
-        let mut witness = vec![0; max_wire_id + 1];
+        let mut witness = vec![FieldElement::zero(); max_wire_id + 1];
 
         for (wire, value) in &self.wire_values {
             witness[wire.id] = *value;
         }
+        witness
+    }
 
-        let mut public_inputs = HashMap::new();
-        for (name, wire) in &circuit.public_inputs {
-            public_inputs.insert(name, self.get_wire_value(wire).unwrap_or(0));
-        }
+    // Every wire value as FieldElement::BYTES little-endian bytes, in wire id
+    // order for all circuit.num_wires() wires, unset wires zero; no header, so
+    // the buffer can be hashed or mapped as is. witness_from_bytes reads it back.
+    pub fn to_bytes(&self, circuit: &Circuit) -> Vec<u8> {
+        (0..circuit.num_wires())
+            .flat_map(|id| {
+                self.get_wire_value(&Wire { id })
+                    .unwrap_or(FieldElement::zero())
+                    .to_le_bytes()
+            })
+            .collect()
+    }
 
-        let mut private_inputs = HashMap::new();
-        for (name, wire) in &circuit.private_inputs {
-            private_inputs.insert(name, self.get_wire_value(wire).unwrap_or(0));
+    // (wire id, label, value) for every wire that has a value, by id; wire 0 is
+    // labelled "one", inputs by their name and the output wire "output"
+    pub fn dump_wire_values(
+        &self,
+        circuit: &Circuit,
+    ) -> Vec<(usize, Option<String>, FieldElement)> {
+        let mut labels: HashMap<&Wire, String> = HashMap::new();
+        labels.insert(&circuit.output_wire, "output".to_string());
+        for (name, wire) in circuit.public_inputs.iter().chain(&circuit.private_inputs) {
+            labels.insert(wire, name.clone());
         }
+        labels.insert(&Wire::ONE, "one".to_string());
+
+        let mut dump: Vec<(usize, Option<String>, FieldElement)> = self
+            .wire_values
+            .iter()
+            .map(|(wire, value)| (wire.id, labels.get(wire).cloned(), *value))
+            .collect();
+        dump.sort_by_key(|(id, _, _)| *id);
+        dump
+    }
+
+    // wire values in the given order, see Circuit::canonical_order
+    pub fn ordered_witness(&self, order: &[usize]) -> Vec<FieldElement> {
+        order
+            .iter()
+            .map(|id| {
+                self.get_wire_value(&Wire { id: *id })
+                    .unwrap_or(FieldElement::zero())
+            })
+            .collect()
+    }
+
+    // `order` writes the witness in that wire order instead of by wire id
+    pub fn save_r1cs_witness(
+        &self,
+        circuit: &Circuit,
+        filename: &str,
+        result: FieldElement,
+        order: Option<&[usize]>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        use serde_json::json;
+
+        let witness = match order {
+            Some(order) => self.ordered_witness(order),
+            None => self.witness_vector(),
+        };
+
+        // in declaration order, as [name, value] pairs
+        let input_values = |inputs: &[(String, Wire)]| -> Vec<(String, FieldElement)> {
+            inputs
+                .iter()
+                .map(|(name, wire)| {
+                    let value = self.get_wire_value(wire).unwrap_or(FieldElement::zero());
+                    (name.clone(), value)
+                })
+                .collect()
+        };
+        let public_inputs = input_values(&circuit.public_inputs);
+        let private_inputs = input_values(&circuit.private_inputs);
 
         let witness_data = json!({
             "witness": witness,
@@ -107,93 +407,911 @@ impl WitnessCalculator {
             "num_wires": witness.len()
         });
 
-        std::fs::write(filename, serde_json::to_string_pretty(&witness_data)?)?;
-        Ok(())
+        write_json_file(filename, &witness_data)
     }
 
+    // assigns the inputs in Circuit::ordered_inputs order, so the first
+    // missing one reported is the same on every run
     fn set_inputs(&mut self, circuit: &Circuit, inputs: InputFile) -> Result<(), WitnessError> {
-        if let Some(public_vals) = inputs.public {
-            for (name, wire) in &circuit.public_inputs {
-                if let Some(value) = public_vals.get(name) {
-                    self.wire_values.insert(wire.clone(), *value);
-                } else {
-                    return Err(WitnessError::MissingPublicInput(name.clone()));
-                }
-            }
-        } else if !circuit.public_inputs.is_empty() {
-            return Err(WitnessError::NoPublicInputsProvided);
+        for (kind, name, wire) in circuit.ordered_inputs() {
+            let value = match kind {
+                InputKind::Public => inputs
+                    .public
+                    .as_ref()
+                    .ok_or(WitnessError::NoPublicInputsProvided)?
+                    .get(&name)
+                    .ok_or_else(|| WitnessError::MissingPublicInput(name.clone()))?,
+                InputKind::Private => inputs
+                    .private
+                    .as_ref()
+                    .ok_or(WitnessError::NoPrivateInputsProvided)?
+                    .get(&name)
+                    .ok_or_else(|| WitnessError::MissingPrivateInput(name.clone()))?,
+            };
+            self.wire_values.insert(wire, *value);
         }
+        Ok(())
+    }
 
-        if let Some(private_vals) = inputs.private {
-            for (name, wire) in &circuit.private_inputs {
-                if let Some(value) = private_vals.get(name) {
-                    self.wire_values.insert(wire.clone(), *value);
-                } else {
-                    return Err(WitnessError::MissingPrivateInput(name.clone()));
-                }
-            }
-        } else if !circuit.private_inputs.is_empty() {
-            return Err(WitnessError::NoPrivateInputsProvided);
-        }
+    fn execute_gate(&mut self, circuit: &Circuit, gate: &Gate) -> Result<(), WitnessError> {
+        let assignments = self.evaluate_gate(circuit, gate)?;
+        self.wire_values.extend(assignments);
         Ok(())
     }
 
-    fn execute_gate(&mut self, gate: &Gate) -> Result<(), WitnessError> {
-        match gate {
-            Gate::Const { output, value } => {
-                self.wire_values.insert(output.clone(), *value);
-                Ok(())
-            }
+    // The values a gate assigns, computed without touching wire_values so gates
+    // of the same layer can be evaluated from several threads at once. A gate
+    // kind with a registered evaluator is evaluated by it, the rest by Gate.
+    fn evaluate_gate(
+        &self,
+        circuit: &Circuit,
+        gate: &Gate,
+    ) -> Result<Vec<(Wire, FieldElement)>, WitnessError> {
+        let mut ctx = WireEnv {
+            calculator: self,
+            circuit,
+            gate,
+            assignments: Vec::new(),
+        };
+        match self.evaluators.get(gate.kind()) {
+            Some(evaluator) => evaluator.eval(&mut ctx)?,
+            None => gate.eval(&mut ctx)?,
+        }
+        Ok(ctx.assignments)
+    }
+}
+
+// Computes the wires a gate assigns from the wires it reads. Gate implements
+// it for every built-in gate kind; an evaluator registered with
+// WitnessCalculator::register_evaluator replaces that for one kind and finds
+// the gate it evaluates in ctx.gate().
+pub trait EvalGate: Send + Sync {
+    fn eval(&self, ctx: &mut WireEnv) -> Result<(), WitnessError>;
+}
+
+impl EvalGate for Gate {
+    fn eval(&self, ctx: &mut WireEnv) -> Result<(), WitnessError> {
+        match self {
+            Gate::Const { output, value } => ctx.assign(output, *value),
             Gate::Add {
                 output,
                 left,
                 right,
             } => {
-                let left_val = self
-                    .get_wire_value(left)
-                    .ok_or_else(|| WitnessError::MissingWireValue(left.to_string()))?;
-                let right_val = self
-                    .get_wire_value(right)
-                    .ok_or_else(|| WitnessError::MissingWireValue(right.to_string()))?;
-                self.wire_values
-                    .insert(output.clone(), left_val + right_val);
-                Ok(())
+                let left_val = ctx.read(left)?;
+                let right_val = ctx.read(right)?;
+                ctx.assign(output, left_val + right_val);
             }
             Gate::Mul {
                 output,
                 left,
                 right,
             } => {
-                let left_val = self
-                    .get_wire_value(left)
-                    .ok_or_else(|| WitnessError::MissingWireValue(left.to_string()))?;
-                let right_val = self
-                    .get_wire_value(right)
-                    .ok_or_else(|| WitnessError::MissingWireValue(right.to_string()))?;
-                self.wire_values
-                    .insert(output.clone(), left_val * right_val);
-                Ok(())
+                let left_val = ctx.read(left)?;
+                let right_val = ctx.read(right)?;
+                ctx.assign(output, left_val * right_val);
             }
-            Gate::Assert {
+            Gate::Square { output, input } => {
+                let input_val = ctx.read(input)?;
+                ctx.assign(output, input_val * input_val);
+            }
+            Gate::Sub {
                 output,
                 left,
                 right,
             } => {
-                let left_val = self
-                    .get_wire_value(left)
-                    .ok_or_else(|| WitnessError::MissingWireValue(left.to_string()))?;
-                let right_val = self
-                    .get_wire_value(right)
-                    .ok_or_else(|| WitnessError::MissingWireValue(right.to_string()))?;
-
-                let diff = left_val - right_val;
-                if diff != 0 {
-                    return Err(WitnessError::AssertionFailed(left_val, right_val));
+                let left_val = ctx.read(left)?;
+                let right_val = ctx.read(right)?;
+                ctx.assign(output, left_val - right_val);
+            }
+            Gate::Assert { left, right } => {
+                let left_val = ctx.read(left)?;
+                let right_val = ctx.read(right)?;
+                if left_val != right_val {
+                    let trace = ctx.operand_trace(&[left, right]);
+                    return Err(WitnessError::AssertionFailed(left_val, right_val, trace));
+                }
+            }
+            Gate::Lc { output, terms } => {
+                let mut sum = FieldElement::zero();
+                for (wire, coeff) in terms {
+                    sum = sum + *coeff * ctx.read(wire)?;
+                }
+                ctx.assign(output, sum);
+            }
+            Gate::IsZero {
+                output,
+                input,
+                inverse,
+            } => {
+                let input_val = ctx.read(input)?;
+                let inverse_val = HintOp::InverseOrZero
+                    .evaluate(input_val)
+                    .expect("inverse_or_zero has a value for every input");
+                // the output follows from the hinted inverse: 1 - input * inverse
+                ctx.assign(inverse, inverse_val);
+                ctx.assign(output, FieldElement::one() - input_val * inverse_val);
+            }
+            Gate::RangeCheck { input, bits } => {
+                let input_val = ctx.read(input)?.as_u64();
+                if bits.len() < 64 && input_val >> bits.len() != 0 {
+                    return Err(WitnessError::OutOfRange(
+                        input.to_string(),
+                        FieldElement::new(input_val),
+                        bits.len(),
+                    ));
+                }
+                for (i, bit) in bits.iter().enumerate() {
+                    ctx.assign(bit, FieldElement::new((input_val >> i) & 1));
+                }
+            }
+            Gate::Inv { output, input } => {
+                let input_val = ctx.read(input)?;
+                let inverse = HintOp::Inverse
+                    .evaluate(input_val)
+                    .ok_or_else(|| WitnessError::InverseOfZero(input.to_string()))?;
+                ctx.assign(output, inverse);
+            }
+        }
+        Ok(())
+    }
+}
+
+// What an evaluator sees of the witness: the gate being evaluated, the values
+// of the wires assigned before it, and the ones it assigns itself
+pub struct WireEnv<'a> {
+    calculator: &'a WitnessCalculator,
+    circuit: &'a Circuit,
+    gate: &'a Gate,
+    assignments: Vec<(Wire, FieldElement)>,
+}
+
+impl WireEnv<'_> {
+    pub fn gate(&self) -> &Gate {
+        self.gate
+    }
+
+    // fails with the dependency chain of the wire if it has no value yet
+    pub fn read(&self, wire: &Wire) -> Result<FieldElement, WitnessError> {
+        self.calculator.read_wire(self.circuit, wire)
+    }
+
+    pub fn assign(&mut self, wire: &Wire, value: FieldElement) {
+        self.assignments.push((wire.clone(), value));
+    }
+
+    // see WitnessCalculator::operand_trace
+    pub fn operand_trace(&self, wires: &[&Wire]) -> Vec<String> {
+        self.calculator.operand_trace(self.circuit, wires)
+    }
+}
+
+// the values of a WitnessCalculator::to_bytes buffer, by wire id; None if its
+// length is not a multiple of FieldElement::BYTES or a value is not below p
+pub fn witness_from_bytes(bytes: &[u8]) -> Option<Vec<FieldElement>> {
+    if !bytes.len().is_multiple_of(FieldElement::BYTES) {
+        return None;
+    }
+    bytes
+        .chunks_exact(FieldElement::BYTES)
+        .map(|chunk| FieldElement::from_le_bytes(chunk.try_into().ok()?))
+        .collect()
+}
+
+// prefixes a missing wire's dependency chain with the gate that read it
+fn gate_error(err: WitnessError, index: usize, gate: &Gate) -> WitnessError {
+    match err {
+        WitnessError::MissingWireValue(wire, mut chain) => {
+            chain.insert(0, format!("{} is read by gate {} ({})", wire, index, gate));
+            WitnessError::MissingWireValue(wire, chain)
+        }
+        other => other,
+    }
+}
+
+impl WitnessCalculator {
+    fn read_wire(&self, circuit: &Circuit, wire: &Wire) -> Result<FieldElement, WitnessError> {
+        self.get_wire_value(wire).ok_or_else(|| {
+            WitnessError::MissingWireValue(wire.to_string(), self.dependency_chain(circuit, wire))
+        })
+    }
+
+    // How each of `wires` got its value: a line per wire with the value and
+    // the input or gate it comes from, followed by the wires that gate read,
+    // indented, down to inputs, constants or OPERAND_TRACE_DEPTH gates deep
+    fn operand_trace(&self, circuit: &Circuit, wires: &[&Wire]) -> Vec<String> {
+        let producers: HashMap<&Wire, &Gate> = circuit
+            .gates
+            .iter()
+            .flat_map(|gate| gate.output_refs().into_iter().map(move |wire| (wire, gate)))
+            .collect();
+        let inputs: HashMap<&Wire, &String> = circuit
+            .public_inputs
+            .iter()
+            .chain(&circuit.private_inputs)
+            .map(|(name, wire)| (wire, name))
+            .collect();
+
+        let mut lines = Vec::new();
+        // depth-first, so every wire is directly followed by its operands
+        let mut stack: Vec<(&Wire, usize)> = wires.iter().rev().map(|wire| (*wire, 0)).collect();
+        while let Some((wire, depth)) = stack.pop() {
+            let value = match self.get_wire_value(wire) {
+                Some(value) => value.to_string(),
+                None => "no value".to_string(),
+            };
+            let origin = match (inputs.get(wire), producers.get(wire)) {
+                _ if *wire == Wire::ONE => "the constant one".to_string(),
+                (Some(name), _) => format!("input {}", name),
+                (None, Some(gate)) => {
+                    if depth + 1 < OPERAND_TRACE_DEPTH {
+                        let operands = gate.input_refs();
+                        stack.extend(
+                            operands
+                                .into_iter()
+                                .rev()
+                                .map(|operand| (operand, depth + 1)),
+                        );
+                    }
+                    format!("from {}", gate)
                 }
+                (None, None) => "not produced by any gate".to_string(),
+            };
+            lines.push(format!(
+                "{}{} = {}, {}",
+                "  ".repeat(depth),
+                wire,
+                value,
+                origin
+            ));
+        }
+        lines
+    }
 
-                self.wire_values.insert(output.clone(), 0);
-                Ok(())
+    // walks back from a wire without a value to the first wire that nothing could have produced
+    fn dependency_chain(&self, circuit: &Circuit, wire: &Wire) -> Vec<String> {
+        let mut chain = Vec::new();
+        let mut visited = HashSet::new();
+        let mut current = Some(wire.clone());
+
+        while let Some(wire) = current.take() {
+            if !visited.insert(wire.clone()) {
+                chain.push(format!("{} depends on itself", wire));
+                break;
             }
+
+            let producer = circuit
+                .gates
+                .iter()
+                .enumerate()
+                .find(|(_, gate)| gate.output_refs().contains(&&wire));
+
+            match producer {
+                Some((index, gate)) => {
+                    let missing: Vec<Wire> = gate
+                        .input_wires()
+                        .into_iter()
+                        .filter(|input| self.get_wire_value(input).is_none())
+                        .collect();
+
+                    if missing.is_empty() {
+                        chain.push(format!(
+                            "{} is produced by gate {} ({}), which had not run yet",
+                            wire, index, gate
+                        ));
+                    } else {
+                        let names: Vec<String> = missing.iter().map(|w| w.to_string()).collect();
+                        chain.push(format!(
+                            "{} is produced by gate {} ({}), whose inputs {} have no value",
+                            wire,
+                            index,
+                            gate,
+                            names.join(", ")
+                        ));
+                        current = missing.into_iter().next();
+                    }
+                }
+                None => chain.push(format!("{} is not an input and no gate produces it", wire)),
+            }
+        }
+
+        chain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::optimizer::OptLevel;
+
+    // x * y_i for many private y_i, then their sum: the first layer is wide
+    // enough to be split across threads
+    fn wide_program(products: usize) -> String {
+        let mut source = String::from("public x\n");
+        for i in 0..products {
+            source += &format!("private y{}\n", i);
+        }
+        source += "let sum = 0\n";
+        for i in 0..products {
+            source += &format!("sum += x * y{}\n", i);
+        }
+        source + "return sum"
+    }
+
+    fn wide_inputs(products: usize) -> InputFile {
+        (0..products)
+            .fold(InputFile::builder().public("x", 3), |builder, i| {
+                builder.private(&format!("y{}", i), i as i32 - 7)
+            })
+            .build()
+    }
+
+    #[test]
+    fn parallel_witness_equals_serial_witness() {
+        let products = 3 * MIN_GATES_PER_THREAD;
+        let circuit = compile(&wide_program(products), OptLevel::O0).unwrap();
+        assert!(circuit.gate_layers()[0].len() >= products);
+
+        let mut serial = WitnessCalculator::new();
+        let expected = serial.calculate_witness(&circuit, wide_inputs(products));
+        for threads in [2, 3, 8] {
+            let mut parallel = WitnessCalculator::with_threads(threads);
+            let result = parallel.calculate_witness(&circuit, wide_inputs(products));
+            assert_eq!(result.as_ref().ok(), expected.as_ref().ok());
+            assert_eq!(parallel.wire_values, serial.wire_values);
         }
     }
+
+    #[test]
+    fn parallel_witness_reports_the_same_failure() {
+        let products = 2 * MIN_GATES_PER_THREAD;
+        let source = wide_program(products).replace("return sum", "assert x == 4\nreturn sum");
+        let circuit = compile(&source, OptLevel::O0).unwrap();
+        let serial = WitnessCalculator::new()
+            .calculate_witness(&circuit, wide_inputs(products))
+            .unwrap_err();
+        let parallel = WitnessCalculator::with_threads(4)
+            .calculate_witness(&circuit, wide_inputs(products))
+            .unwrap_err();
+        assert_eq!(serial.to_string(), parallel.to_string());
+    }
+
+    // doubles every product and records the operands it read
+    struct DoublingMul {
+        seen: std::sync::Arc<std::sync::Mutex<Vec<(FieldElement, FieldElement)>>>,
+    }
+
+    impl EvalGate for DoublingMul {
+        fn eval(&self, ctx: &mut WireEnv) -> Result<(), WitnessError> {
+            let Gate::Mul {
+                output,
+                left,
+                right,
+            } = ctx.gate().clone()
+            else {
+                unreachable!("registered for mul gates only");
+            };
+            let (left_val, right_val) = (ctx.read(&left)?, ctx.read(&right)?);
+            self.seen.lock().unwrap().push((left_val, right_val));
+            ctx.assign(&output, FieldElement::new(2) * left_val * right_val);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn registered_evaluator_sees_the_wire_values() {
+        let circuit = compile("public x\nprivate y\nreturn x * y + 1", OptLevel::O0).unwrap();
+        let seen = std::sync::Arc::default();
+        let mut calculator = WitnessCalculator::new();
+        calculator.register_evaluator(
+            "mul",
+            DoublingMul {
+                seen: std::sync::Arc::clone(&seen),
+            },
+        );
+        let inputs = InputFile::builder().public("x", 3).private("y", 5).build();
+        let result = calculator.calculate_witness(&circuit, inputs).unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [(FieldElement::new(3), FieldElement::new(5))]
+        );
+        // the add gate after it reads what the custom evaluator assigned
+        assert_eq!(result, FieldElement::new(31));
+    }
+
+    // every input combination through the witness and the R1CS constraints
+    fn truth_table(source: &str, inputs: &[&str], expected: &[i32]) {
+        for opt_level in [OptLevel::O0, OptLevel::O2] {
+            let circuit = compile(source, opt_level).unwrap();
+            let r1cs = circuit.to_r1cs();
+            for (row, expected) in expected.iter().enumerate() {
+                let values = (0..inputs.len()).map(|i| (row >> (inputs.len() - 1 - i)) as i32 & 1);
+                let row_inputs = inputs
+                    .iter()
+                    .zip(values)
+                    .fold(InputFile::builder(), |builder, (name, value)| {
+                        builder.public(name, value)
+                    })
+                    .build();
+                let mut calculator = WitnessCalculator::new();
+                let output = calculator.calculate_witness(&circuit, row_inputs).unwrap();
+                assert_eq!(
+                    output,
+                    FieldElement::from(*expected),
+                    "{} row {}",
+                    source,
+                    row
+                );
+                let witness = calculator.witness_vector();
+                assert!(r1cs.evaluate(&witness).iter().all(|eval| eval.satisfied));
+            }
+        }
+    }
+
+    #[test]
+    fn boolean_operators_follow_their_truth_tables() {
+        // rows are a, b = 00, 01, 10, 11
+        truth_table(
+            "public a\npublic b\nreturn a and b",
+            &["a", "b"],
+            &[0, 0, 0, 1],
+        );
+        truth_table(
+            "public a\npublic b\nreturn a or b",
+            &["a", "b"],
+            &[0, 1, 1, 1],
+        );
+        truth_table("public a\nreturn not a", &["a"], &[1, 0]);
+    }
+
+    #[test]
+    fn boolean_operators_reject_operands_other_than_0_and_1() {
+        for source in [
+            "public a\npublic b\nreturn a and b",
+            "public a\npublic b\nreturn a or b",
+            "public a\npublic b\nreturn (not a) + b",
+        ] {
+            for level in [OptLevel::O0, OptLevel::O2] {
+                let circuit = compile(source, level).unwrap();
+                // 2 and 1 would be 2, 2 or 1 would be 1, not 2 would be -1
+                let inputs = InputFile::builder().public("a", 2).public("b", 1).build();
+                let err = WitnessCalculator::new()
+                    .calculate_witness(&circuit, inputs)
+                    .unwrap_err();
+                // a * a == a, 4 against 2
+                assert!(
+                    matches!(err, WitnessError::AssertionFailed(left, right, _)
+                        if left == FieldElement::new(4) && right == FieldElement::new(2)),
+                    "{}: {}",
+                    source,
+                    err
+                );
+            }
+        }
+        // a constant operand fails at compile time
+        assert!(matches!(
+            compile("public a\nreturn a * (not 2)", OptLevel::O1),
+            Err(crate::error::CompilerError::ConstantAssert(_))
+        ));
+    }
+
+    #[test]
+    fn results_of_comparisons_need_no_boolean_check() {
+        let squares = |source: &str| {
+            compile(source, OptLevel::O0)
+                .unwrap()
+                .gates
+                .iter()
+                .filter(|gate| matches!(gate, Gate::Square { .. }))
+                .count()
+        };
+        assert_eq!(
+            squares("public a\npublic b\nreturn (a != 1) and not (b != 2)"),
+            0
+        );
+        // each input is checked once, however often it is an operand
+        assert_eq!(squares("public a\npublic b\nreturn (a and b) or not a"), 2);
+    }
+
+    // what an i32, or even i64, witness would overflow on is reduced modulo p
+    #[test]
+    fn products_past_machine_integers_reduce_modulo_p() {
+        let circuit = compile(
+            "public x\nprivate y\nlet p = x * y\nreturn p * p",
+            OptLevel::O0,
+        )
+        .unwrap();
+        let inputs = InputFile::builder()
+            .public("x", 100_000)
+            .private("y", 300_000)
+            .build();
+        let result = WitnessCalculator::new()
+            .calculate_witness(&circuit, inputs)
+            .unwrap();
+        let product = 100_000u128 * 300_000;
+        assert!(product > i32::MAX as u128 && product * product > u64::MAX as u128);
+        let expected = (product * product % crate::field::MODULUS as u128) as u64;
+        assert_eq!(result, FieldElement::new(expected));
+    }
+
+    #[test]
+    fn missing_wire_names_the_gate_that_should_have_produced_it() {
+        let mut circuit = compile(
+            "public x\nprivate y\nlet a = x * y\nreturn a + x",
+            OptLevel::O0,
+        )
+        .unwrap();
+        // move the add in front of the mul that produces its operand
+        let mul = circuit
+            .gates
+            .iter()
+            .position(|gate| matches!(gate, Gate::Mul { .. }))
+            .unwrap();
+        let add = circuit.gates.remove(mul + 1);
+        assert!(matches!(add, Gate::Add { .. }));
+        circuit.gates.insert(mul, add);
+
+        let inputs = InputFile::builder().public("x", 3).private("y", 5).build();
+        let err = WitnessCalculator::new()
+            .calculate_witness(&circuit, inputs)
+            .unwrap_err();
+        let WitnessError::MissingWireValue(_, chain) = &err else {
+            panic!("expected a missing wire, got {}", err);
+        };
+        assert_eq!(chain.len(), 2, "{}", err);
+        assert!(
+            chain[0].contains(&format!("is read by gate {}", mul)),
+            "{}",
+            err
+        );
+        assert!(
+            chain[1].contains(&format!("is produced by gate {}", mul + 1)),
+            "{}",
+            err
+        );
+        assert!(chain[1].contains("which had not run yet"), "{}", err);
+    }
+
+    #[test]
+    fn is_zero_of_zero_is_one_and_of_five_is_zero() {
+        let circuit = compile("public x\nreturn is_zero(x)", OptLevel::O0).unwrap();
+        let r1cs = circuit.to_r1cs();
+        for (x, expected) in [(0, 1), (5, 0)] {
+            let mut calculator = WitnessCalculator::new();
+            let inputs = InputFile::builder().public("x", x).build();
+            let output = calculator.calculate_witness(&circuit, inputs).unwrap();
+            assert_eq!(output, FieldElement::from(expected), "is_zero({})", x);
+            let evals = r1cs.evaluate(&calculator.witness_vector());
+            assert!(evals.iter().all(|eval| eval.satisfied), "is_zero({})", x);
+        }
+    }
+
+    #[test]
+    fn json_and_toml_inputs_load_the_same_values() {
+        let path = |ext: &str| {
+            std::env::temp_dir()
+                .join(format!(
+                    "circuit-compiler-{}-inputs.{}",
+                    std::process::id(),
+                    ext
+                ))
+                .display()
+                .to_string()
+        };
+        let (toml_file, json_file) = (path("toml"), path("json"));
+        std::fs::write(
+            &toml_file,
+            "[public]\nx = 3\n\n[private]\ny = \"18446744069414584320\"\n",
+        )
+        .unwrap();
+        std::fs::write(&json_file, r#"{"public": {"x": 3}, "private": {"y": -1}}"#).unwrap();
+        let from_toml = InputFile::load_from_file(&toml_file).unwrap();
+        let from_json = InputFile::load_from_file(&json_file).unwrap();
+        std::fs::remove_file(&toml_file).unwrap();
+        std::fs::remove_file(&json_file).unwrap();
+
+        assert_eq!(from_toml.public, from_json.public);
+        assert_eq!(from_toml.private, from_json.private);
+        assert_eq!(from_json.private.unwrap()["y"], -FieldElement::one());
+    }
+
+    #[test]
+    fn u8_input_of_300_is_out_of_range() {
+        let circuit = compile("public x: u8\nreturn x * x", OptLevel::O1).unwrap();
+        let inputs = InputFile::builder().public("x", 300).build();
+        let err = WitnessCalculator::new()
+            .calculate_witness(&circuit, inputs)
+            .unwrap_err();
+        assert!(
+            matches!(err, WitnessError::OutOfRange(_, value, 8) if value == FieldElement::new(300)),
+            "{}",
+            err
+        );
+
+        let inputs = InputFile::builder().public("x", 255).build();
+        assert!(WitnessCalculator::new()
+            .calculate_witness(&circuit, inputs)
+            .is_ok());
+    }
+
+    #[test]
+    fn batch_reports_the_row_that_fails_its_assert() {
+        let circuit = compile(
+            "public x\nprivate y\nassert x * y == 12\nreturn x + y",
+            OptLevel::O1,
+        )
+        .unwrap();
+        let rows = [(3, 4), (2, 5), (6, 2)]
+            .map(|(x, y)| InputFile::builder().public("x", x).private("y", y).build());
+        let results = WitnessCalculator::new().calculate_batch(&circuit, &rows);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &FieldElement::new(7));
+        assert!(matches!(results[1], Err(WitnessError::AssertionFailed(..))));
+        assert_eq!(results[2].as_ref().unwrap(), &FieldElement::new(8));
+    }
+
+    #[test]
+    fn assert_eq_of_three_is_two_asserts() {
+        let circuit = compile(
+            "public x\nprivate y\nassert_eq(x, y, 5)\nreturn x",
+            OptLevel::O0,
+        )
+        .unwrap();
+        let asserts = circuit
+            .gates
+            .iter()
+            .filter(|gate| matches!(gate, Gate::Assert { .. }))
+            .count();
+        assert_eq!(asserts, 2);
+
+        for (x, y, holds) in [(5, 5, true), (5, 4, false), (4, 4, false)] {
+            let inputs = InputFile::builder().public("x", x).private("y", y).build();
+            let result = WitnessCalculator::new().calculate_witness(&circuit, inputs);
+            assert_eq!(result.is_ok(), holds, "x = {}, y = {}", x, y);
+        }
+    }
+
+    #[test]
+    fn wire_dump_labels_the_output_with_its_value() {
+        let circuit = compile(
+            "public x\nprivate y\nlet t = x * y\nreturn t + 1",
+            OptLevel::O0,
+        )
+        .unwrap();
+        let mut calculator = WitnessCalculator::new();
+        let inputs = InputFile::builder().public("x", 3).private("y", 5).build();
+        calculator.calculate_witness(&circuit, inputs).unwrap();
+
+        let dump = calculator.dump_wire_values(&circuit);
+        assert!(dump.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        let labelled = |label: &str| {
+            dump.iter()
+                .find(|(_, name, _)| name.as_deref() == Some(label))
+                .map(|(id, _, value)| (*id, *value))
+        };
+        assert_eq!(
+            labelled("output"),
+            Some((circuit.output_wire.id, FieldElement::new(16)))
+        );
+        assert_eq!(labelled("one"), Some((0, FieldElement::one())));
+        assert_eq!(labelled("y"), Some((2, FieldElement::new(5))));
+    }
+
+    #[test]
+    fn float_input_is_not_an_integer() {
+        let filename = std::env::temp_dir()
+            .join(format!(
+                "circuit-compiler-{}-float.toml",
+                std::process::id()
+            ))
+            .display()
+            .to_string();
+        std::fs::write(&filename, "[public]\nx = 3.5\n").unwrap();
+        let err = InputFile::load_from_file(&filename).unwrap_err();
+        std::fs::remove_file(&filename).unwrap();
+
+        let err = err.downcast_ref::<WitnessError>().unwrap();
+        assert!(
+            matches!(err, WitnessError::InputNotInteger(name, value) if name == "x" && value == "3.5"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn not_equal_is_one_for_distinct_inputs_and_zero_for_equal_ones() {
+        let circuit = compile("public a\nprivate b\nreturn a != b", OptLevel::O1).unwrap();
+        let r1cs = circuit.to_r1cs();
+        for (a, b, expected) in [(3, 7, 1), (7, 3, 1), (4, 4, 0), (0, 0, 0)] {
+            let mut calculator = WitnessCalculator::new();
+            let inputs = InputFile::builder().public("a", a).private("b", b).build();
+            let output = calculator.calculate_witness(&circuit, inputs).unwrap();
+            assert_eq!(output, FieldElement::from(expected), "{} != {}", a, b);
+            assert!(r1cs
+                .evaluate(&calculator.witness_vector())
+                .iter()
+                .all(|eval| eval.satisfied));
+        }
+    }
+
+    #[test]
+    fn assert_not_equal_fails_on_equal_inputs() {
+        let circuit =
+            compile("public a\nprivate b\nassert a != b\nreturn a", OptLevel::O1).unwrap();
+        let inputs = InputFile::builder().public("a", 3).private("b", 7).build();
+        assert!(WitnessCalculator::new()
+            .calculate_witness(&circuit, inputs)
+            .is_ok());
+        let inputs = InputFile::builder().public("a", 4).private("b", 4).build();
+        assert!(WitnessCalculator::new()
+            .calculate_witness(&circuit, inputs)
+            .is_err());
+    }
+
+    #[test]
+    fn witness_dumps_are_byte_identical_and_in_declaration_order() {
+        let circuit = compile(
+            "private d\npublic c\nprivate b\npublic a\nreturn a * b + c * d",
+            OptLevel::O1,
+        )
+        .unwrap();
+        let dump = |run: usize| {
+            let filename = std::env::temp_dir()
+                .join(format!(
+                    "circuit-compiler-{}-witness-{}.json",
+                    std::process::id(),
+                    run
+                ))
+                .display()
+                .to_string();
+            let mut calculator = WitnessCalculator::new();
+            let inputs = InputFile::builder()
+                .public("a", 1)
+                .public("c", 3)
+                .private("b", 2)
+                .private("d", 4)
+                .build();
+            let result = calculator.calculate_witness(&circuit, inputs).unwrap();
+            calculator
+                .save_r1cs_witness(&circuit, &filename, result, None)
+                .unwrap();
+            let bytes = std::fs::read(&filename).unwrap();
+            std::fs::remove_file(&filename).unwrap();
+            bytes
+        };
+        let first = dump(0);
+        assert_eq!(first, dump(1));
+
+        let json: serde_json::Value = serde_json::from_slice(&first).unwrap();
+        assert_eq!(
+            json["public_inputs"],
+            serde_json::json!([["c", "3"], ["a", "1"]])
+        );
+        assert_eq!(
+            json["private_inputs"],
+            serde_json::json!([["d", "4"], ["b", "2"]])
+        );
+    }
+
+    #[test]
+    fn one_calculator_serves_two_circuits_independently() {
+        let product = compile("public x\nprivate y\nreturn x * y", OptLevel::O0).unwrap();
+        let sum = compile("public a\nreturn a + 10", OptLevel::O0).unwrap();
+        let mut calculator = WitnessCalculator::new();
+
+        let inputs = InputFile::builder().public("x", 6).private("y", 7).build();
+        assert_eq!(
+            calculator.calculate_witness(&product, inputs).unwrap(),
+            FieldElement::new(42)
+        );
+        let inputs = InputFile::builder().public("a", 5).build();
+        assert_eq!(
+            calculator.calculate_witness(&sum, inputs).unwrap(),
+            FieldElement::new(15)
+        );
+        // nothing of the product's wires is left over
+        assert_eq!(
+            calculator.witness_vector().len(),
+            sum.to_r1cs().num_variables
+        );
+
+        // wire 2 held y in the first run; a circuit reading it unassigned must fail
+        let mut gap = sum.clone();
+        gap.gates = vec![Gate::Add {
+            output: Wire { id: 3 },
+            left: Wire { id: 1 },
+            right: Wire { id: 2 },
+        }];
+        gap.output_wire = Wire { id: 3 };
+        let inputs = InputFile::builder().public("x", 6).private("y", 7).build();
+        calculator.calculate_witness(&product, inputs).unwrap();
+        let inputs = InputFile::builder().public("a", 5).build();
+        assert!(matches!(
+            calculator.calculate_witness(&gap, inputs),
+            Err(WitnessError::MissingWireValue(..))
+        ));
+    }
+
+    #[test]
+    fn witness_bytes_have_a_fixed_width_per_wire_and_round_trip() {
+        let circuit = compile("public x\nprivate y\nreturn x * y - 1", OptLevel::O0).unwrap();
+        let mut calculator = WitnessCalculator::new();
+        let inputs = InputFile::builder().public("x", 6).private("y", 0).build();
+        calculator.calculate_witness(&circuit, inputs).unwrap();
+
+        let bytes = calculator.to_bytes(&circuit);
+        assert_eq!(bytes.len(), circuit.num_wires() * FieldElement::BYTES);
+        let values = witness_from_bytes(&bytes).unwrap();
+        for (id, value) in values.iter().enumerate() {
+            assert_eq!(
+                Some(*value),
+                calculator.get_wire_value(&Wire { id }),
+                "wire {}",
+                id
+            );
+        }
+        // 6 * 0 - 1 is p - 1, the widest value there is
+        assert_eq!(values[circuit.output_wire.id], -FieldElement::one());
+
+        // a cut-off buffer, or a value of p or more, doesn't read back
+        assert_eq!(witness_from_bytes(&bytes[1..]), None);
+        assert_eq!(witness_from_bytes(&[0xff; FieldElement::BYTES]), None);
+    }
+
+    #[test]
+    fn builder_and_constructors_give_the_same_witness() {
+        let circuit = compile("public x\nprivate y\nreturn x * y + 1", OptLevel::O1).unwrap();
+        let built = InputFile::builder().public("x", 3).private("y", 5).build();
+        let constructed = InputFile::from_public_and_private(
+            HashMap::from([("x".to_string(), FieldElement::new(3))]),
+            HashMap::from([("y".to_string(), FieldElement::new(5))]),
+        );
+        for inputs in [built, constructed] {
+            let output = WitnessCalculator::new()
+                .calculate_witness(&circuit, inputs)
+                .unwrap();
+            assert_eq!(output, FieldElement::new(16));
+        }
+
+        // a section nothing was set in stays None
+        let public_only = InputFile::builder().public("x", 3).build();
+        assert!(public_only.private.is_none() && public_only.pin.is_none());
+        let from_public =
+            InputFile::from_public(HashMap::from([("x".to_string(), FieldElement::new(3))]));
+        let square = compile("public x\nreturn x * x", OptLevel::O1).unwrap();
+        for inputs in [public_only, from_public] {
+            let output = WitnessCalculator::new()
+                .calculate_witness(&square, inputs)
+                .unwrap();
+            assert_eq!(output, FieldElement::new(9));
+        }
+    }
+
+    #[test]
+    fn failing_assert_traces_the_values_of_its_operands() {
+        let circuit = compile(
+            "public x\nprivate y\nassert x + 1 == y\nreturn x",
+            OptLevel::O1,
+        )
+        .unwrap();
+        let inputs = InputFile::builder().public("x", 3).private("y", 5).build();
+        let err = WitnessCalculator::new()
+            .calculate_witness(&circuit, inputs)
+            .unwrap_err();
+        let WitnessError::AssertionFailed(left, right, trace) = err else {
+            panic!("expected an assertion failure, found {}", err);
+        };
+        assert_eq!((left, right), (FieldElement::new(4), FieldElement::new(5)));
+        // x + 1, then the x and the 1 it adds, then y
+        assert_eq!(
+            trace,
+            [
+                "w4 = 4, from w4 = w1 + w3",
+                "  w1 = 3, input x",
+                "  w3 = 1, from w3 = 1",
+                "w2 = 5, input y",
+            ]
+        );
+    }
 }
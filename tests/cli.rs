@@ -0,0 +1,226 @@
+// Runs the built binary the way the README does. Every run writes into
+// circuit/ under its working directory, so each test works in its own temp
+// copy of examples/ and inputs/ instead of the source tree.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+struct Workspace {
+    dir: PathBuf,
+}
+
+impl Workspace {
+    fn new(name: &str) -> Workspace {
+        let dir = std::env::temp_dir().join(format!(
+            "circuit-compiler-cli-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        for sub in ["examples", "inputs"] {
+            copy_dir(&root.join(sub), &dir.join(sub));
+        }
+        Workspace { dir }
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_circuit-compiler"))
+            .args(args)
+            .current_dir(&self.dir)
+            .output()
+            .expect("the binary runs")
+    }
+
+    fn read(&self, path: &str) -> String {
+        std::fs::read_to_string(self.dir.join(path)).unwrap()
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    std::fs::create_dir_all(to).unwrap();
+    for entry in std::fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            std::fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn witness_subcommand_runs_a_saved_circuit() {
+    let workspace = Workspace::new("witness_subcommand_runs_a_saved_circuit");
+    let compiled = workspace.run(&["examples/simple.zk"]);
+    assert!(compiled.status.success(), "{}", stderr(&compiled));
+
+    let output = workspace.run(&["witness", "circuit/simple.json", "inputs/inputs.toml"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("constraints satisfied"));
+}
+
+#[test]
+fn witness_subcommand_without_inputs_prints_usage() {
+    let workspace = Workspace::new("witness_subcommand_without_inputs_prints_usage");
+    let output = workspace.run(&["witness", "circuit/simple.json"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("Usage:"));
+}
+
+#[test]
+fn threads_option_calculates_the_same_witness() {
+    let workspace = Workspace::new("threads_option_calculates_the_same_witness");
+    let output = workspace.run(&[
+        "examples/very_complex.zk",
+        "inputs/very_complex_inputs.toml",
+        "--threads=4",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("result matches"));
+
+    let output = workspace.run(&["examples/simple.zk", "--threads=0"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("Invalid --threads"));
+}
+
+#[test]
+fn source_map_names_included_files() {
+    let workspace = Workspace::new("source_map_names_included_files");
+    let output = workspace.run(&["examples/include.zk", "inputs/include_inputs.toml"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let map: serde_json::Value =
+        serde_json::from_str(&workspace.read("circuit/include.r1cs.map")).unwrap();
+    assert_eq!(map["source"], "examples/include.zk");
+    let files: Vec<&serde_json::Value> = map["constraints"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| &entry["span"]["file"])
+        .collect();
+    assert!(files.contains(&&serde_json::json!("examples/lib/squares.zk")));
+    assert!(files.contains(&&serde_json::Value::Null));
+}
+
+#[test]
+fn check_subcommand_needs_exactly_one_file() {
+    let workspace = Workspace::new("check_subcommand_needs_exactly_one_file");
+    let output = workspace.run(&["check", "examples/simple.zk"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    for args in [
+        &["check"][..],
+        &["check", "examples/simple.zk", "examples/include.zk"],
+    ] {
+        let output = workspace.run(args);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains("Usage:"));
+    }
+}
+
+#[test]
+fn programs_without_a_return_get_a_hint() {
+    let workspace = Workspace::new("programs_without_a_return_get_a_hint");
+    for (name, source, hint) in [
+        ("empty.zk", "", "The file is empty"),
+        (
+            "inputs_only.zk",
+            "public x\nprivate y\n",
+            "End the program with `return <expr>`",
+        ),
+    ] {
+        std::fs::write(workspace.dir.join(name), source).unwrap();
+        let output = workspace.run(&[name]);
+        assert_eq!(output.status.code(), Some(1), "{}", name);
+        let printed = stdout(&output) + &stderr(&output);
+        assert!(!printed.contains("panicked"), "{}", printed);
+        assert!(printed.contains(hint), "{}", printed);
+    }
+}
+
+#[test]
+fn compiling_twice_writes_the_same_files() {
+    let workspace = Workspace::new("compiling_twice_writes_the_same_files");
+    let saved = || {
+        [
+            "circuit/gadget.json",
+            "circuit/gadget.r1cs",
+            "circuit/gadget.sym",
+        ]
+        .map(|path| workspace.read(path))
+    };
+    let output = workspace.run(&["examples/gadget.zk"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let first = saved();
+
+    let output = workspace.run(&["examples/gadget.zk"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(saved(), first);
+
+    let output = workspace.run(&["examples/gadget.zk", "--seed=7"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn max_gates_applies_to_the_optimized_circuit() {
+    let workspace = Workspace::new("max_gates_applies_to_the_optimized_circuit");
+    // 4 gates before optimization, 2 once 5 + 10 is folded
+    let output = workspace.run(&["examples/optimize_test.zk", "--max-gates=2"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let output = workspace.run(&["examples/optimize_test.zk", "--max-gates=1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("Circuit has more than 1 gates"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn stats_json_reports_sizes_before_and_after_optimization() {
+    let workspace = Workspace::new("stats_json");
+    let output = workspace.run(&["examples/optimize_test.zk", "--stats-json"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let stats: serde_json::Value =
+        serde_json::from_str(&workspace.read("circuit/optimize_test.stats.json")).unwrap();
+    assert_eq!(
+        stats["before"],
+        serde_json::json!({
+            "gates": { "add": 1, "const": 2, "mul": 1 },
+            "total_gates": 4,
+            "constraints": 2,
+            "wires": 6,
+            "public_inputs": 1,
+            "private_inputs": 0,
+        })
+    );
+    // 5 + 10 folded into one constant
+    assert_eq!(
+        stats["after"],
+        serde_json::json!({
+            "gates": { "const": 1, "mul": 1 },
+            "total_gates": 2,
+            "constraints": 1,
+            "wires": 4,
+            "public_inputs": 1,
+            "private_inputs": 0,
+        })
+    );
+}
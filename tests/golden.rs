@@ -0,0 +1,43 @@
+// Pins the wire numbering of a sample program: the input wires and gate
+// listing of examples/very_complex.zk must match the fixture on every
+// compilation, whatever keys the compiler's hash maps pick. Update
+// tests/golden/very_complex.gates only when a change of numbering is intended.
+
+use circuit_compiler::compiler::compile;
+use circuit_compiler::optimizer::OptLevel;
+use std::path::Path;
+
+fn read(path: &str) -> String {
+    std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join(path)).unwrap()
+}
+
+// input wires, then the gates and the output
+fn listing(source: &str) -> String {
+    let circuit = compile(source, OptLevel::O1).unwrap();
+    let inputs = circuit
+        .public_inputs
+        .iter()
+        .map(|input| ("public", input))
+        .chain(
+            circuit
+                .private_inputs
+                .iter()
+                .map(|input| ("private", input)),
+        );
+    let mut listing: String = inputs
+        .map(|(kind, (name, wire))| format!("{} {}: w{}\n", kind, name, wire.id))
+        .collect();
+    listing.push_str(&circuit.to_aligned_string());
+    listing.push('\n');
+    listing
+}
+
+#[test]
+fn very_complex_gates_match_the_golden_listing_on_every_compilation() {
+    let source = read("examples/very_complex.zk");
+    let expected = read("tests/golden/very_complex.gates");
+    // every compilation's hash maps get new random keys
+    for run in 0..4 {
+        assert_eq!(listing(&source), expected, "run {}", run);
+    }
+}
@@ -0,0 +1,53 @@
+// Compiling through the library logs each stage instead of printing it. The
+// logger is process-wide, so this lives in its own test binary.
+
+use circuit_compiler::compiler::compile;
+use circuit_compiler::optimizer::OptLevel;
+use std::sync::Mutex;
+
+struct CapturingLogger {
+    records: Mutex<Vec<(log::Level, String)>>,
+}
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.records
+            .lock()
+            .unwrap()
+            .push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger {
+    records: Mutex::new(Vec::new()),
+};
+
+#[test]
+fn compilation_logs_every_stage() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Debug);
+
+    compile("public x\nprivate y\nreturn x * y", OptLevel::O1).unwrap();
+
+    let records = LOGGER.records.lock().unwrap();
+    let first_lines: Vec<(log::Level, &str)> = records
+        .iter()
+        .map(|(level, message)| (*level, message.lines().next().unwrap_or("")))
+        .collect();
+    assert_eq!(
+        first_lines,
+        [
+            (log::Level::Debug, "AST:"),
+            (log::Level::Debug, "SSA IR:"),
+            (log::Level::Debug, "Optimized SSA (O1):"),
+            (log::Level::Debug, "Circuit:"),
+            (log::Level::Info, "Compiled to 1 gates, 1 constraints"),
+        ]
+    );
+}
@@ -0,0 +1,54 @@
+// Counts the heap allocations of parsing a large token stream. The parser
+// borrows tokens and only clones an identifier's name into the AST node that
+// keeps it, so parsing allocates what the AST holds and no more; a token
+// clone per consumed token would add one allocation per identifier.
+
+use circuit_compiler::lexer::Lexer;
+use circuit_compiler::parser::Parser;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn parsing_allocates_only_what_the_ast_keeps() {
+    let statements = 10_000;
+    let mut source = String::from("public x0\n");
+    for i in 1..=statements {
+        source += &format!("let x{} = x{} + x0\n", i, i - 1);
+    }
+    source += &format!("return x{}", statements);
+    let tokens = Lexer::new(&source).tokenize().unwrap();
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let program = Parser::new(tokens).parse().unwrap();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    drop(program);
+
+    // per statement: the let's name and its entry in the parser's constant
+    // table, the two Var names and the two boxed operands of the +; the rest
+    // is the statement list growing. Cloning each consumed token would add
+    // three, one per identifier.
+    assert!(
+        allocations <= 6 * statements + 100,
+        "{} allocations for {} statements",
+        allocations,
+        statements
+    );
+}